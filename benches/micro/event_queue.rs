@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Compares the event queue of the scheduler, a binary heap
+//! of tags with an index of the events by tag, to the sorted
+//! VecDeque it replaced. Inserting into the VecDeque is O(n),
+//! which hurts as soon as events are not pushed in order.
+
+#![allow(unused, non_snake_case, non_camel_case_types)]
+#[macro_use]
+extern crate reactor_rt;
+
+use std::collections::VecDeque;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use reactor_rt::internals::{Event, EventQueue};
+use reactor_rt::{Duration, EventTag};
+
+/// The previous implementation of [EventQueue].
+#[derive(Default)]
+struct SortedVecQueue<'x> {
+    value_list: VecDeque<(EventTag, Event<'x>)>,
+}
+
+impl<'x> SortedVecQueue<'x> {
+    fn push(&mut self, tag: EventTag, evt: Event<'x>) {
+        match self.value_list.binary_search_by_key(&tag, |e| e.0) {
+            Ok(idx) => self.value_list[idx].1.absorb(evt),
+            Err(idx) => self.value_list.insert(idx, (tag, evt)),
+        }
+    }
+
+    fn remove(&mut self, tag: EventTag) -> Option<Event<'x>> {
+        let idx = self.value_list.binary_search_by_key(&tag, |e| e.0).ok()?;
+        self.value_list.remove(idx).map(|e| e.1)
+    }
+
+    fn take_earliest(&mut self) -> Option<Event<'x>> {
+        self.value_list.pop_front().map(|e| e.1)
+    }
+}
+
+fn tag(micros: u64) -> EventTag {
+    EventTag::offset(Duration::from_micros(micros), 0)
+}
+
+/// Tags in pseudo-random order, as when many timers with
+/// different periods and physical actions are pending.
+fn shuffled_tags(n: u64) -> Vec<EventTag> {
+    let mut x = 0x2545_f491_u64;
+    (0..n)
+        .map(|_| {
+            // xorshift
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            tag(x % (n * 10))
+        })
+        .collect()
+}
+
+fn ordered_tags(n: u64) -> Vec<EventTag> {
+    (0..n).map(tag).collect()
+}
+
+fn push_pop_heap(tags: &[EventTag]) {
+    let mut queue = EventQueue::default();
+    for &t in tags {
+        queue.push(Event::terminate_at(t));
+    }
+    while let Some(evt) = queue.take_earliest() {
+        black_box(evt);
+    }
+}
+
+fn push_pop_vec(tags: &[EventTag]) {
+    let mut queue = SortedVecQueue::default();
+    for &t in tags {
+        queue.push(t, Event::terminate_at(t));
+    }
+    while let Some(evt) = queue.take_earliest() {
+        black_box(evt);
+    }
+}
+
+fn extend_pop_heap(tags: &[EventTag]) {
+    let mut queue = EventQueue::default();
+    queue.extend(tags.iter().map(|&t| Event::terminate_at(t)));
+    while let Some(evt) = queue.take_earliest() {
+        black_box(evt);
+    }
+}
+
+fn remove_heap(tags: &[EventTag]) {
+    let mut queue = EventQueue::default();
+    queue.extend(tags.iter().map(|&t| Event::terminate_at(t)));
+    for &t in tags.iter().step_by(2) {
+        black_box(queue.remove(t));
+    }
+    while let Some(evt) = queue.take_earliest() {
+        black_box(evt);
+    }
+}
+
+fn remove_vec(tags: &[EventTag]) {
+    let mut queue = SortedVecQueue::default();
+    for &t in tags {
+        queue.push(t, Event::terminate_at(t));
+    }
+    for &t in tags.iter().step_by(2) {
+        black_box(queue.remove(t));
+    }
+    while let Some(evt) = queue.take_earliest() {
+        black_box(evt);
+    }
+}
+
+fn bench_event_queue(c: &mut Criterion) {
+    let mut group = c.benchmark_group("EventQueue");
+    for n in [100, 1_000, 10_000] {
+        let shuffled = shuffled_tags(n);
+        let ordered = ordered_tags(n);
+        group.bench_with_input(BenchmarkId::new("push-pop/shuffled/VecDeque", n), &shuffled, |b, i| {
+            b.iter(|| push_pop_vec(i))
+        });
+        group.bench_with_input(BenchmarkId::new("push-pop/shuffled/Heap", n), &shuffled, |b, i| {
+            b.iter(|| push_pop_heap(i))
+        });
+        group.bench_with_input(BenchmarkId::new("push-pop/ordered/VecDeque", n), &ordered, |b, i| {
+            b.iter(|| push_pop_vec(i))
+        });
+        group.bench_with_input(BenchmarkId::new("push-pop/ordered/Heap", n), &ordered, |b, i| {
+            b.iter(|| push_pop_heap(i))
+        });
+        group.bench_with_input(BenchmarkId::new("extend-pop/shuffled/Heap", n), &shuffled, |b, i| {
+            b.iter(|| extend_pop_heap(i))
+        });
+        group.bench_with_input(BenchmarkId::new("remove/shuffled/VecDeque", n), &shuffled, |b, i| {
+            b.iter(|| remove_vec(i))
+        });
+        group.bench_with_input(BenchmarkId::new("remove/shuffled/Heap", n), &shuffled, |b, i| {
+            b.iter(|| remove_heap(i))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_event_queue);
+criterion_main!(benches);
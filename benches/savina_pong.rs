@@ -225,11 +225,6 @@ mod reactors {
                     ),
                 }
             }
-
-            fn cleanup_tag(&mut self, ctx: &::reactor_rt::CleanupCtx) {
-                ctx.cleanup_port(&mut self.__send);
-                ctx.cleanup_port(&mut self.__receive);
-            }
         }
     }
 
@@ -321,7 +316,7 @@ mod reactors {
                     __impl,
                     __send: __assembler.new_port::<u32>("send", ::reactor_rt::assembly::PortKind::Output),
                     __receive: __assembler.new_port::<u32>("receive", ::reactor_rt::assembly::PortKind::Input),
-                    __serve: __assembler.new_logical_action::<()>("serve", None),
+                    __serve: __assembler.new_logical_action::<()>("serve", None, None, ::reactor_rt::SpacingPolicy::Defer),
                 })
             }
         }
@@ -384,12 +379,6 @@ mod reactors {
                     ),
                 }
             }
-
-            fn cleanup_tag(&mut self, ctx: &::reactor_rt::CleanupCtx) {
-                ctx.cleanup_port(&mut self.__send);
-                ctx.cleanup_port(&mut self.__receive);
-                ctx.cleanup_logical_action(&mut self.__serve);
-            }
         }
     }
 
@@ -515,8 +504,6 @@ mod reactors {
                     ),
                 }
             }
-
-            fn cleanup_tag(&mut self, ctx: &::reactor_rt::CleanupCtx) {}
         }
     }
 }
@@ -0,0 +1,442 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Procedural macros to declare reactors in Rust, without
+//! the LFC code generator. See [macro@reactor].
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    bracketed, parse_macro_input, Attribute, Error, Expr, Field, Fields, Ident, ImplItem, Item, ItemImpl, ItemStruct, Token,
+};
+
+/// Declares a reactor. The attribute is put both on the
+/// struct of the reactor and on the impl block that contains
+/// its reactions. Together, they implement `ReactorInitializer`
+/// and `ReactorBehavior` with the same calls into the assembly
+/// API as the code generated by LFC.
+///
+/// Fields of the struct are components if annotated with
+/// - `#[input]` or `#[output]`, for ports,
+/// - `#[logical_action]` or `#[logical_action(min_delay)]`, for logical actions,
+/// - `#[timer]`, `#[timer(offset)]` or `#[timer(offset, period)]`, for timers.
+///
+/// Other fields are state variables, which are initialized
+/// with [Default]. The reactor has no parameters.
+///
+/// Methods of the impl block annotated with `#[reaction(...)]`
+/// are reactions. They take a `&mut ReactionCtx` parameter.
+/// The attribute lists the components that trigger the reaction,
+/// that it sets or schedules, and that it only reads, as in
+/// `#[reaction(triggers = [startup, input], effects = [output], uses = [other])]`.
+/// `startup` and `shutdown` are the special triggers of LF.
+/// Reactions execute in the order they are declared in.
+///
+/// ```ignore
+/// use reactor_rt::prelude::*;
+/// use reactor_rt::reactor;
+///
+/// #[reactor]
+/// struct Doubler {
+///     #[input]
+///     input: Port<u32>,
+///     #[output]
+///     output: Port<u32>,
+/// }
+///
+/// #[reactor]
+/// impl Doubler {
+///     #[reaction(triggers = [input], effects = [output])]
+///     fn double(&mut self, ctx: &mut ReactionCtx) {
+///         let value = ctx.get(&self.input).unwrap();
+///         ctx.set(&mut self.output, value * 2);
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn reactor(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = TokenStream2::from(args);
+        return Error::new(args.span(), "#[reactor] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let result = match parse_macro_input!(item as Item) {
+        Item::Struct(item) => expand_struct(item),
+        Item::Impl(item) => expand_impl(item),
+        item => Err(Error::new(
+            item.span(),
+            "#[reactor] applies to the struct of a reactor, and to the impl block of its reactions",
+        )),
+    };
+    result.unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Kind of a component field, with the arguments of its attribute.
+enum ComponentKind {
+    Input,
+    Output,
+    LogicalAction {
+        min_delay: Option<TokenStream2>,
+    },
+    Timer {
+        offset: Option<TokenStream2>,
+        period: Option<TokenStream2>,
+    },
+}
+
+/// Remove the component attribute of a field, if any.
+fn take_component_kind(attrs: &mut Vec<Attribute>) -> syn::Result<Option<ComponentKind>> {
+    let mut kind = None;
+    let mut remaining = Vec::with_capacity(attrs.len());
+    for attr in attrs.drain(..) {
+        let parsed = if attr.path.is_ident("input") {
+            no_args(&attr)?;
+            ComponentKind::Input
+        } else if attr.path.is_ident("output") {
+            no_args(&attr)?;
+            ComponentKind::Output
+        } else if attr.path.is_ident("logical_action") {
+            let mut args = args(&attr, 1)?.into_iter();
+            ComponentKind::LogicalAction { min_delay: args.next() }
+        } else if attr.path.is_ident("timer") {
+            let mut args = args(&attr, 2)?.into_iter();
+            ComponentKind::Timer { offset: args.next(), period: args.next() }
+        } else {
+            remaining.push(attr);
+            continue;
+        };
+        if kind.replace(parsed).is_some() {
+            return Err(Error::new(attr.span(), "A field can only be a single component"));
+        }
+    }
+    *attrs = remaining;
+    Ok(kind)
+}
+
+fn no_args(attr: &Attribute) -> syn::Result<()> {
+    if attr.tokens.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(attr.tokens.span(), "Expected no arguments"))
+    }
+}
+
+fn args(attr: &Attribute, max: usize) -> syn::Result<Vec<TokenStream2>> {
+    if attr.tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+    let args = attr.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+    if args.len() > max {
+        return Err(Error::new(attr.tokens.span(), format!("Expected at most {} arguments", max)));
+    }
+    Ok(args.into_iter().map(|expr| quote! { #expr }).collect())
+}
+
+fn opt_duration(expr: Option<TokenStream2>) -> TokenStream2 {
+    expr.unwrap_or_else(|| quote! { ::reactor_rt::Duration::ZERO })
+}
+
+/// Name of the function that declares a field as the effect
+/// of a reaction. It only exists for ports and actions, so
+/// that other effects are rejected by the compiler.
+fn effects_fn(field: &Ident) -> Ident {
+    format_ident!("__reactor_effects_{}", field, span = field.span())
+}
+
+/// Expands the struct of a reactor: adds a field for its ID,
+/// and functions to create, clean up and declare its components.
+fn expand_struct(mut item: ItemStruct) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() {
+        return Err(Error::new(item.generics.span(), "Generic reactors are not supported"));
+    }
+    let fields = match &mut item.fields {
+        Fields::Named(fields) => &mut fields.named,
+        _ => return Err(Error::new(item.span(), "Expected a struct with named fields")),
+    };
+
+    let mut inits = Vec::new();
+    let mut effects = Vec::new();
+    let mut timers = Vec::new();
+    for field in fields.iter_mut() {
+        let ident = field.ident.clone().unwrap();
+        let name = ident.to_string();
+        let effects_ident = effects_fn(&ident);
+        match take_component_kind(&mut field.attrs)? {
+            Some(kind @ ComponentKind::Input) | Some(kind @ ComponentKind::Output) => {
+                let port_kind = match kind {
+                    ComponentKind::Input => quote! { Input },
+                    _ => quote! { Output },
+                };
+                inits.push(quote! { #ident: __cc.new_port(#name, ::reactor_rt::assembly::PortKind::#port_kind) });
+                effects.push(quote! {
+                    fn #effects_ident(
+                        __dd: &mut ::reactor_rt::assembly::DependencyDeclarator<'_, '_, Self>,
+                        __self: &Self,
+                        __reaction: ::reactor_rt::GlobalReactionId,
+                    ) -> ::reactor_rt::assembly::AssemblyResult<()> {
+                        __dd.effects_port(__reaction, &__self.#ident)
+                    }
+                });
+            }
+            Some(ComponentKind::LogicalAction { min_delay }) => {
+                let min_delay = match min_delay {
+                    Some(expr) => quote! { Some(#expr) },
+                    None => quote! { None },
+                };
+                inits.push(quote! {
+                    #ident: __cc.new_logical_action(#name, #min_delay, None, ::reactor_rt::SpacingPolicy::Defer)
+                });
+                // actions are scheduled dynamically, there is nothing to declare
+                effects.push(quote! {
+                    fn #effects_ident(
+                        _: &mut ::reactor_rt::assembly::DependencyDeclarator<'_, '_, Self>,
+                        _: &Self,
+                        _: ::reactor_rt::GlobalReactionId,
+                    ) -> ::reactor_rt::assembly::AssemblyResult<()> {
+                        Ok(())
+                    }
+                });
+            }
+            Some(ComponentKind::Timer { offset, period }) => {
+                let offset = opt_duration(offset);
+                let period = opt_duration(period);
+                inits.push(quote! { #ident: __cc.new_timer(#name, #offset, #period) });
+                timers.push(ident);
+            }
+            None => inits.push(quote! { #ident: ::core::default::Default::default() }),
+        }
+    }
+    fields.push(Field::parse_named.parse2(quote! {
+        #[doc(hidden)]
+        __reactor_id: ::reactor_rt::ReactorId
+    })?);
+
+    // each timer has two synthetic reactions, one to start
+    // it and one to reschedule it, which come after those
+    // declared by the user
+    let num_timers = timers.len();
+    let mut declare_timers = Vec::new();
+    let mut react_timers = Vec::new();
+    for (i, timer) in timers.iter().enumerate() {
+        let (start, reschedule) = (Literal::usize_unsuffixed(2 * i), Literal::usize_unsuffixed(2 * i + 1));
+        declare_timers.push(quote! {
+            __dd.declare_triggers(::reactor_rt::assembly::TriggerId::STARTUP, __reactions[#start])?;
+            __dd.effects_timer(__reactions[#start], &__self.#timer)?;
+            __dd.declare_triggers(::reactor_rt::assembly::TriggerLike::get_id(&__self.#timer), __reactions[#reschedule])?;
+        });
+        react_timers.push(quote! {
+            #start => __ctx.bootstrap_timer(&mut self.#timer),
+            #reschedule => __ctx.reschedule_timer(&mut self.#timer),
+        });
+    }
+
+    let name = &item.ident;
+    Ok(quote! {
+        #item
+
+        #[allow(dead_code, unused_variables)]
+        impl #name {
+            const __REACTOR_NUM_TIMERS: usize = #num_timers;
+
+            fn __reactor_create(
+                __cc: &mut ::reactor_rt::assembly::ComponentCreator<'_, '_, Self>,
+                __id: ::reactor_rt::ReactorId,
+            ) -> ::core::result::Result<Self, ::reactor_rt::assembly::AssemblyError> {
+                Ok(Self {
+                    __reactor_id: __id,
+                    #(#inits,)*
+                })
+            }
+
+            fn __reactor_declare_timers(
+                __dd: &mut ::reactor_rt::assembly::DependencyDeclarator<'_, '_, Self>,
+                __self: &Self,
+                __reactions: &[::reactor_rt::GlobalReactionId],
+            ) -> ::reactor_rt::assembly::AssemblyResult<()> {
+                #(#declare_timers)*
+                Ok(())
+            }
+
+            fn __reactor_react_timer(&mut self, __ctx: &mut ::reactor_rt::ReactionCtx, __index: usize) {
+                match __index {
+                    #(#react_timers)*
+                    _ => unreachable!("Invalid reaction id"),
+                }
+            }
+
+            #(#effects)*
+        }
+    })
+}
+
+/// Arguments of a `#[reaction]` attribute.
+#[derive(Default)]
+struct ReactionArgs {
+    triggers: Vec<Ident>,
+    effects: Vec<Ident>,
+    uses: Vec<Ident>,
+}
+
+impl Parse for ReactionArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = ReactionArgs::default();
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            let content;
+            bracketed!(content in input);
+            let idents = content.parse_terminated::<Ident, Token![,]>(Ident::parse)?;
+            let target = match key.to_string().as_str() {
+                "triggers" => &mut args.triggers,
+                "effects" => &mut args.effects,
+                "uses" => &mut args.uses,
+                _ => return Err(Error::new(key.span(), "Expected one of triggers, effects or uses")),
+            };
+            target.extend(idents);
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Remove the `#[reaction]` attribute of a method, if any.
+fn take_reaction_args(attrs: &mut Vec<Attribute>) -> syn::Result<Option<ReactionArgs>> {
+    let pos = match attrs.iter().position(|attr| attr.path.is_ident("reaction")) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    let attr = attrs.remove(pos);
+    if attr.tokens.is_empty() {
+        Ok(Some(ReactionArgs::default()))
+    } else {
+        attr.parse_args().map(Some)
+    }
+}
+
+/// Expands the impl block of the reactions of a reactor:
+/// implements the assembly of the reactor and the dispatch
+/// of its reactions.
+fn expand_impl(mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(Error::new(path.span(), "Expected the inherent impl block of a reactor"));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(Error::new(item.generics.span(), "Generic reactors are not supported"));
+    }
+
+    let mut reactions = Vec::new();
+    for impl_item in item.items.iter_mut() {
+        if let ImplItem::Method(method) = impl_item {
+            if let Some(args) = take_reaction_args(&mut method.attrs)? {
+                reactions.push((method.sig.ident.clone(), args));
+            }
+        }
+    }
+
+    let num_reactions = reactions.len();
+    let mut names = Vec::new();
+    let mut declarations = Vec::new();
+    let mut dispatch = Vec::new();
+    for (i, (method, args)) in reactions.iter().enumerate() {
+        let i = Literal::usize_unsuffixed(i);
+        let name = method.to_string();
+        names.push(quote! { __names[#i] = Some(#name); });
+
+        let triggers = args.triggers.iter().map(|trigger| match trigger.to_string().as_str() {
+            "startup" => quote! { ::reactor_rt::assembly::TriggerId::STARTUP },
+            "shutdown" => quote! { ::reactor_rt::assembly::TriggerId::SHUTDOWN },
+            _ => quote! { ::reactor_rt::assembly::TriggerLike::get_id(&__self.#trigger) },
+        });
+        let effects = args.effects.iter().map(effects_fn);
+        let uses = &args.uses;
+        declarations.push(quote! {
+            #(__dd.declare_triggers(#triggers, __reactions[#i])?;)*
+            #(Self::#effects(__dd, __self, __reactions[#i])?;)*
+            #(__dd.declare_uses(__reactions[#i], ::reactor_rt::assembly::TriggerLike::get_id(&__self.#uses))?;)*
+        });
+        dispatch.push(quote! { #i => self.#method(__ctx), });
+    }
+
+    let self_ty = &item.self_ty;
+    let num_local_reactions = quote! { #num_reactions + 2 * <#self_ty>::__REACTOR_NUM_TIMERS };
+    let timer_index = if num_reactions == 0 {
+        quote! { __rid }
+    } else {
+        quote! { __rid - #num_reactions }
+    };
+    Ok(quote! {
+        #item
+
+        impl ::reactor_rt::assembly::ReactorInitializer for #self_ty {
+            type Wrapped = Self;
+            type Params = ();
+            const MAX_REACTION_ID: ::reactor_rt::LocalReactionId =
+                ::reactor_rt::LocalReactionId::new((#num_local_reactions) as _);
+
+            fn assemble(
+                _: Self::Params,
+                __ctx: ::reactor_rt::assembly::AssemblyCtx<Self>,
+            ) -> ::reactor_rt::assembly::AssemblyResult<::reactor_rt::assembly::FinishedReactor<Self>> {
+                __ctx.assemble(|__ctx| {
+                    #[allow(unused_mut)]
+                    let mut __names = [None; #num_local_reactions];
+                    #(#names)*
+                    __ctx.assemble_self(
+                        |__cc, __id| Self::__reactor_create(__cc, __id),
+                        // number of non-synthetic reactions
+                        #num_reactions,
+                        // reaction debug labels
+                        __names,
+                        // dependency declarations
+                        |__dd, __self, __reactions| {
+                            #(#declarations)*
+                            Self::__reactor_declare_timers(__dd, __self, &__reactions[#num_reactions..])
+                        },
+                    )
+                })
+            }
+        }
+
+        impl ::reactor_rt::ReactorBehavior for #self_ty {
+            #[inline]
+            fn id(&self) -> ::reactor_rt::ReactorId {
+                self.__reactor_id
+            }
+
+            fn react(&mut self, __ctx: &mut ::reactor_rt::ReactionCtx, __rid: ::reactor_rt::LocalReactionId) {
+                match __rid.raw() as usize {
+                    #(#dispatch)*
+                    __rid => self.__reactor_react_timer(__ctx, #timer_index),
+                }
+            }
+        }
+    })
+}
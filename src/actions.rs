@@ -22,19 +22,40 @@
  * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 
+#[cfg(not(feature = "no-unsafe"))]
+use std::cell::UnsafeCell;
 use std::cmp::Reverse;
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+#[cfg(feature = "no-unsafe")]
+use atomic_refcell::AtomicRefCell;
+
 use crate::assembly::{TriggerId, TriggerLike};
 use crate::*;
 
 use vecmap::{Entry, VecMap};
 
 /// A logical action.
-pub struct LogicalAction<T: Sync>(pub(crate) Action<Logical, T>);
+pub struct LogicalAction<T: Sync>(Arc<ActionCell<T>>);
+
+cfg_if! {
+    if #[cfg(feature = "no-unsafe")] {
+        type ActionCell<T> = AtomicRefCell<Action<Logical, T>>;
+    } else {
+        type ActionCell<T> = UnsafeCell<Action<Logical, T>>;
+    }
+}
+
+// The cell is shared with the handle through which the scheduler
+// drops the values of the action, see [LogicalAction::share]. The
+// scheduler only uses it between tags, never concurrently with the
+// reactions of the reactor that owns the action, so the action is
+// Send and Sync exactly when it would be without the cell.
+unsafe impl<T: Sync> Sync for LogicalAction<T> {}
+unsafe impl<T: Send + Sync> Send for LogicalAction<T> {}
 
 /// A physical action. Physical actions may only be used with
 /// the API of [AsyncCtx](crate::AsyncCtx).
@@ -44,8 +65,60 @@ pub struct PhysicalAction<T: Sync>(pub(crate) Action<Physical, T>);
 pub(crate) struct Logical;
 pub(crate) struct Physical;
 
+/// Identifies an event scheduled on an action, so that it can
+/// be cancelled later with [ReactionCtx::cancel](crate::ReactionCtx::cancel).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ScheduleToken {
+    pub(crate) trigger: TriggerId,
+    pub(crate) tag: EventTag,
+}
+
+impl ScheduleToken {
+    /// The tag at which the event is scheduled.
+    pub fn tag(&self) -> EventTag {
+        self.tag
+    }
+}
+
+/// What to do with an event that is scheduled on an action
+/// less than `min_spacing` after the previous one. This mirrors
+/// the spacing policies of LF actions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SpacingPolicy {
+    /// Schedule the new event at the earliest tag that respects
+    /// the minimum spacing. This is the default.
+    Defer,
+    /// Ignore the new event.
+    Drop,
+    /// If the previous event is still pending, replace its value
+    /// with the new one. Otherwise, behave like [Self::Defer].
+    Replace,
+    /// If the previous event is still pending, cancel it and
+    /// schedule the new event at its intended tag. Reactions
+    /// that were to be triggered by the cancelled event may still
+    /// be executed at its tag, but will see the action as absent.
+    /// If the previous event is not pending anymore, behave like
+    /// [Self::Defer].
+    Update,
+}
+
+impl Default for SpacingPolicy {
+    fn default() -> Self {
+        SpacingPolicy::Defer
+    }
+}
+
 pub(crate) struct Action<Kind, T: Sync> {
     pub(crate) min_delay: Duration,
+    min_spacing: Option<Duration>,
+    policy: SpacingPolicy,
+    /// Tag of the last event scheduled on this action, used
+    /// to enforce the minimum spacing.
+    last_tag: Option<EventTag>,
+    /// Whether values scheduled for a tag that already holds
+    /// a value are bumped to the next free microstep instead
+    /// of overwriting it.
+    queued: bool,
     id: TriggerId,
     // is_logical: bool,
     _logical: PhantomData<Kind>,
@@ -53,7 +126,8 @@ pub(crate) struct Action<Kind, T: Sync> {
     /// Stores values of an action for future scheduled events.
     /// We rely strongly on the fact that any value put in there by [Action.schedule_future_value]
     /// will be cleaned up after that tag. Otherwise the map will
-    /// blow up the heap.
+    /// blow up the heap. The scheduler does it after the tags
+    /// where the action is present.
     map: VecMap<Reverse<EventTag>, Option<T>>,
 }
 
@@ -75,14 +149,99 @@ impl<K, T: Sync> Action<K, T> {
         }
     }
 
+    /// Schedule a value at the `intended` tag, enforcing the minimum
+    /// spacing of this action according to its policy. The `now` tag
+    /// is used to determine whether the previous event is still
+    /// pending. Returns the tag at which an event must be enqueued,
+    /// or None if no new event is necessary.
+    pub(crate) fn schedule_with_spacing(&mut self, mut intended: EventTag, now: EventTag, value: Option<T>) -> Option<EventTag> {
+        if self.queued {
+            while self.map.contains_key(&Reverse(intended)) {
+                intended = intended.next_microstep();
+            }
+        }
+
+        let (min_spacing, last) = match (self.min_spacing, self.last_tag) {
+            (Some(min_spacing), Some(last)) => (min_spacing, last),
+            _ => {
+                self.last_tag = Some(intended);
+                self.schedule_future_value(intended, value);
+                return Some(intended);
+            }
+        };
+
+        let earliest = last.successor(min_spacing);
+        if intended >= earliest {
+            self.last_tag = Some(intended);
+            self.schedule_future_value(intended, value);
+            return Some(intended);
+        }
+
+        let pending = last > now && self.map.contains_key(&Reverse(last));
+        let tag = match self.policy {
+            SpacingPolicy::Drop => {
+                trace!(
+                    "Event at {} dropped because of min_spacing (last event at {})",
+                    intended,
+                    last
+                );
+                return None;
+            }
+            SpacingPolicy::Replace if pending => {
+                self.schedule_future_value(last, value);
+                return None;
+            }
+            SpacingPolicy::Update if pending => {
+                self.forget_value(&last);
+                intended
+            }
+            _ => earliest,
+        };
+        self.last_tag = Some(tag);
+        self.schedule_future_value(tag, value);
+        Some(tag)
+    }
+
+    /// Returns the values that are scheduled for future tags,
+    /// to write them into a checkpoint.
+    pub(crate) fn pending_values(&self) -> impl Iterator<Item = (EventTag, Option<&T>)> + '_ {
+        self.map.iter().map(|(Reverse(tag), value)| (*tag, value.as_ref()))
+    }
+
+    pub(crate) fn last_tag(&self) -> Option<EventTag> {
+        self.last_tag
+    }
+
+    pub(crate) fn set_last_tag(&mut self, tag: Option<EventTag>) {
+        self.last_tag = tag;
+    }
+
+    /// Remove the event scheduled at the given tag, if any.
+    /// Unlike [Self::forget_value], this tells whether an event
+    /// was removed, even if it carried no value.
+    #[inline]
+    pub(crate) fn cancel(&mut self, time: &EventTag) -> bool {
+        self.map.remove(&Reverse(*time)).is_some()
+    }
+
     #[inline]
     pub(crate) fn forget_value(&mut self, time: &EventTag) -> Option<T> {
         self.map.remove(&Reverse(*time)).flatten()
     }
 
-    fn new_impl(id: TriggerId, min_delay: Option<Duration>, _is_logical: bool) -> Self {
+    fn new_impl(
+        id: TriggerId,
+        min_delay: Option<Duration>,
+        min_spacing: Option<Duration>,
+        policy: SpacingPolicy,
+        _is_logical: bool,
+    ) -> Self {
         Action {
             min_delay: min_delay.unwrap_or(Duration::ZERO),
+            min_spacing,
+            policy,
+            last_tag: None,
+            queued: false,
             // is_logical,
             id,
             _logical: PhantomData,
@@ -123,7 +282,7 @@ impl<T: Sync, K> triggers::ReactionTriggerWithRefAccess<T> for Action<K, T> {
 impl<T: Sync> ReactionTrigger<T> for LogicalAction<T> {
     #[inline]
     fn is_present(&self, now: &EventTag, start: &Instant) -> bool {
-        self.0.is_present(now, start)
+        self.inner().is_present(now, start)
     }
 
     #[inline]
@@ -131,31 +290,78 @@ impl<T: Sync> ReactionTrigger<T> for LogicalAction<T> {
     where
         T: Copy,
     {
-        self.0.get_value(now, start)
+        self.inner().get_value(now, start)
     }
 
     #[inline]
     fn use_value_ref<O>(&self, now: &EventTag, start: &Instant, action: impl FnOnce(Option<&T>) -> O) -> O {
-        self.0.use_value_ref(now, start, action)
+        self.inner().use_value_ref(now, start, action)
     }
 }
 
 #[cfg(not(feature = "no-unsafe"))]
 impl<T: Sync> triggers::ReactionTriggerWithRefAccess<T> for LogicalAction<T> {
     fn get_value_ref(&self, now: &EventTag, start: &Instant) -> Option<&T> {
-        self.0.get_value_ref(now, start)
+        self.inner().get_value_ref(now, start)
     }
 }
 
 impl<T: Sync> LogicalAction<T> {
-    pub(crate) fn new(id: TriggerId, min_delay: Option<Duration>) -> Self {
-        Self(Action::new_impl(id, min_delay, true))
+    pub(crate) fn new(id: TriggerId, min_delay: Option<Duration>, min_spacing: Option<Duration>, policy: SpacingPolicy) -> Self {
+        Self(Arc::new(ActionCell::new(Action::new_impl(
+            id,
+            min_delay,
+            min_spacing,
+            policy,
+            true,
+        ))))
+    }
+
+    /// Create a queued action: every value scheduled on it is
+    /// preserved, values scheduled for the same tag are pushed
+    /// to successive microsteps.
+    pub(crate) fn new_queued(id: TriggerId, min_delay: Option<Duration>) -> Self {
+        let mut action = Self::new(id, min_delay, None, SpacingPolicy::Defer);
+        action.inner_mut().queued = true;
+        action
+    }
+
+    /// Returns another handle to this action, through which
+    /// the scheduler drops its value after a tag.
+    pub(crate) fn share(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "no-unsafe")] {
+            pub(crate) fn inner(&self) -> impl Deref<Target = Action<Logical, T>> + '_ {
+                AtomicRefCell::borrow(&self.0)
+            }
+
+            pub(crate) fn inner_mut(&mut self) -> impl DerefMut<Target = Action<Logical, T>> + '_ {
+                AtomicRefCell::borrow_mut(&self.0)
+            }
+        } else {
+            // Like the cell of a port, the action is only accessed by
+            // the reactor that owns it during reactions, and by the
+            // scheduler between tags, never concurrently.
+
+            #[inline]
+            pub(crate) fn inner(&self) -> &Action<Logical, T> {
+                unsafe { &*self.0.get() }
+            }
+
+            #[inline]
+            pub(crate) fn inner_mut(&mut self) -> &mut Action<Logical, T> {
+                unsafe { &mut *self.0.get() }
+            }
+        }
     }
 }
 
 impl<T: Sync> PhysicalAction<T> {
-    fn new(id: TriggerId, min_delay: Option<Duration>) -> Self {
-        Self(Action::new_impl(id, min_delay, false))
+    fn new(id: TriggerId, min_delay: Option<Duration>, min_spacing: Option<Duration>, policy: SpacingPolicy) -> Self {
+        Self(Action::new_impl(id, min_delay, min_spacing, policy, false))
     }
 }
 
@@ -167,7 +373,7 @@ impl<T: Sync> TriggerLike for PhysicalAction<T> {
 
 impl<T: Sync> TriggerLike for LogicalAction<T> {
     fn get_id(&self) -> TriggerId {
-        self.0.id
+        self.inner().id
     }
 }
 /*#[cfg(test)] //fixme
@@ -227,12 +433,18 @@ mod test {
 /// on the action are
 ///
 /// See [crate::ReactionCtx::spawn_physical_thread].
-#[derive(Clone)]
 pub struct PhysicalActionRef<T: Sync>(Arc<Mutex<PhysicalAction<T>>>);
 
+// not derived, so that values need not be Clone
+impl<T: Sync> Clone for PhysicalActionRef<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
 impl<T: Sync> PhysicalActionRef<T> {
-    pub(crate) fn new(id: TriggerId, min_delay: Option<Duration>) -> Self {
-        Self(Arc::new(Mutex::new(PhysicalAction::new(id, min_delay))))
+    pub(crate) fn new(id: TriggerId, min_delay: Option<Duration>, min_spacing: Option<Duration>, policy: SpacingPolicy) -> Self {
+        Self(Arc::new(Mutex::new(PhysicalAction::new(id, min_delay, min_spacing, policy))))
     }
 
     pub(crate) fn use_mut<O>(&self, f: impl FnOnce(&mut PhysicalAction<T>) -> O) -> Result<O, ()> {
@@ -277,3 +489,248 @@ impl<T: Sync> ReactionTrigger<T> for PhysicalActionRef<T> {
         self.use_value(|a| a.0.use_value_ref(now, start, action)).unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn action(policy: SpacingPolicy) -> LogicalAction<i32> {
+        LogicalAction::new(TriggerId::FIRST_REGULAR, None, Some(Duration::from_millis(10)), policy)
+    }
+
+    #[test]
+    fn test_without_min_spacing_values_are_overwritten() {
+        let mut action = LogicalAction::<i32>::new(TriggerId::FIRST_REGULAR, None, None, SpacingPolicy::Drop);
+        assert_eq!(
+            Some(tag!(T0 + 5 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(1))
+        );
+        assert_eq!(
+            Some(tag!(T0 + 5 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+    }
+
+    #[test]
+    fn test_queued_action_preserves_values() {
+        let mut action = LogicalAction::<i32>::new_queued(TriggerId::FIRST_REGULAR, None);
+        let t5 = tag!(T0 + 5 ms);
+        assert_eq!(
+            Some(t5),
+            action.inner_mut().schedule_with_spacing(t5, EventTag::ORIGIN, Some(1))
+        );
+        assert_eq!(
+            Some(t5.next_microstep()),
+            action.inner_mut().schedule_with_spacing(t5, EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(
+            Some(t5.next_microstep().next_microstep()),
+            action.inner_mut().schedule_with_spacing(t5, EventTag::ORIGIN, Some(3))
+        );
+        assert_eq!(Some(1), action.inner_mut().forget_value(&t5));
+        assert_eq!(Some(2), action.inner_mut().forget_value(&t5.next_microstep()));
+        assert_eq!(
+            Some(3),
+            action.inner_mut().forget_value(&t5.next_microstep().next_microstep())
+        );
+    }
+
+    #[test]
+    fn test_queued_action_preserves_values_across_microsteps() {
+        let mut action = LogicalAction::<i32>::new_queued(TriggerId::FIRST_REGULAR, None);
+        let t5 = tag!(T0 + 5 ms);
+        let t5_1 = t5.next_microstep();
+        let t5_2 = t5_1.next_microstep();
+        assert_eq!(
+            Some(t5_1),
+            action.inner_mut().schedule_with_spacing(t5_1, EventTag::ORIGIN, Some(1))
+        );
+        // each value takes the first free microstep from its tag
+        assert_eq!(
+            Some(t5),
+            action.inner_mut().schedule_with_spacing(t5, EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(
+            Some(t5_2),
+            action.inner_mut().schedule_with_spacing(t5, EventTag::ORIGIN, Some(3))
+        );
+
+        // once 5 ms is processed, the pending microsteps are still skipped
+        assert_eq!(Some(2), action.inner_mut().forget_value(&t5));
+        assert_eq!(
+            Some(tag!(T0 + 5 ms, 3)),
+            action.inner_mut().schedule_with_spacing(t5_1, t5, Some(4))
+        );
+        assert_eq!(Some(1), action.inner_mut().forget_value(&t5_1));
+        assert_eq!(Some(3), action.inner_mut().forget_value(&t5_2));
+        assert_eq!(Some(4), action.inner_mut().forget_value(&tag!(T0 + 5 ms, 3)));
+    }
+
+    #[test]
+    fn test_cancel_removes_event() {
+        let mut action = LogicalAction::<i32>::new(TriggerId::FIRST_REGULAR, None, None, SpacingPolicy::Defer);
+        action
+            .inner_mut()
+            .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, None);
+        assert!(action.inner_mut().is_present(&tag!(T0 + 5 ms), &Instant::now()));
+        assert!(action.inner_mut().cancel(&tag!(T0 + 5 ms)));
+        assert!(!action.inner_mut().is_present(&tag!(T0 + 5 ms), &Instant::now()));
+        assert!(!action.inner_mut().cancel(&tag!(T0 + 5 ms)));
+    }
+
+    #[test]
+    fn test_defer_policy() {
+        let mut action = action(SpacingPolicy::Defer);
+        assert_eq!(
+            Some(tag!(T0 + 5 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(1))
+        );
+        assert_eq!(
+            Some(tag!(T0 + 15 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(
+            Some(tag!(T0 + 25 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 20 ms), EventTag::ORIGIN, Some(3))
+        );
+        assert_eq!(
+            Some(tag!(T0 + 40 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 40 ms), EventTag::ORIGIN, Some(4))
+        );
+        assert_eq!(Some(1), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+        assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 15 ms)));
+        assert_eq!(Some(3), action.inner_mut().forget_value(&tag!(T0 + 25 ms)));
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let mut action = action(SpacingPolicy::Drop);
+        assert_eq!(
+            Some(tag!(T0 + 5 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(1))
+        );
+        assert_eq!(
+            None,
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 10 ms), EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(Some(1), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+        assert_eq!(None, action.inner_mut().forget_value(&tag!(T0 + 10 ms)));
+    }
+
+    #[test]
+    fn test_replace_policy() {
+        let mut action = action(SpacingPolicy::Replace);
+        assert_eq!(
+            Some(tag!(T0 + 5 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(1))
+        );
+        assert_eq!(
+            None,
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 10 ms), EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+        // the previous event has been processed, so this is deferred
+        assert_eq!(
+            Some(tag!(T0 + 15 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 10 ms), tag!(T0 + 5 ms), Some(3))
+        );
+    }
+
+    #[test]
+    fn test_update_policy() {
+        let mut action = action(SpacingPolicy::Update);
+        assert_eq!(
+            Some(tag!(T0 + 20 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 20 ms), EventTag::ORIGIN, Some(1))
+        );
+        assert_eq!(
+            Some(tag!(T0 + 25 ms)),
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 25 ms), EventTag::ORIGIN, Some(2))
+        );
+        assert_eq!(None, action.inner_mut().forget_value(&tag!(T0 + 20 ms)));
+        assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 25 ms)));
+    }
+
+    #[test]
+    fn test_policies_at_the_spacing_boundary() {
+        for policy in [
+            SpacingPolicy::Defer,
+            SpacingPolicy::Drop,
+            SpacingPolicy::Replace,
+            SpacingPolicy::Update,
+        ] {
+            // exactly min_spacing after the last event, no policy applies
+            let mut action = action(policy);
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(1));
+            assert_eq!(
+                Some(tag!(T0 + 15 ms)),
+                action
+                    .inner_mut()
+                    .schedule_with_spacing(tag!(T0 + 15 ms), EventTag::ORIGIN, Some(2))
+            );
+            assert_eq!(Some(1), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+            assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 15 ms)));
+        }
+
+        // just before the boundary, each policy applies
+        let just_before = EventTag::offset(Duration::from_millis(15) - Duration::from_nanos(1), 0);
+        let schedule_twice = |policy| {
+            let mut action = action(policy);
+            action
+                .inner_mut()
+                .schedule_with_spacing(tag!(T0 + 5 ms), EventTag::ORIGIN, Some(1));
+            let tag = action
+                .inner_mut()
+                .schedule_with_spacing(just_before, EventTag::ORIGIN, Some(2));
+            (tag, action)
+        };
+
+        let (tag, mut action) = schedule_twice(SpacingPolicy::Defer);
+        assert_eq!(Some(tag!(T0 + 15 ms)), tag);
+        assert_eq!(Some(1), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+        assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 15 ms)));
+
+        let (tag, mut action) = schedule_twice(SpacingPolicy::Drop);
+        assert_eq!(None, tag);
+        assert_eq!(Some(1), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+        assert_eq!(None, action.inner_mut().forget_value(&just_before));
+
+        let (tag, mut action) = schedule_twice(SpacingPolicy::Replace);
+        assert_eq!(None, tag);
+        assert_eq!(Some(2), action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+
+        let (tag, mut action) = schedule_twice(SpacingPolicy::Update);
+        assert_eq!(Some(just_before), tag);
+        assert_eq!(None, action.inner_mut().forget_value(&tag!(T0 + 5 ms)));
+        assert_eq!(Some(2), action.inner_mut().forget_value(&just_before));
+    }
+}
@@ -1,12 +1,15 @@
 //! Module containing the API to initialize a reactor program.
 
+use std::fmt::{Display, Formatter};
+
 use AssemblyErrorImpl::*;
 
 pub use crate::ids::GlobalReactionId;
 // this is where most of the stuff is implemented
 pub use crate::scheduler::assembly_impl::*;
+pub use crate::scheduler::mutations::Mutator;
 pub use crate::triggers::{TriggerId, TriggerLike};
-use crate::{DebugInfoRegistry, LocalReactionId, ReactorBehavior};
+use crate::{DebugInfoRegistry, LocalReactionId, Mode, ReactorBehavior, ReactorId};
 pub(crate) type PortId = TriggerId;
 
 /// Wrapper around the user struct for safe dispatch.
@@ -41,8 +44,50 @@ pub type AssemblyResult<T = ()> = Result<T, AssemblyError>;
 pub struct AssemblyError(pub(crate) AssemblyErrorImpl);
 
 impl AssemblyError {
-    pub(crate) fn lift(self, debug: &DebugInfoRegistry) -> String {
-        self.display(debug)
+    /// Resolve the ids of the components involved in
+    /// this error into their path.
+    pub(crate) fn lift(self, debug: &DebugInfoRegistry) -> AssemblyFailure {
+        match self.0 {
+            CyclicDependency(upstream, downstream) => AssemblyFailure::CyclicDependency {
+                upstream: debug.fmt_component(upstream).to_string(),
+                downstream: debug.fmt_component(downstream).to_string(),
+            },
+            CyclicDependencyGraph => AssemblyFailure::CyclicDependencyGraph,
+            CannotBind(upstream, downstream) => AssemblyFailure::CannotBind {
+                upstream: debug.fmt_component(upstream).to_string(),
+                downstream: debug.fmt_component(downstream).to_string(),
+            },
+            InvalidDeadlineHandler(reaction, handler) => AssemblyFailure::InvalidDeadlineHandler {
+                reaction: debug.fmt_reaction(reaction).to_string(),
+                handler: debug.fmt_reaction(handler).to_string(),
+            },
+            IdOverflow => AssemblyFailure::IdOverflow,
+            DuplicateNetworkInput(port) => AssemblyFailure::DuplicateNetworkInput { port },
+            InvalidInitialMode(reactor) => {
+                AssemblyFailure::InvalidInitialMode { reactor: debug.get_debug_info(reactor).to_string() }
+            }
+            ForeignModeReaction(reaction, mode) => AssemblyFailure::ForeignModeMember {
+                member: debug.fmt_reaction(reaction).to_string(),
+                mode: debug.fmt_component(mode.get_id()).to_string(),
+            },
+            ForeignModeTimer(timer, mode) => AssemblyFailure::ForeignModeMember {
+                member: debug.fmt_component(timer).to_string(),
+                mode: debug.fmt_component(mode.get_id()).to_string(),
+            },
+            ForeignModeChild(child, mode) => AssemblyFailure::ForeignModeMember {
+                member: debug.get_debug_info(child).to_string(),
+                mode: debug.fmt_component(mode.get_id()).to_string(),
+            },
+            UnknownPort(port) if debug.has_trigger(port) => {
+                AssemblyFailure::UnknownPort { path: debug.fmt_component(port).to_string() }
+            }
+            UnknownPort(port) => AssemblyFailure::UnknownPort { path: format!("{:?}", port) },
+            PortTypeMismatch(upstream, downstream) => AssemblyFailure::PortTypeMismatch {
+                upstream: debug.fmt_component(upstream).to_string(),
+                downstream: debug.fmt_component(downstream).to_string(),
+            },
+            UnsupportedMutation(component) => AssemblyFailure::UnsupportedMutation { component },
+        }
     }
 }
 
@@ -50,28 +95,96 @@ pub(crate) enum AssemblyErrorImpl {
     CyclicDependency(PortId, PortId),
     CyclicDependencyGraph,
     CannotBind(PortId, PortId),
+    InvalidDeadlineHandler(GlobalReactionId, GlobalReactionId),
     IdOverflow,
+    DuplicateNetworkInput(u32),
+    InvalidInitialMode(ReactorId),
+    ForeignModeReaction(GlobalReactionId, Mode),
+    ForeignModeTimer(TriggerId, Mode),
+    ForeignModeChild(ReactorId, Mode),
+    UnknownPort(PortId),
+    PortTypeMismatch(PortId, PortId),
+    UnsupportedMutation(&'static str),
 }
 
-impl AssemblyError {
-    fn display(&self, debug: &DebugInfoRegistry) -> String {
-        match self.0 {
-            CyclicDependency(upstream, downstream) => format!(
-                "Port {} is already in the downstream of port {}",
-                debug.fmt_component(upstream),
-                debug.fmt_component(downstream)
-            ),
-            CyclicDependencyGraph => "Cyclic dependency graph".to_string(),
-            CannotBind(upstream, downstream) => format!(
-                "Cannot bind {} to {}, downstream is already bound",
-                debug.fmt_component(upstream),
-                debug.fmt_component(downstream)
+/// An [AssemblyError] that prevented the program from being
+/// assembled. Components are identified by their path in
+/// the program, eg `/main/child/port`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AssemblyFailure {
+    /// Binding the two ports would create a cycle.
+    CyclicDependency { upstream: String, downstream: String },
+    /// The dependency graph between reactions is cyclic.
+    CyclicDependencyGraph,
+    /// The downstream port is already bound to another port.
+    CannotBind { upstream: String, downstream: String },
+    /// The deadline handler is not another reaction of the
+    /// reactor of the reaction.
+    InvalidDeadlineHandler { reaction: String, handler: String },
+    /// Too many components were allocated.
+    IdOverflow,
+    /// The path given to [ProgramBuilder::connect](crate::ProgramBuilder::connect),
+    /// or the ID given to a [Mutator], does not name a port of
+    /// a reactor of the program.
+    UnknownPort { path: String },
+    /// The ports given to [ProgramBuilder::connect](crate::ProgramBuilder::connect)
+    /// or to a [Mutator] have different types.
+    PortTypeMismatch { upstream: String, downstream: String },
+    /// Several network inputs of the program have the same port
+    /// number, see [ComponentCreator::new_network_input].
+    DuplicateNetworkInput { port: u32 },
+    /// A modal reactor does not have exactly one initial mode,
+    /// see [ComponentCreator::new_mode].
+    InvalidInitialMode { reactor: String },
+    /// A reaction, timer or child reactor was declared in a mode
+    /// of a reactor it does not belong to, see eg
+    /// [DependencyDeclarator::declare_mode_reaction].
+    ForeignModeMember { member: String, mode: String },
+    /// Reactors added by a [Mutator] declare a kind of component
+    /// that cannot be added to a running program.
+    UnsupportedMutation { component: &'static str },
+}
+
+impl Display for AssemblyFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssemblyFailure::CyclicDependency { upstream, downstream } => {
+                write!(f, "Port {} is already in the downstream of port {}", upstream, downstream)
+            }
+            AssemblyFailure::CyclicDependencyGraph => write!(f, "Cyclic dependency graph"),
+            AssemblyFailure::CannotBind { upstream, downstream } => {
+                write!(f, "Cannot bind {} to {}, downstream is already bound", upstream, downstream)
+            }
+            AssemblyFailure::InvalidDeadlineHandler { reaction, handler } => write!(
+                f,
+                "Deadline handler {} of reaction {} must be another reaction of the same reactor",
+                handler, reaction
             ),
-            IdOverflow => "Overflow when allocating component ID".to_string(),
+            AssemblyFailure::IdOverflow => write!(f, "Overflow when allocating component ID"),
+            AssemblyFailure::UnknownPort { path } => write!(f, "No port {}", path),
+            AssemblyFailure::PortTypeMismatch { upstream, downstream } => {
+                write!(f, "Cannot bind {} to {}, ports have different types", upstream, downstream)
+            }
+            AssemblyFailure::DuplicateNetworkInput { port } => write!(f, "Duplicate network input {}", port),
+            AssemblyFailure::InvalidInitialMode { reactor } => {
+                write!(f, "Modal reactor {} must have exactly one initial mode", reactor)
+            }
+            AssemblyFailure::ForeignModeMember { member, mode } => {
+                write!(
+                    f,
+                    "{} cannot be declared in mode {}, which belongs to another reactor",
+                    member, mode
+                )
+            }
+            AssemblyFailure::UnsupportedMutation { component } => {
+                write!(f, "{} cannot be added to a running program", component)
+            }
         }
     }
 }
 
+impl std::error::Error for AssemblyFailure {}
+
 /// Kind of a port.
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum PortKind {
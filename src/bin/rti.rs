@@ -0,0 +1,52 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Runtime infrastructure (RTI) for federated reactor programs.
+//!
+//! Usage: `rti <number of federates> [<address>]`.
+//! The address defaults to `127.0.0.1:15045`.
+
+use reactor_rt::federated::Rti;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:15045";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let num_federates = args.next().and_then(|n| n.parse::<usize>().ok()).filter(|n| *n > 0);
+    let num_federates = match num_federates {
+        Some(n) => n,
+        None => {
+            eprintln!("Usage: rti <number of federates> [<address>]");
+            std::process::exit(1)
+        }
+    };
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+
+    let rti = Rti::bind(&address, num_federates).expect("Could not bind the RTI");
+    eprintln!("RTI listening on {} for {} federates", address, num_federates);
+    if let Err(e) = rti.run() {
+        eprintln!("RTI failed: {}", e);
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Federated execution of reactor programs.
+//!
+//! A federated program is split into several *federates*, which
+//! are top-level reactors running in separate processes. Each
+//! federate runs its own [SyncScheduler](crate::SyncScheduler),
+//! started with [SchedulerOptions::federate](crate::SchedulerOptions::federate)
+//! set. Federates communicate over TCP with a central *runtime
+//! infrastructure* ([Rti]), which
+//! - synchronizes the start time of all federates,
+//! - forwards tagged messages between federates, and
+//! - grants tag advances to federates, so that no federate
+//!   processes a tag before it has received all messages sent
+//!   to it for that tag.
+//!
+//! The RTI is available as the `rti` binary of this crate.
+//!
+//! Ports crossing a federate boundary are replaced by
+//! generated network reactions: the sending side serializes
+//! the port value and calls [ReactionCtx::send_network_message](crate::ReactionCtx::send_network_message),
+//! the receiving side is triggered by the network input created with
+//! [ComponentCreator::new_network_input](crate::assembly::ComponentCreator::new_network_input),
+//! deserializes the value and sets its port.
+//!
+//! Current limitations:
+//! - all connections between federates are assumed to have no delay,
+//! - zero-delay cycles between federates are not supported. The RTI
+//!   sends provisional tag advance grants (PTAG) to their members, but
+//!   federates do not send absent messages for their outputs, so a
+//!   provisional grant only lets a federate process the tags before
+//!   the granted one,
+//! - a message received for a tag that the federate has already
+//!   started processing is delayed by one microstep,
+//! - a federate that only reacts to physical actions may observe
+//!   them after downstream federates have advanced their logical time.
+
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub use self::rti::Rti;
+use crate::{EventTag, MicroStep};
+
+pub(crate) mod protocol;
+mod rti;
+
+/// The ID of a federate within a federation. Federates
+/// are numbered from zero.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FederateId(pub u16);
+
+impl FederateId {
+    #[inline]
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Display for FederateId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "federate#{}", self.0)
+    }
+}
+
+/// Identifies the receiving end of a connection between
+/// two federates.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct NetworkChannel {
+    /// The federate that receives the messages.
+    pub federate: FederateId,
+    /// The network input within that federate, see
+    /// [ComponentCreator::new_network_input](crate::assembly::ComponentCreator::new_network_input).
+    pub port: u32,
+}
+
+/// Options to run a program as a federate. See
+/// [SchedulerOptions::federate](crate::SchedulerOptions::federate).
+#[derive(Clone, Debug)]
+pub struct FederateOptions {
+    /// ID of this federate.
+    pub id: FederateId,
+    /// Address the RTI listens on.
+    pub rti_address: SocketAddr,
+    /// Federates that send messages to this one.
+    pub upstream: Vec<FederateId>,
+}
+
+/// A tag greater than any tag a program may reach.
+/// A federate that has no more events to process
+/// sends this as its next event tag.
+pub(crate) const FOREVER: EventTag = EventTag {
+    offset_from_t0: Duration::from_nanos(u64::MAX),
+    microstep: MicroStep::MAX,
+};
@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Wire protocol spoken between federates and the RTI.
+//!
+//! Each message starts with a one-byte kind, followed by
+//! its fields. Integers are big-endian, tags are encoded
+//! as their offset from T0 in nanoseconds (u64) followed
+//! by their microstep (u32).
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use super::{FederateId, NetworkChannel};
+use crate::{EventTag, MicroStep};
+
+const REGISTER: u8 = 1;
+const START_TIME: u8 = 2;
+const NEXT_EVENT_TAG: u8 = 3;
+const TAG_ADVANCE_GRANT: u8 = 4;
+const PROVISIONAL_TAG_ADVANCE_GRANT: u8 = 5;
+const LOGICAL_TAG_COMPLETE: u8 = 6;
+const TAGGED_MESSAGE: u8 = 7;
+const RESIGN: u8 = 8;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum Message {
+    /// Federate -> RTI. First message sent by a federate,
+    /// lists the federates it receives messages from.
+    Register {
+        federate: FederateId,
+        upstream: Vec<FederateId>,
+    },
+    /// RTI -> federate. Start time of the federation, in
+    /// nanoseconds since the unix epoch.
+    StartTime(u64),
+    /// Federate -> RTI. The federate wants to process the given tag (NET).
+    NextEventTag(EventTag),
+    /// RTI -> federate. The federate may process all tags
+    /// up to the given one (TAG).
+    TagAdvanceGrant(EventTag),
+    /// RTI -> federate. Messages for the given tag may still
+    /// arrive, but not for earlier tags, which the federate may
+    /// process (PTAG).
+    ProvisionalTagAdvanceGrant(EventTag),
+    /// Federate -> RTI. The federate is done processing the given tag (LTC).
+    LogicalTagComplete(EventTag),
+    /// Federate -> RTI -> federate. A value sent over a connection
+    /// between federates.
+    TaggedMessage {
+        channel: NetworkChannel,
+        tag: EventTag,
+        payload: Vec<u8>,
+    },
+    /// Federate -> RTI. The federate has shut down.
+    Resign,
+}
+
+impl Message {
+    pub(crate) fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut buf = Vec::new();
+        match self {
+            Message::Register { federate, upstream } => {
+                buf.push(REGISTER);
+                put_u16(&mut buf, federate.0);
+                let len = u16::try_from(upstream.len()).map_err(|_| too_long("upstream federates"))?;
+                put_u16(&mut buf, len);
+                for up in upstream {
+                    put_u16(&mut buf, up.0);
+                }
+            }
+            Message::StartTime(nanos) => {
+                buf.push(START_TIME);
+                put_u64(&mut buf, *nanos);
+            }
+            Message::NextEventTag(tag) => put_tag_message(&mut buf, NEXT_EVENT_TAG, *tag),
+            Message::TagAdvanceGrant(tag) => put_tag_message(&mut buf, TAG_ADVANCE_GRANT, *tag),
+            Message::ProvisionalTagAdvanceGrant(tag) => put_tag_message(&mut buf, PROVISIONAL_TAG_ADVANCE_GRANT, *tag),
+            Message::LogicalTagComplete(tag) => put_tag_message(&mut buf, LOGICAL_TAG_COMPLETE, *tag),
+            Message::TaggedMessage { channel, tag, payload } => {
+                put_tag_message(&mut buf, TAGGED_MESSAGE, *tag);
+                put_u16(&mut buf, channel.federate.0);
+                put_u32(&mut buf, channel.port);
+                let len = u32::try_from(payload.len()).map_err(|_| too_long("payload bytes"))?;
+                put_u32(&mut buf, len);
+                buf.extend_from_slice(payload);
+            }
+            Message::Resign => buf.push(RESIGN),
+        }
+        w.write_all(&buf)?;
+        w.flush()
+    }
+
+    pub(crate) fn read_from(r: &mut impl Read) -> io::Result<Message> {
+        let msg = match get_u8(r)? {
+            REGISTER => {
+                let federate = FederateId(get_u16(r)?);
+                let len = get_u16(r)?;
+                let upstream = (0..len).map(|_| get_u16(r).map(FederateId)).collect::<io::Result<_>>()?;
+                Message::Register { federate, upstream }
+            }
+            START_TIME => Message::StartTime(get_u64(r)?),
+            NEXT_EVENT_TAG => Message::NextEventTag(get_tag(r)?),
+            TAG_ADVANCE_GRANT => Message::TagAdvanceGrant(get_tag(r)?),
+            PROVISIONAL_TAG_ADVANCE_GRANT => Message::ProvisionalTagAdvanceGrant(get_tag(r)?),
+            LOGICAL_TAG_COMPLETE => Message::LogicalTagComplete(get_tag(r)?),
+            TAGGED_MESSAGE => {
+                let tag = get_tag(r)?;
+                let federate = FederateId(get_u16(r)?);
+                let port = get_u32(r)?;
+                let mut payload = vec![0; get_u32(r)? as usize];
+                r.read_exact(&mut payload)?;
+                Message::TaggedMessage {
+                    channel: NetworkChannel { federate, port },
+                    tag,
+                    payload,
+                }
+            }
+            RESIGN => Message::Resign,
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown message kind {}", kind),
+                ))
+            }
+        };
+        Ok(msg)
+    }
+}
+
+/// Error for a message field that has too many elements
+/// to encode its length.
+fn too_long(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Too many {} to send", what))
+}
+
+fn put_tag_message(buf: &mut Vec<u8>, kind: u8, tag: EventTag) {
+    buf.push(kind);
+    let nanos = u64::try_from(tag.offset_from_t0.as_nanos()).unwrap_or(u64::MAX);
+    put_u64(buf, nanos);
+    put_u32(buf, tag.microstep.raw());
+}
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_be_bytes())
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_be_bytes())
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_be_bytes())
+}
+
+fn get_tag(r: &mut impl Read) -> io::Result<EventTag> {
+    let offset_from_t0 = Duration::from_nanos(get_u64(r)?);
+    let microstep = MicroStep::new(get_u32(r)?);
+    Ok(EventTag { offset_from_t0, microstep })
+}
+
+fn get_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn get_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    r.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn get_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn get_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::federated::FOREVER;
+    use crate::{delay, tag};
+
+    fn round_trip(msg: Message) {
+        let mut buf = Vec::new();
+        msg.write_to(&mut buf).unwrap();
+        let read = Message::read_from(&mut buf.as_slice()).unwrap();
+        assert_eq!(msg, read);
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        round_trip(Message::Register {
+            federate: FederateId(2),
+            upstream: vec![FederateId(0), FederateId(1)],
+        });
+        round_trip(Message::StartTime(1_234_567_890));
+        round_trip(Message::NextEventTag(tag!(T0 + 20 ms, 3)));
+        round_trip(Message::TagAdvanceGrant(FOREVER));
+        round_trip(Message::ProvisionalTagAdvanceGrant(tag!(T0)));
+        round_trip(Message::LogicalTagComplete(tag!(T0 + 1 sec)));
+        round_trip(Message::TaggedMessage {
+            channel: NetworkChannel { federate: FederateId(1), port: 4 },
+            tag: tag!(T0 + 5 ms, 1),
+            payload: vec![1, 2, 3],
+        });
+        round_trip(Message::Resign);
+    }
+
+    #[test]
+    fn test_unknown_message_kind() {
+        let bytes = [0u8];
+        assert!(Message::read_from(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_too_many_upstream_federates() {
+        let msg = Message::Register {
+            federate: FederateId(0),
+            upstream: vec![FederateId(1); usize::from(u16::MAX) + 1],
+        };
+        let mut buf = Vec::new();
+        assert_eq!(msg.write_to(&mut buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // nothing was written
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,484 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::protocol::Message;
+use super::{FederateId, FOREVER};
+use crate::EventTag;
+
+/// Delay between the moment all federates are registered
+/// and the start time of the federation. This leaves time
+/// for the start time message to reach every federate.
+const START_DELAY: Duration = Duration::from_millis(100);
+
+/// Time a new connection has to register a federate.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The runtime infrastructure of a federation. It coordinates
+/// the advance of logical time of all federates, and forwards
+/// messages between them. See the [module documentation](super).
+pub struct Rti {
+    listener: TcpListener,
+    num_federates: usize,
+}
+
+impl Rti {
+    /// Binds the RTI to the given address. It will wait for
+    /// exactly `num_federates` federates to register.
+    pub fn bind(addr: impl ToSocketAddrs, num_federates: usize) -> io::Result<Self> {
+        assert!(num_federates > 0, "A federation needs at least one federate");
+        Ok(Self { listener: TcpListener::bind(addr)?, num_federates })
+    }
+
+    /// The address the RTI listens on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Run the RTI in this thread, until all federates have resigned.
+    pub fn run(self) -> io::Result<()> {
+        let mut federation = Federation::new(self.num_federates);
+        let mut streams = self.accept_federates(&mut federation)?;
+
+        let start_time = (SystemTime::now() + START_DELAY)
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before the unix epoch");
+        for stream in &mut streams {
+            Message::StartTime(start_time.as_nanos() as u64).write_to(stream)?;
+        }
+        info!("All {} federates registered, starting federation", self.num_federates);
+
+        let (tx, rx) = mpsc::channel();
+        for (i, stream) in streams.iter().enumerate() {
+            let mut stream = stream.try_clone()?;
+            let tx = tx.clone();
+            let federate = FederateId(i as u16);
+            std::thread::spawn(move || loop {
+                let msg = Message::read_from(&mut stream);
+                let done = !matches!(msg, Ok(ref msg) if *msg != Message::Resign);
+                if tx.send((federate, msg)).is_err() || done {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        while !federation.all_resigned() {
+            let (federate, msg) = rx.recv().expect("All federates have hung up");
+            match msg {
+                Ok(Message::NextEventTag(tag)) => federation.next_event_tag(federate, tag),
+                Ok(Message::LogicalTagComplete(tag)) => federation.logical_tag_complete(federate, tag),
+                Ok(Message::Resign) => {
+                    info!("{} has resigned", federate);
+                    federation.resign(federate)
+                }
+                Ok(Message::TaggedMessage { channel, tag, payload }) => {
+                    // Messages are forwarded in order, so they reach the
+                    // destination before any grant that depends on them.
+                    let msg = Message::TaggedMessage { channel, tag, payload };
+                    if federation.has_resigned(channel.federate) {
+                        warn!(
+                            "Dropping message from {} to {}, which has resigned",
+                            federate, channel.federate
+                        );
+                    } else if let Err(e) = msg.write_to(&mut streams[channel.federate.index()]) {
+                        warn!("Could not forward message to {}: {}", channel.federate, e);
+                    }
+                }
+                Ok(msg) => warn!("Unexpected message from {}: {:?}", federate, msg),
+                Err(e) => {
+                    warn!("Lost connection to {}: {}", federate, e);
+                    federation.resign(federate)
+                }
+            }
+
+            for (federate, grant) in federation.compute_grants() {
+                trace!("Sending {:?} to {}", grant, federate);
+                if let Err(e) = grant.write_to(&mut streams[federate.index()]) {
+                    warn!("Could not send grant to {}: {}", federate, e);
+                }
+            }
+        }
+
+        info!("All federates have resigned");
+        Ok(())
+    }
+
+    /// Wait for all federates to connect and register.
+    /// The returned streams are indexed by federate ID.
+    /// Connections that do not register correctly are
+    /// closed, and the RTI keeps waiting.
+    fn accept_federates(&self, federation: &mut Federation) -> io::Result<Vec<TcpStream>> {
+        let mut streams: Vec<Option<TcpStream>> = (0..self.num_federates).map(|_| None).collect();
+        let mut remaining = self.num_federates;
+
+        while remaining > 0 {
+            let (mut stream, addr) = self.listener.accept()?;
+            match read_registration(&mut stream) {
+                Ok(Message::Register { federate, upstream })
+                    if federate.index() < self.num_federates
+                        && streams[federate.index()].is_none()
+                        && upstream.iter().all(|up| up.index() < self.num_federates) =>
+                {
+                    info!("{} registered from {}", federate, addr);
+                    federation.federates[federate.index()].upstream = upstream;
+                    streams[federate.index()] = Some(stream);
+                    remaining -= 1;
+                }
+                Ok(msg) => warn!("Rejecting invalid registration from {}: {:?}", addr, msg),
+                Err(e) => warn!("Rejecting connection from {}: {}", addr, e),
+            }
+        }
+
+        Ok(streams.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+/// Read the first message of a new connection, which should
+/// register a federate.
+fn read_registration(stream: &mut TcpStream) -> io::Result<Message> {
+    stream.set_nodelay(true)?;
+    // don't let a silent connection block the federation
+    stream.set_read_timeout(Some(REGISTRATION_TIMEOUT))?;
+    let msg = Message::read_from(stream)?;
+    stream.set_read_timeout(None)?;
+    Ok(msg)
+}
+
+/// What the RTI knows about a federate.
+#[derive(Default)]
+struct FederateInfo {
+    /// Federates that send messages to this one.
+    upstream: Vec<FederateId>,
+    /// Latest tag announced by the federate (NET).
+    next_event: Option<EventTag>,
+    /// Latest tag completed by the federate (LTC).
+    completed: Option<EventTag>,
+    /// Latest tag granted to the federate (TAG).
+    granted: Option<EventTag>,
+    /// Latest tag provisionally granted to the federate (PTAG).
+    provisionally_granted: Option<EventTag>,
+    resigned: bool,
+}
+
+/// Bookkeeping of the RTI, independent of networking.
+struct Federation {
+    federates: Vec<FederateInfo>,
+}
+
+impl Federation {
+    fn new(num_federates: usize) -> Self {
+        Self {
+            federates: (0..num_federates).map(|_| FederateInfo::default()).collect(),
+        }
+    }
+
+    fn next_event_tag(&mut self, federate: FederateId, tag: EventTag) {
+        self.federates[federate.index()].next_event = Some(tag);
+    }
+
+    fn logical_tag_complete(&mut self, federate: FederateId, tag: EventTag) {
+        self.federates[federate.index()].completed = Some(tag);
+    }
+
+    fn resign(&mut self, federate: FederateId) {
+        self.federates[federate.index()].resigned = true;
+    }
+
+    fn has_resigned(&self, federate: FederateId) -> bool {
+        self.federates[federate.index()].resigned
+    }
+
+    fn all_resigned(&self) -> bool {
+        self.federates.iter().all(|f| f.resigned)
+    }
+
+    /// Compute the grants that can be sent given the current
+    /// state of the federation, and record them as sent.
+    fn compute_grants(&mut self) -> Vec<(FederateId, Message)> {
+        let earliest = self.earliest_outputs(&[]);
+        let mut grants = Vec::new();
+
+        for f in 0..self.federates.len() {
+            let info = &self.federates[f];
+            let tag = match info.next_event {
+                Some(tag) if !info.resigned && info.granted.map_or(true, |granted| granted < tag) => tag,
+                _ => continue,
+            };
+
+            let eit = self.earliest_input(f, &earliest);
+            if eit > tag || eit == FOREVER {
+                self.federates[f].granted = Some(tag);
+                grants.push((FederateId(f as u16), Message::TagAdvanceGrant(tag)));
+            } else if info.provisionally_granted.map_or(true, |granted| granted < tag) && self.may_grant_provisionally(f, tag) {
+                self.federates[f].provisionally_granted = Some(tag);
+                grants.push((FederateId(f as u16), Message::ProvisionalTagAdvanceGrant(tag)));
+            }
+        }
+
+        grants
+    }
+
+    /// Earliest tag at which the given federate may send a message
+    /// on its own, that is, not in reaction to a network input.
+    fn own_next_output(&self, f: usize) -> EventTag {
+        let info = &self.federates[f];
+        if info.resigned {
+            return FOREVER;
+        }
+        match (info.next_event, info.completed) {
+            (Some(next), Some(completed)) if next > completed => next,
+            // the federate hasn't announced its next tag yet
+            (_, Some(completed)) => completed.next_microstep(),
+            (Some(next), None) => next,
+            (None, None) => EventTag::ORIGIN,
+        }
+    }
+
+    /// Earliest tag at which each federate may send a message.
+    /// Federates may react to their inputs at the same tag, so
+    /// this is propagated downstream. The own events of the
+    /// `excluded` federates are ignored.
+    fn earliest_outputs(&self, excluded: &[usize]) -> Vec<EventTag> {
+        let mut earliest: Vec<EventTag> = (0..self.federates.len())
+            .map(|f| {
+                if excluded.contains(&f) {
+                    FOREVER
+                } else {
+                    self.own_next_output(f)
+                }
+            })
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (f, info) in self.federates.iter().enumerate() {
+                if info.resigned {
+                    continue;
+                }
+                for up in &info.upstream {
+                    if earliest[up.index()] < earliest[f] {
+                        earliest[f] = earliest[up.index()];
+                        changed = true;
+                    }
+                }
+            }
+        }
+        earliest
+    }
+
+    /// Earliest tag at which the federate may receive a message (EIT).
+    fn earliest_input(&self, f: usize, earliest_outputs: &[EventTag]) -> EventTag {
+        self.federates[f]
+            .upstream
+            .iter()
+            .map(|up| earliest_outputs[up.index()])
+            .min()
+            .unwrap_or(FOREVER)
+    }
+
+    /// A federate that is part of a zero-delay cycle may be
+    /// granted a tag provisionally if no federate outside of
+    /// the cycle may send it a message at that tag, and no
+    /// member of the cycle has to process an earlier tag.
+    /// Otherwise the members of the cycle would wait for each
+    /// other forever.
+    fn may_grant_provisionally(&self, f: usize, tag: EventTag) -> bool {
+        let cycle = self.cycle_of(f);
+        if cycle.is_empty() {
+            return false;
+        }
+        let earliest = self.earliest_outputs(&cycle);
+        self.earliest_input(f, &earliest) > tag && cycle.iter().all(|&member| self.own_next_output(member) >= tag)
+    }
+
+    /// Returns the federates that are part of a cycle
+    /// with the given one (including itself), or an
+    /// empty vec if it's not in a cycle.
+    fn cycle_of(&self, f: usize) -> Vec<usize> {
+        let upstream_of_f = self.transitive_upstream(f);
+        if !upstream_of_f[f] {
+            return Vec::new();
+        }
+        (0..self.federates.len())
+            .filter(|&other| upstream_of_f[other] && self.transitive_upstream(other)[f])
+            .collect()
+    }
+
+    fn transitive_upstream(&self, f: usize) -> Vec<bool> {
+        let mut seen = vec![false; self.federates.len()];
+        let mut todo: Vec<usize> = self.federates[f].upstream.iter().map(|up| up.index()).collect();
+        while let Some(up) = todo.pop() {
+            if !seen[up] {
+                seen[up] = true;
+                todo.extend(self.federates[up].upstream.iter().map(|up| up.index()));
+            }
+        }
+        seen
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{delay, tag};
+
+    fn federation(upstreams: &[&[u16]]) -> Federation {
+        let mut federation = Federation::new(upstreams.len());
+        for (info, upstream) in federation.federates.iter_mut().zip(upstreams) {
+            info.upstream = upstream.iter().copied().map(FederateId).collect();
+        }
+        federation
+    }
+
+    #[test]
+    fn test_downstream_waits_for_upstream_completion() {
+        let (up, down) = (FederateId(0), FederateId(1));
+        let mut fed = federation(&[&[], &[0]]);
+        fed.next_event_tag(up, tag!(T0));
+        fed.next_event_tag(down, tag!(T0));
+
+        assert_eq!(fed.compute_grants(), vec![(up, Message::TagAdvanceGrant(tag!(T0)))]);
+
+        fed.logical_tag_complete(up, tag!(T0));
+        assert_eq!(fed.compute_grants(), vec![(down, Message::TagAdvanceGrant(tag!(T0)))]);
+
+        // down may advance up to the next event of up, excluded
+        fed.next_event_tag(up, tag!(T0 + 10 ms));
+        fed.logical_tag_complete(down, tag!(T0));
+        fed.next_event_tag(down, tag!(T0 + 5 ms));
+        assert_eq!(
+            fed.compute_grants(),
+            vec![
+                (up, Message::TagAdvanceGrant(tag!(T0 + 10 ms))),
+                (down, Message::TagAdvanceGrant(tag!(T0 + 5 ms))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resigned_upstream_grants_forever() {
+        let (up, down) = (FederateId(0), FederateId(1));
+        let mut fed = federation(&[&[], &[0]]);
+        fed.next_event_tag(down, FOREVER);
+        assert_eq!(fed.compute_grants(), vec![]);
+
+        fed.resign(up);
+        assert_eq!(fed.compute_grants(), vec![(down, Message::TagAdvanceGrant(FOREVER))]);
+    }
+
+    #[test]
+    fn test_zero_delay_cycle_gets_provisional_grants() {
+        let (a, b) = (FederateId(0), FederateId(1));
+        let mut fed = federation(&[&[1], &[0]]);
+        fed.next_event_tag(a, tag!(T0));
+        fed.next_event_tag(b, tag!(T0));
+
+        assert_eq!(
+            fed.compute_grants(),
+            vec![
+                (a, Message::ProvisionalTagAdvanceGrant(tag!(T0))),
+                (b, Message::ProvisionalTagAdvanceGrant(tag!(T0))),
+            ]
+        );
+        // not sent twice
+        assert_eq!(fed.compute_grants(), vec![]);
+    }
+
+    #[test]
+    fn test_cycle_waits_for_outside_upstream() {
+        let (a, b, c) = (FederateId(0), FederateId(1), FederateId(2));
+        // c -> a <-> b
+        let mut fed = federation(&[&[1, 2], &[0], &[]]);
+        fed.next_event_tag(a, tag!(T0));
+        fed.next_event_tag(b, tag!(T0));
+
+        assert_eq!(fed.compute_grants(), vec![]);
+
+        fed.next_event_tag(c, tag!(T0 + 1 sec));
+        assert_eq!(
+            fed.compute_grants(),
+            vec![
+                (a, Message::ProvisionalTagAdvanceGrant(tag!(T0))),
+                (b, Message::ProvisionalTagAdvanceGrant(tag!(T0))),
+                (c, Message::TagAdvanceGrant(tag!(T0 + 1 sec))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rti_over_localhost() {
+        let rti = Rti::bind("127.0.0.1:0", 1).unwrap();
+        let addr = rti.local_addr().unwrap();
+        let rti = std::thread::spawn(move || rti.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Message::Register { federate: FederateId(0), upstream: vec![] }
+            .write_to(&mut stream)
+            .unwrap();
+        assert_matches!(Message::read_from(&mut stream).unwrap(), Message::StartTime(_));
+
+        Message::NextEventTag(tag!(T0)).write_to(&mut stream).unwrap();
+        assert_eq!(Message::read_from(&mut stream).unwrap(), Message::TagAdvanceGrant(tag!(T0)));
+
+        Message::LogicalTagComplete(tag!(T0)).write_to(&mut stream).unwrap();
+        Message::Resign.write_to(&mut stream).unwrap();
+        rti.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_rti_rejects_invalid_registrations() {
+        let rti = Rti::bind("127.0.0.1:0", 1).unwrap();
+        let addr = rti.local_addr().unwrap();
+        let rti = std::thread::spawn(move || rti.run());
+
+        // not a message
+        let mut garbage = TcpStream::connect(addr).unwrap();
+        garbage.write_all(&[0]).unwrap();
+        // not a federate of this federation
+        let mut unknown = TcpStream::connect(addr).unwrap();
+        Message::Register { federate: FederateId(1), upstream: vec![] }
+            .write_to(&mut unknown)
+            .unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Message::Register { federate: FederateId(0), upstream: vec![] }
+            .write_to(&mut stream)
+            .unwrap();
+        assert_matches!(Message::read_from(&mut stream).unwrap(), Message::StartTime(_));
+        // rejected connections are closed
+        assert!(Message::read_from(&mut garbage).is_err());
+        assert!(Message::read_from(&mut unknown).is_err());
+
+        Message::Resign.write_to(&mut stream).unwrap();
+        rti.join().unwrap().unwrap();
+    }
+}
@@ -50,6 +50,9 @@
 //! This is a default feature.
 //! - `no-unsafe`: disable optimisations that use unsafe code in this runtime.
 //! Just provided for comparison, should probably be removed (unsafe code is fine).
+//!
+//! Programs may also be split into several processes, see the
+//! [federated] module.
 
 // #![deny(unused_crate_dependencies)]
 #![deny(unused_extern_crates)]
@@ -76,9 +79,14 @@ extern crate smallvec;
 extern crate static_assertions;
 #[macro_use]
 extern crate cfg_if;
+// the code generated by #[reactor] refers to this crate by name
+#[cfg(test)]
+extern crate self as reactor_rt;
 
 pub use std::time::{Duration, Instant};
 
+pub use reactor_rt_macros::reactor;
+
 pub(crate) use scheduler::debug::*;
 
 pub use self::actions::*;
@@ -89,6 +97,7 @@ pub use self::time::*;
 pub use self::timers::*;
 pub use self::triggers::ReactionTrigger;
 pub use self::util::*;
+pub use self::watchdogs::*;
 
 #[cfg(test)]
 pub mod test;
@@ -101,16 +110,18 @@ mod time;
 mod timers;
 mod triggers;
 mod util;
+mod watchdogs;
 
 pub mod assembly;
+pub mod federated;
 
 /// The prelude that is imported at the top of reactor files
 /// generated by LFC.
 pub mod prelude {
     pub use crate::Offset::*;
     pub use crate::{
-        after, assert_tag_is, delay, tag, AsyncCtx, Duration, EventTag, Instant, LogicalAction, Multiport, PhysicalActionRef,
-        Port, ReactionCtx, Timer,
+        after, assert_tag_is, delay, tag, AsyncCtx, Duration, EventTag, Instant, LogicalAction, Mode, ModeTransition, Multiport,
+        PhysicalActionRef, Port, ReactionCtx, Timer, Watchdog,
     };
 
     /// Alias for the unit type, so that it can be written without quotes in LF.
@@ -143,7 +154,19 @@ pub trait ReactorBehavior {
 
     /// Acknowledge that the given tag is done executing and
     /// free resources if need be.
-    fn cleanup_tag(&mut self, ctx: &CleanupCtx);
+    ///
+    /// The scheduler clears the ports set and the actions present
+    /// at a tag itself, so reactors don't need to implement this.
+    /// It is not called anymore, and only kept so that existing
+    /// implementations still compile.
+    fn cleanup_tag(&mut self, _ctx: &CleanupCtx) {}
+
+    /// Returns this reactor as a [Checkpointable], if its state
+    /// can be saved in a checkpoint. Programs can only be
+    /// checkpointed if all their reactors support it.
+    fn as_checkpointable(&mut self) -> Option<&mut dyn Checkpointable> {
+        None
+    }
 }
 assert_obj_safe!(ReactorBehavior);
 
@@ -25,19 +25,21 @@
 use std::borrow::Borrow;
 #[cfg(not(feature = "no-unsafe"))]
 use std::cell::UnsafeCell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::fmt::Debug;
 #[cfg(feature = "no-unsafe")]
 use std::ops::Deref;
 use std::ops::{DerefMut, Index, IndexMut};
 use std::rc::Rc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use atomic_refcell::AtomicRefCell;
 use AssemblyErrorImpl::{CannotBind, CyclicDependency};
 
 use crate::assembly::{AssemblyError, AssemblyErrorImpl, PortId, PortKind, TriggerId, TriggerLike};
-use crate::{EventTag, ReactionTrigger};
+use crate::util::vecmap::VecMap;
+use crate::{Clock, EventTag, ReactionTrigger};
 
 /// Represents a port, which carries values of type `T`.
 /// Ports reify the data inputs and outputs of a reactor.
@@ -225,6 +227,53 @@ impl<T: Sync> Port<T> {
         *mut_downstream_cell.deref_mut() = new_binding;
         Ok(())
     }
+
+    /// Create a connection with an after-delay from this port
+    /// to the downstream port. The downstream port is marked as
+    /// bound, so that it cannot be set by reactions or bound
+    /// to another upstream port. Unlike with [Self::forward_to],
+    /// both ports keep their own cell, so cycles are legal.
+    pub(crate) fn delay_to(&mut self, downstream: &mut Port<T>, delay: Duration) -> Result<DelayedConnection<T>, AssemblyError>
+    where
+        T: Clone,
+    {
+        if downstream.bind_status == BindStatus::Bound {
+            return Err(AssemblyError(CannotBind(self.id, downstream.id)));
+        }
+        downstream.bind_status = BindStatus::Bound;
+
+        Ok(DelayedConnection {
+            upstream: self.share(),
+            downstream: downstream.share(),
+            delay,
+            physical: false,
+            buffer: VecMap::new(),
+        })
+    }
+
+    /// Create a physical connection from this port to the
+    /// downstream port. This is like [Self::delay_to], but the
+    /// values are timestamped with physical time when they
+    /// are forwarded, plus the `delay`.
+    pub(crate) fn physical_to(&mut self, downstream: &mut Port<T>, delay: Duration) -> Result<DelayedConnection<T>, AssemblyError>
+    where
+        T: Clone,
+    {
+        let connection = self.delay_to(downstream, delay)?;
+        Ok(DelayedConnection { physical: true, ..connection })
+    }
+
+    /// Returns another handle to the binding of this port. The
+    /// handle follows the port if it is bound to an upstream
+    /// later on, and may be set even if this port is bound.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            id: self.id,
+            kind: self.kind,
+            bind_status: BindStatus::Free,
+            upstream_binding: Rc::clone(&self.upstream_binding),
+        }
+    }
 }
 
 impl<T: Sync> ReactionTrigger<T> for Port<T> {
@@ -260,6 +309,81 @@ impl<T: Sync> TriggerLike for Port<T> {
     }
 }
 
+/// A connection with an after-delay between two ports, see
+/// [DependencyDeclarator::bind_ports_delayed](crate::assembly::DependencyDeclarator::bind_ports_delayed),
+/// or a physical connection, see
+/// [DependencyDeclarator::bind_ports_physical](crate::assembly::DependencyDeclarator::bind_ports_physical).
+/// The values of the upstream port are buffered until the
+/// tag at which they are delivered to the downstream port.
+pub(crate) struct DelayedConnection<T: Sync> {
+    upstream: Port<T>,
+    downstream: Port<T>,
+    delay: Duration,
+    /// Whether values are timestamped with physical time.
+    physical: bool,
+    /// Values to deliver, sorted by tag. As in the map of
+    /// actions, the earliest tag is at the end.
+    buffer: VecMap<Reverse<EventTag>, T>,
+}
+
+/// Type-erased [DelayedConnection], which is driven by the scheduler.
+pub(crate) trait DelayedConnectionBehavior {
+    /// ID of the upstream port, whose value is forwarded
+    /// at the end of the tags where it is present.
+    fn upstream_id(&self) -> PortId;
+
+    /// ID of the downstream port, whose reactions must
+    /// be triggered when a value is delivered.
+    fn downstream_id(&self) -> PortId;
+
+    /// Whether this is a physical connection. The tags of the
+    /// values of physical connections are sent to the scheduler
+    /// like those of physical actions.
+    fn is_physical(&self) -> bool;
+
+    /// Set the downstream port, if a value is to be delivered
+    /// at this tag. Called before any reaction executes.
+    fn deliver(&mut self, tag: EventTag);
+
+    /// Called at the end of a tag where the upstream port may
+    /// be present. Buffers its value, if any, and returns the tag
+    /// at which the buffered value must be delivered. The `clock`
+    /// and `initial_time` are used to timestamp the values of
+    /// physical connections.
+    fn forward(&mut self, tag: EventTag, clock: &dyn Clock, initial_time: Instant) -> Option<EventTag>;
+}
+
+impl<T: Sync + Clone> DelayedConnectionBehavior for DelayedConnection<T> {
+    fn upstream_id(&self) -> PortId {
+        self.upstream.id
+    }
+
+    fn downstream_id(&self) -> PortId {
+        self.downstream.id
+    }
+
+    fn is_physical(&self) -> bool {
+        self.physical
+    }
+
+    fn deliver(&mut self, tag: EventTag) {
+        if let Some(value) = self.buffer.remove(&Reverse(tag)) {
+            self.downstream.set_impl(Some(value));
+        }
+    }
+
+    fn forward(&mut self, tag: EventTag, clock: &dyn Clock, initial_time: Instant) -> Option<EventTag> {
+        let value = self.upstream.use_ref(Option::<T>::clone)?;
+        let mut eta = tag.successor(self.delay);
+        if self.physical {
+            // physical time may lag behind logical time, eg in fast mode
+            eta = eta.max(EventTag::absolute(initial_time, clock.now() + self.delay));
+        }
+        self.buffer.insert(Reverse(eta), value);
+        Some(eta)
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum BindStatus {
     /// A bindable port is also writable explicitly (with set)
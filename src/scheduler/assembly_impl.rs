@@ -23,11 +23,15 @@
  */
 
 use std::borrow::Cow;
+use std::io;
 use std::marker::PhantomData;
 
 use index_vec::{Idx, IndexVec};
 
-use super::{ReactorBox, ReactorVec};
+use super::cleanup::{TagCleanup, TagCleanups};
+use super::federate::NetworkInputs;
+use super::replay::{RecordedAction, RecordedTriggers};
+use super::{DelayedConnectionVec, DelayedConnections, ReactorBox, ReactorVec};
 use crate::assembly::*;
 use crate::scheduler::dependencies::DepGraph;
 use crate::*;
@@ -40,20 +44,47 @@ pub(super) struct RootAssembler {
     pub(super) graph: DepGraph,
     /// Debug infos
     pub(super) debug_info: DebugInfoRegistry,
+    /// Network inputs, if this is a federate
+    network_inputs: NetworkInputs,
+    /// Connections with an after-delay
+    delayed_connections: DelayedConnectionVec,
+    /// Triggers of physical events that can be recorded and replayed
+    recorded_triggers: RecordedTriggers,
+    /// Ports and actions to clean up after the tags where they are present
+    tag_cleanups: TagCleanups,
+    /// All watchdogs, which are woken up when the scheduler shuts down
+    watchdogs: Vec<Watchdog>,
 
     /// Next reactor ID to assign
-    reactor_id: ReactorId,
+    pub(super) reactor_id: ReactorId,
     /// Next trigger ID to assign
-    cur_trigger: TriggerId,
+    pub(super) cur_trigger: TriggerId,
 }
 
+/// The components of an assembled program, which
+/// are handed over to the scheduler.
+pub(super) type AssembledTree = (
+    ReactorVec<'static>,
+    DepGraph,
+    DebugInfoRegistry,
+    NetworkInputs,
+    DelayedConnections,
+    RecordedTriggers,
+    TagCleanups,
+    Vec<Watchdog>,
+);
+
 impl RootAssembler {
     /// Register a reactor into the global data structure that owns them during execution.
     fn register_reactor<R: ReactorInitializer + 'static>(&mut self, child: R) {
-        if child.id().index() >= self.reactors.len() {
-            self.reactors.resize_with(child.id().index() + 1, || None)
+        self.register_boxed(child.id(), Box::new(child))
+    }
+
+    pub(super) fn register_boxed(&mut self, id: ReactorId, child: ReactorBox<'static>) {
+        if id.index() >= self.reactors.len() {
+            self.reactors.resize_with(id.index() + 1, || None)
         }
-        let prev = self.reactors[child.id()].replace(Box::new(child));
+        let prev = self.reactors[id].replace(child);
         // this is impossible because we control how we allocate IDs entirely
         debug_assert!(prev.is_none(), "Overwrote a reactor during initialization")
     }
@@ -66,23 +97,154 @@ impl RootAssembler {
     }
 
     /// Top level fun that assembles the main reactor
-    pub fn assemble_tree<R: ReactorInitializer + 'static>(
+    pub fn assemble_tree<R: ReactorInitializer + 'static>(main_args: R::Params) -> Result<AssembledTree, AssemblyFailure> {
+        Self::assemble_tree_with::<R>(main_args, |main| Box::new(main))
+    }
+
+    /// Like [Self::assemble_tree], but the main reactor is
+    /// boxed with the given function, eg to keep a handle on it.
+    pub(super) fn assemble_tree_with<R: ReactorInitializer + 'static>(
         main_args: R::Params,
-    ) -> (ReactorVec<'static>, DepGraph, DebugInfoRegistry) {
+        box_main: impl FnOnce(R) -> ReactorBox<'static>,
+    ) -> Result<AssembledTree, AssemblyFailure> {
         let mut root = RootAssembler::default();
         let assembler = AssemblyCtx::new(&mut root, ReactorDebugInfo::root::<R::Wrapped>());
 
         let main_reactor = match R::assemble(main_args, assembler) {
             Ok(main) => main.finish(),
-            Err(e) => std::panic::panic_any(e.lift(&root.debug_info)),
+            Err(e) => return Err(e.lift(&root.debug_info)),
         };
         root.debug_info.record_main_reactor(main_reactor.id());
-        root.register_reactor(main_reactor);
+        root.register_boxed(main_reactor.id(), box_main(main_reactor));
+        Ok(root.finish())
+    }
 
-        let RootAssembler { graph, reactors, debug_info: id_registry, .. } = root;
+    /// Hand over the assembled program to the scheduler.
+    pub(super) fn finish(self) -> AssembledTree {
+        let RootAssembler {
+            graph,
+            reactors,
+            debug_info: id_registry,
+            network_inputs,
+            delayed_connections,
+            recorded_triggers,
+            tag_cleanups,
+            watchdogs,
+            ..
+        } = self;
 
         let reactors = reactors.into_iter().map(|r| r.expect("Uninitialized reactor!")).collect();
-        (reactors, graph, id_registry)
+        let delayed_connections = DelayedConnections::new(delayed_connections, &graph);
+        (
+            reactors,
+            graph,
+            id_registry,
+            network_inputs,
+            delayed_connections,
+            recorded_triggers,
+            tag_cleanups,
+            watchdogs,
+        )
+    }
+
+    /// Create an assembler that adds reactors to a running
+    /// program, with the given graph and debug info of that
+    /// program, see [Mutator](super::mutations::Mutator).
+    pub(super) fn for_mutation(graph: DepGraph, debug_info: DebugInfoRegistry, num_reactors: usize) -> Self {
+        let cur_trigger = TriggerId::from_usize(debug_info.trigger_ids().count());
+        Self {
+            graph,
+            debug_info,
+            reactor_id: ReactorId::from_usize(num_reactors),
+            cur_trigger,
+            ..Default::default()
+        }
+    }
+
+    /// Assemble a reactor with the given debug info. It needs
+    /// to be registered using [Self::register_boxed] later.
+    pub(super) fn assemble_reactor<R: ReactorInitializer>(
+        &mut self,
+        args: R::Params,
+        debug: ReactorDebugInfo,
+    ) -> AssemblyResult<R> {
+        let ctx = AssemblyCtx::new(self, debug);
+        Ok(R::assemble(args, ctx)?.finish())
+    }
+
+    /// Check that the reactors added to a running program only
+    /// declare components that the scheduler can take over.
+    pub(super) fn check_mutation(&self) -> AssemblyResult<()> {
+        let unsupported = if !self.network_inputs.is_empty() {
+            "Network input"
+        } else if !self.delayed_connections.is_empty() {
+            "Delayed connection"
+        } else if !self.watchdogs.is_empty() {
+            "Watchdog"
+        } else if !self.recorded_triggers.is_empty() {
+            "Recorded physical action"
+        } else if self.graph.modes.has_modes() {
+            "Mode"
+        } else {
+            return Ok(());
+        };
+        Err(AssemblyError(AssemblyErrorImpl::UnsupportedMutation(unsupported)))
+    }
+
+    /// Hand over the reactors added to a running program to the
+    /// scheduler, with the new graph and debug info. The IDs of
+    /// the new reactors start at `first`.
+    pub(super) fn finish_mutation(
+        self,
+        first: ReactorId,
+    ) -> (Vec<ReactorBox<'static>>, DepGraph, DebugInfoRegistry, TagCleanups) {
+        let reactors = self
+            .reactors
+            .into_iter()
+            .skip(first.index())
+            .map(|r| r.expect("Uninitialized reactor!"))
+            .collect();
+        (reactors, self.graph, self.debug_info, self.tag_cleanups)
+    }
+
+    /// Create and return a new id for a trigger component.
+    pub(super) fn next_trigger_id(&mut self, debug_name: Cow<'static, str>) -> TriggerId {
+        let id = self.cur_trigger.get_and_incr().expect("Overflow while allocating ID");
+        self.debug_info.record_trigger(id, debug_name);
+        id
+    }
+
+    /// Register a handle to clean up the given component at
+    /// the end of each tag where it is present.
+    pub(super) fn record_cleanup(&mut self, id: TriggerId, handle: Box<dyn TagCleanup>) {
+        self.tag_cleanups.insert(id, handle);
+    }
+
+    /// Record the reactions of a reactor into the graph,
+    /// see [AssemblyCtx::new_reactions].
+    pub(super) fn record_reactions(
+        &mut self,
+        reactions: &[GlobalReactionId],
+        num_non_synthetic: usize,
+        names: &[Option<&'static str>],
+    ) {
+        assert!(num_non_synthetic <= reactions.len());
+
+        let mut prev: Option<GlobalReactionId> = None;
+        for (i, r) in reactions.iter().cloned().enumerate() {
+            if let Some(label) = names[i] {
+                self.debug_info.record_reaction(r, Cow::Borrowed(label))
+            }
+            self.graph.record_reaction(r);
+            if i < num_non_synthetic {
+                if let Some(prev) = prev {
+                    // Add an edge that represents that the
+                    // previous reaction takes precedence
+                    self.graph.reaction_priority(prev, r);
+                }
+            }
+            prev = Some(r);
+        }
     }
 }
 
@@ -94,6 +256,11 @@ impl Default for RootAssembler {
             debug_info: DebugInfoRegistry::new(),
             reactors: Default::default(),
             cur_trigger: TriggerId::FIRST_REGULAR,
+            network_inputs: Default::default(),
+            delayed_connections: Default::default(),
+            recorded_triggers: Default::default(),
+            tag_cleanups: Default::default(),
+            watchdogs: Default::default(),
         }
     }
 }
@@ -182,7 +349,7 @@ where
 
         let first_trigger_id = self.globals.cur_trigger;
 
-        let mut ich = create_self(&mut ComponentCreator { assembler: &mut self }, id)?;
+        let mut ich = create_self(&mut ComponentCreator { assembler: &mut self, id }, id)?;
         // after creation, globals.cur_trigger has been mutated
         // record proper debug info.
         self.globals
@@ -206,25 +373,8 @@ where
         num_non_synthetic: usize,
         names: [Option<&'static str>; N],
     ) -> [GlobalReactionId; N] {
-        assert!(num_non_synthetic <= N);
-
         let result = array![i => GlobalReactionId::new(my_id, LocalReactionId::from_usize(i)); N];
-
-        let mut prev: Option<GlobalReactionId> = None;
-        for (i, r) in result.iter().cloned().enumerate() {
-            if let Some(label) = names[i] {
-                self.globals.debug_info.record_reaction(r, Cow::Borrowed(label))
-            }
-            self.globals.graph.record_reaction(r);
-            if i < num_non_synthetic {
-                if let Some(prev) = prev {
-                    // Add an edge that represents that the
-                    // previous reaction takes precedence
-                    self.globals.graph.reaction_priority(prev, r);
-                }
-            }
-            prev = Some(r);
-        }
+        self.globals.record_reactions(&result, num_non_synthetic, &names);
 
         self.cur_local = self.cur_local.plus(N);
         result
@@ -352,6 +502,66 @@ impl<S: ReactorInitializer> DependencyDeclarator<'_, '_, S> {
         Ok(())
     }
 
+    /// Declare a deadline on a reaction. If, when the reaction
+    /// is about to execute, physical time lags behind the logical
+    /// time of the current tag by more than `deadline`, then the
+    /// `handler` reaction is executed instead of the reaction body.
+    ///
+    /// The handler must be another reaction of the same reactor.
+    /// It is typically synthesized by the code generator (it's
+    /// not one of the user-declared reactions).
+    pub fn declare_deadline(
+        &mut self,
+        reaction: GlobalReactionId,
+        deadline: Duration,
+        handler: GlobalReactionId,
+    ) -> AssemblyResult<()> {
+        if reaction == handler || reaction.0.container() != handler.0.container() {
+            return Err(AssemblyError(AssemblyErrorImpl::InvalidDeadlineHandler(reaction, handler)));
+        }
+        self.graph().reaction_deadline(reaction, deadline, handler.0.local());
+        Ok(())
+    }
+
+    /// Declare that a reaction belongs to a mode of its reactor.
+    /// It is only executed while the reactor is in that mode.
+    pub fn declare_mode_reaction(&mut self, reaction: GlobalReactionId, mode: Mode) -> AssemblyResult<()> {
+        if reaction.0.container() != mode.reactor() {
+            return Err(AssemblyError(AssemblyErrorImpl::ForeignModeReaction(reaction, mode)));
+        }
+        self.graph().modes.reaction_mode(reaction, mode);
+        Ok(())
+    }
+
+    /// Declare that a timer belongs to a mode. The timer is
+    /// suspended while the mode is inactive, and restarted or
+    /// resumed when the mode is entered, see [ModeTransition].
+    /// Timers of child reactors must be declared in the innermost
+    /// mode that contains them to be suspended. The timer must
+    /// belong to the reactor of the mode, or to one of its
+    /// descendants.
+    pub fn declare_mode_timer(&mut self, timer: &mut Timer, mode: Mode) -> AssemblyResult<()> {
+        let debug = &self.assembler.globals.debug_info;
+        let container = debug.get_trigger_container(timer.get_id());
+        if !container.map_or(false, |r| debug.is_within(r, mode.reactor())) {
+            return Err(AssemblyError(AssemblyErrorImpl::ForeignModeTimer(timer.get_id(), mode)));
+        }
+        self.graph().modes.timer_mode(timer, mode);
+        Ok(())
+    }
+
+    /// Declare that a child reactor belongs to a mode. None of
+    /// its reactions, nor those of its descendants, execute
+    /// while the mode is inactive. The child must be a direct
+    /// child of the reactor of the mode.
+    pub fn declare_mode_child(&mut self, child: ReactorId, mode: Mode) -> AssemblyResult<()> {
+        if !self.assembler.globals.debug_info.is_child_of(child, mode.reactor()) {
+            return Err(AssemblyError(AssemblyErrorImpl::ForeignModeChild(child, mode)));
+        }
+        self.graph().modes.child_mode(child, mode);
+        Ok(())
+    }
+
     /// Bind two ports together.
     #[inline]
     pub fn bind_ports<T: Sync>(&mut self, upstream: &mut Port<T>, downstream: &mut Port<T>) -> AssemblyResult<()> {
@@ -360,6 +570,42 @@ impl<S: ReactorInitializer> DependencyDeclarator<'_, '_, S> {
         Ok(())
     }
 
+    /// Connect two ports with an after-delay. The values set
+    /// on the upstream port at some tag are buffered, and the
+    /// downstream port is set at that tag plus the `delay`
+    /// (or at the next microstep if the delay is zero).
+    ///
+    /// There is no instantaneous dependency between both ports,
+    /// so cycles that go through a delayed connection are legal.
+    pub fn bind_ports_delayed<T: Sync + Clone + 'static>(
+        &mut self,
+        upstream: &mut Port<T>,
+        downstream: &mut Port<T>,
+        delay: Duration,
+    ) -> AssemblyResult<()> {
+        let connection = upstream.delay_to(downstream, delay)?;
+        self.assembler.globals.delayed_connections.push(Box::new(connection));
+        Ok(())
+    }
+
+    /// Connect two ports with a physical connection. The values
+    /// set on the upstream port are delivered to the downstream
+    /// port at a new tag, which is derived from the physical time
+    /// at which they're forwarded, plus the `delay`. This decouples
+    /// the logical timelines of both ends of the connection.
+    ///
+    /// There is no instantaneous dependency between both ports.
+    pub fn bind_ports_physical<T: Sync + Clone + 'static>(
+        &mut self,
+        upstream: &mut Port<T>,
+        downstream: &mut Port<T>,
+        delay: Duration,
+    ) -> AssemblyResult<()> {
+        let connection = upstream.physical_to(downstream, delay)?;
+        self.assembler.globals.delayed_connections.push(Box::new(connection));
+        Ok(())
+    }
+
     /// Bind the ports of the upstream to those of the downstream,
     /// as if zipping both iterators.
     /// todo this will just throw away bindings if both iterators are not of the same size
@@ -407,20 +653,22 @@ impl<S: ReactorInitializer> DependencyDeclarator<'_, '_, S> {
 /// Creates the components of a reactor.
 pub struct ComponentCreator<'a, 'x, S: ReactorInitializer> {
     assembler: &'a mut AssemblyCtx<'x, S>,
+    /// ID of the reactor being created.
+    id: ReactorId,
 }
 
 impl<S: ReactorInitializer> ComponentCreator<'_, '_, S> {
-    pub fn new_port<T: Sync>(&mut self, lf_name: &'static str, kind: PortKind) -> Port<T> {
+    pub fn new_port<T: Sync + 'static>(&mut self, lf_name: &'static str, kind: PortKind) -> Port<T> {
         self.new_port_impl(Cow::Borrowed(lf_name), kind)
     }
 
-    fn new_port_impl<T: Sync>(&mut self, lf_name: Cow<'static, str>, kind: PortKind) -> Port<T> {
+    fn new_port_impl<T: Sync + 'static>(&mut self, lf_name: Cow<'static, str>, kind: PortKind) -> Port<T> {
         let id = self.next_comp_id(lf_name);
         self.graph().record_port(id);
-        Port::new(id, kind)
+        self.new_port_handle(Port::new(id, kind))
     }
 
-    pub fn new_multiport<T: Sync>(
+    pub fn new_multiport<T: Sync + 'static>(
         &mut self,
         lf_name: &'static str,
         kind: PortKind,
@@ -436,7 +684,7 @@ impl<S: ReactorInitializer> ComponentCreator<'_, '_, S> {
         ))
     }
 
-    fn new_port_bank_component<T: Sync>(
+    fn new_port_bank_component<T: Sync + 'static>(
         &mut self,
         lf_name: &'static str,
         kind: PortKind,
@@ -445,19 +693,103 @@ impl<S: ReactorInitializer> ComponentCreator<'_, '_, S> {
     ) -> Port<T> {
         let channel_id = self.next_comp_id(Cow::Owned(format!("{}[{}]", lf_name, index)));
         self.graph().record_port_bank_component(bank_id, channel_id);
-        Port::new(channel_id, kind)
+        self.new_port_handle(Port::new(channel_id, kind))
+    }
+
+    /// Register the port to be cleared at the end of the tags
+    /// where it is set.
+    fn new_port_handle<T: Sync + 'static>(&mut self, port: Port<T>) -> Port<T> {
+        let handle = Box::new(port.share());
+        self.assembler.globals.record_cleanup(port.get_id(), handle);
+        port
+    }
+
+    pub fn new_logical_action<T: Sync + 'static>(
+        &mut self,
+        lf_name: &'static str,
+        min_delay: Option<Duration>,
+        min_spacing: Option<Duration>,
+        policy: SpacingPolicy,
+    ) -> LogicalAction<T> {
+        let id = self.next_comp_id(Cow::Borrowed(lf_name));
+        self.graph().record_laction(id);
+        self.new_action_handle(LogicalAction::new(id, min_delay, min_spacing, policy))
     }
 
-    pub fn new_logical_action<T: Sync>(&mut self, lf_name: &'static str, min_delay: Option<Duration>) -> LogicalAction<T> {
+    /// Create a logical action that never loses values: if several
+    /// values are scheduled for the same tag, later ones are delivered
+    /// at successive microsteps.
+    pub fn new_queued_logical_action<T: Sync + 'static>(
+        &mut self,
+        lf_name: &'static str,
+        min_delay: Option<Duration>,
+    ) -> LogicalAction<T> {
         let id = self.next_comp_id(Cow::Borrowed(lf_name));
         self.graph().record_laction(id);
-        LogicalAction::new(id, min_delay)
+        self.new_action_handle(LogicalAction::new_queued(id, min_delay))
+    }
+
+    /// Register the action to drop its value at the end of
+    /// the tags where it is present.
+    fn new_action_handle<T: Sync + 'static>(&mut self, action: LogicalAction<T>) -> LogicalAction<T> {
+        let handle = Box::new(action.share());
+        self.assembler.globals.record_cleanup(action.get_id(), handle);
+        action
     }
 
-    pub fn new_physical_action<T: Sync>(&mut self, lf_name: &'static str, min_delay: Option<Duration>) -> PhysicalActionRef<T> {
+    pub fn new_physical_action<T: Sync + 'static>(
+        &mut self,
+        lf_name: &'static str,
+        min_delay: Option<Duration>,
+        min_spacing: Option<Duration>,
+        policy: SpacingPolicy,
+    ) -> PhysicalActionRef<T> {
         let id = self.next_comp_id(Cow::Borrowed(lf_name));
         self.graph().record_paction(id);
-        PhysicalActionRef::new(id, min_delay)
+        let action = PhysicalActionRef::new(id, min_delay, min_spacing, policy);
+        self.assembler.globals.record_cleanup(id, Box::new(action.clone()));
+        action
+    }
+
+    /// Record the values of the given physical action when the
+    /// program runs with [SchedulerOptions::record], so that they
+    /// can be replayed with [SchedulerOptions::replay]. Physical
+    /// actions that are not registered are replayed without values.
+    pub fn record_physical_action<T: Sync + 'static>(
+        &mut self,
+        action: &PhysicalActionRef<T>,
+        serialize: fn(&T) -> Vec<u8>,
+        deserialize: fn(Vec<u8>) -> io::Result<T>,
+    ) {
+        let recorded = RecordedAction { action: action.clone(), serialize, deserialize };
+        self.assembler
+            .globals
+            .recorded_triggers
+            .insert(action.get_id(), Box::new(recorded));
+    }
+
+    /// Create the receiving end of a connection from another
+    /// federate. The action is triggered at the tag of each
+    /// received message, with the serialized value. See [crate::federated].
+    ///
+    /// Port numbers must be unique within the program.
+    pub fn new_network_input(&mut self, lf_name: &'static str, port: u32) -> AssemblyResult<PhysicalActionRef<Vec<u8>>> {
+        if self.assembler.globals.network_inputs.contains_key(&port) {
+            return Err(AssemblyError(AssemblyErrorImpl::DuplicateNetworkInput(port)));
+        }
+        let action = self.new_physical_action(lf_name, None, None, SpacingPolicy::Defer);
+        self.assembler.globals.network_inputs.insert(port, action.clone());
+        Ok(action)
+    }
+
+    /// Create a mode of this reactor. Exactly one mode
+    /// of a modal reactor must be the initial mode.
+    pub fn new_mode(&mut self, lf_name: &'static str, initial: bool) -> Mode {
+        let id = self.next_comp_id(Cow::Borrowed(lf_name));
+        let mode = Mode::new(id, self.id);
+        self.graph().record_mode(id);
+        self.graph().modes.record_mode(mode, initial);
+        mode
     }
 
     pub fn new_timer(&mut self, lf_name: &'static str, offset: Duration, period: Duration) -> Timer {
@@ -466,16 +798,19 @@ impl<S: ReactorInitializer> ComponentCreator<'_, '_, S> {
         Timer::new(id, offset, period)
     }
 
+    pub fn new_watchdog(&mut self, lf_name: &'static str, timeout: Duration) -> Watchdog {
+        let id = self.next_comp_id(Cow::Borrowed(lf_name));
+        self.graph().record_watchdog(id);
+        let watchdog = Watchdog::new(id, timeout);
+        let globals = &mut self.assembler.globals;
+        globals.recorded_triggers.insert(id, Box::new(watchdog.clone()));
+        globals.watchdogs.push(watchdog.clone());
+        watchdog
+    }
+
     /// Create and return a new id for a trigger component.
     fn next_comp_id(&mut self, debug_name: Cow<'static, str>) -> TriggerId {
-        let id = self
-            .assembler
-            .globals
-            .cur_trigger
-            .get_and_incr()
-            .expect("Overflow while allocating ID");
-        self.assembler.globals.debug_info.record_trigger(id, debug_name);
-        id
+        self.assembler.globals.next_trigger_id(debug_name)
     }
 
     #[inline]
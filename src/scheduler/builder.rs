@@ -0,0 +1,584 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Assembly of reactor programs from closures, see [ProgramBuilder].
+
+use std::any::{type_name, Any, TypeId};
+use std::borrow::Cow;
+
+use index_vec::Idx;
+
+use super::assembly_impl::{AssembledTree, RootAssembler};
+use super::cleanup::TagCleanup;
+use super::debug::ReactorDebugInfo;
+use super::dependencies::DepGraph;
+use crate::assembly::{AssemblyError, AssemblyFailure, PortKind, TriggerId};
+use crate::*;
+
+/// Builds a reactor program from closures, without a code
+/// generator. This is meant for small programs and tests, as
+/// reactors are looked up dynamically.
+///
+/// Each reactor is a child of an implicit main reactor. Its
+/// components and reactions are declared in order, and are
+/// referred to by name. Reactions may be triggered by the
+/// special triggers `startup` and `shutdown`. Reaction bodies
+/// access the components of their reactor and its state through
+/// a [ReactorState].
+///
+/// ```no_run
+/// # use reactor_rt::prelude::*;
+/// # use reactor_rt::{ProgramBuilder, SchedulerOptions};
+/// let mut program = ProgramBuilder::new();
+/// program
+///     .reactor("source")
+///     .output::<u32>("out")
+///     .reaction(&["startup"], &["out"], |ctx, this| {
+///         ctx.set(this.port_mut::<u32>("out"), 42);
+///     });
+/// program
+///     .reactor("sink")
+///     .input::<u32>("in")
+///     .reaction(&["in"], &[], |ctx, this| {
+///         println!("Received {:?}", ctx.get(this.port::<u32>("in")));
+///     });
+/// program.connect("source.out", "sink.in");
+/// program.run(SchedulerOptions::default()).unwrap();
+/// ```
+#[derive(Default)]
+pub struct ProgramBuilder {
+    reactors: Vec<ReactorSpec>,
+    /// Port bindings, from upstream to downstream.
+    connections: Vec<(ComponentRef, ComponentRef)>,
+    /// Errors of [Self::connect], the first one is returned
+    /// when the program is assembled.
+    errors: Vec<AssemblyFailure>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a reactor with the given name to the program, and
+    /// declare its components and reactions.
+    ///
+    /// Panics if there is already a reactor with this name.
+    pub fn reactor(&mut self, name: &'static str) -> ReactorBuilder<'_> {
+        assert!(self.reactors.iter().all(|r| r.name != name), "Duplicate reactor {}", name);
+        self.reactors.push(ReactorSpec {
+            name,
+            state: Box::new(()),
+            components: Vec::new(),
+            reactions: Vec::new(),
+        });
+        let spec = self.reactors.last_mut().unwrap();
+        ReactorBuilder { spec }
+    }
+
+    /// Bind two ports, given by their path `reactor.port`.
+    ///
+    /// If a port does not exist, or if the ports have different
+    /// types, the error is returned by [Self::run].
+    pub fn connect(&mut self, upstream: &str, downstream: &str) {
+        match self.resolve_connection(upstream, downstream) {
+            Ok(connection) => self.connections.push(connection),
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    fn resolve_connection(&self, upstream: &str, downstream: &str) -> Result<(ComponentRef, ComponentRef), AssemblyFailure> {
+        let up = self.resolve_port(upstream)?;
+        let down = self.resolve_port(downstream)?;
+        if up == down {
+            return Err(AssemblyFailure::CyclicDependency {
+                upstream: upstream.to_owned(),
+                downstream: downstream.to_owned(),
+            });
+        }
+        let up_spec = &self.reactors[up.0].components[up.1];
+        let down_spec = &self.reactors[down.0].components[down.1];
+        if up_spec.value_type != down_spec.value_type {
+            return Err(AssemblyFailure::PortTypeMismatch {
+                upstream: format!("{} ({})", upstream, up_spec.type_name),
+                downstream: format!("{} ({})", downstream, down_spec.type_name),
+            });
+        }
+        Ok((up, down))
+    }
+
+    fn resolve_port(&self, path: &str) -> Result<ComponentRef, AssemblyFailure> {
+        let unknown = || AssemblyFailure::UnknownPort { path: path.to_owned() };
+        let (reactor, port) = path.split_once('.').ok_or_else(unknown)?;
+        let r = self.reactors.iter().position(|r| r.name == reactor).ok_or_else(unknown)?;
+        match self.reactors[r].resolve(port) {
+            Some(c) if matches!(self.reactors[r].components[c].kind, ComponentKind::Port(_)) => Ok((r, c)),
+            _ => Err(unknown()),
+        }
+    }
+
+    /// Assemble the program and run it in this thread, until it
+    /// shuts down. See [SyncScheduler::try_run_main].
+    pub fn run(self, options: SchedulerOptions) -> Result<RunSummary, RuntimeError> {
+        SyncScheduler::run_assembled(options, || self.assemble(), None, |_| {})
+    }
+
+    /// Assemble the program, as [RootAssembler::assemble_tree]
+    /// does for programs generated by LFC.
+    pub(super) fn assemble(self) -> Result<AssembledTree, AssemblyFailure> {
+        if let Some(e) = self.errors.into_iter().next() {
+            return Err(e);
+        }
+        let mut root = RootAssembler::default();
+        let main_debug = ReactorDebugInfo::root::<ProgramBuilder>();
+
+        // children are assigned their IDs before the main reactor
+        let mut reactors = Vec::with_capacity(self.reactors.len());
+        for spec in self.reactors {
+            reactors.push(spec.assemble(&mut root, &main_debug));
+        }
+
+        for ((up_r, up_c), (down_r, down_c)) in self.connections {
+            let bind = reactors[up_r].state.components[up_c].bind;
+            let (up, down) = if up_r == down_r {
+                pick_two(&mut reactors[up_r].state.components, up_c, down_c)
+            } else {
+                let (up, down) = pick_two(&mut reactors, up_r, down_r);
+                (&mut up.state.components[up_c], &mut down.state.components[down_c])
+            };
+            if let Err(e) = bind(up.value.as_mut(), down.value.as_mut(), &mut root.graph) {
+                return Err(e.lift(&root.debug_info));
+            }
+        }
+
+        let main_id = root.reactor_id.get_and_incr();
+        root.debug_info.record_reactor(main_id, main_debug);
+        root.debug_info.set_id_range(main_id, root.cur_trigger..root.cur_trigger);
+        root.debug_info.record_main_reactor(main_id);
+        for reactor in reactors {
+            root.debug_info.record_reactor_container(main_id, reactor.id);
+            root.register_boxed(reactor.id, Box::new(reactor));
+        }
+        let main = BuiltReactor {
+            id: main_id,
+            reactions: Vec::new(),
+            state: ReactorState {
+                name: "/",
+                components: Vec::new(),
+                state: Box::new(()),
+            },
+        };
+        root.register_boxed(main_id, Box::new(main));
+        Ok(root.finish())
+    }
+}
+
+/// Mutable references to two distinct items of a slice.
+fn pick_two<T>(items: &mut [T], i: usize, j: usize) -> (&mut T, &mut T) {
+    debug_assert_ne!(i, j);
+    if i < j {
+        let (left, right) = items.split_at_mut(j);
+        (&mut left[i], &mut right[0])
+    } else {
+        let (left, right) = items.split_at_mut(i);
+        (&mut right[0], &mut left[j])
+    }
+}
+
+/// Declares the components and reactions of a reactor,
+/// see [ProgramBuilder::reactor].
+pub struct ReactorBuilder<'a> {
+    spec: &'a mut ReactorSpec,
+}
+
+impl ReactorBuilder<'_> {
+    /// Set the state of the reactor, which reactions access
+    /// with [ReactorState::state]. By default this is `()`.
+    pub fn state<S: 'static>(self, state: S) -> Self {
+        self.spec.state = Box::new(state);
+        self
+    }
+
+    pub fn input<T: Sync + 'static>(self, name: &'static str) -> Self {
+        self.component::<T>(name, ComponentKind::Port(PortKind::Input))
+    }
+
+    pub fn output<T: Sync + 'static>(self, name: &'static str) -> Self {
+        self.component::<T>(name, ComponentKind::Port(PortKind::Output))
+    }
+
+    pub fn logical_action<T: Sync + 'static>(self, name: &'static str, min_delay: Option<Duration>) -> Self {
+        self.component::<T>(name, ComponentKind::LogicalAction(min_delay))
+    }
+
+    pub fn timer(self, name: &'static str, offset: Duration, period: Duration) -> Self {
+        self.component::<()>(name, ComponentKind::Timer(offset, period))
+    }
+
+    fn component<T: Sync + 'static>(self, name: &'static str, kind: ComponentKind) -> Self {
+        assert!(
+            name != "startup" && name != "shutdown" && self.spec.resolve(name).is_none(),
+            "Duplicate component {} in reactor {}",
+            name,
+            self.spec.name
+        );
+        self.spec.components.push(ComponentSpec {
+            name,
+            kind,
+            value_type: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            create: create_component::<T>,
+            bind: bind_ports::<T>,
+            cleanup_handle: cleanup_handle::<T>,
+        });
+        self
+    }
+
+    /// Add a reaction to the reactor. Reactions are executed in
+    /// the order they are declared in. The triggers and effects
+    /// are names of components declared before.
+    ///
+    /// Panics if a trigger or an effect does not exist, or if
+    /// a timer is declared as an effect.
+    pub fn reaction(
+        self,
+        triggers: &[&str],
+        effects: &[&str],
+        body: impl FnMut(&mut ReactionCtx, &mut ReactorState) + 'static,
+    ) -> Self {
+        let triggers = triggers
+            .iter()
+            .map(|&name| match name {
+                "startup" => TriggerRef::Startup,
+                "shutdown" => TriggerRef::Shutdown,
+                _ => TriggerRef::Component(self.resolve_or_panic(name)),
+            })
+            .collect();
+        let effects = effects
+            .iter()
+            .map(|&name| {
+                let c = self.resolve_or_panic(name);
+                assert!(
+                    !matches!(self.spec.components[c].kind, ComponentKind::Timer(..)),
+                    "Timer {} cannot be the effect of a reaction",
+                    name
+                );
+                c
+            })
+            .collect();
+        self.spec
+            .reactions
+            .push(ReactionSpec { triggers, effects, body: Box::new(body) });
+        self
+    }
+
+    fn resolve_or_panic(&self, name: &str) -> usize {
+        self.spec
+            .resolve(name)
+            .unwrap_or_else(|| panic!("Reactor {} has no component {}", self.spec.name, name))
+    }
+}
+
+/// Index of a reactor in the builder, and of a
+/// component in that reactor.
+type ComponentRef = (usize, usize);
+
+struct ReactorSpec {
+    name: &'static str,
+    state: Box<dyn Any>,
+    components: Vec<ComponentSpec>,
+    reactions: Vec<ReactionSpec>,
+}
+
+impl ReactorSpec {
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.components.iter().position(|c| c.name == name)
+    }
+
+    /// Create the components and reactions of this reactor,
+    /// and record them into the graph.
+    fn assemble(self, root: &mut RootAssembler, main_debug: &ReactorDebugInfo) -> BuiltReactor {
+        let id = root.reactor_id.get_and_incr();
+        let debug = main_debug.derive_untyped(type_name::<BuiltReactor>(), self.name);
+        root.debug_info.record_reactor(id, debug);
+
+        let first_trigger_id = root.cur_trigger;
+        let components: Vec<Component> = self
+            .components
+            .iter()
+            .map(|spec| {
+                let trigger_id = root.next_trigger_id(Cow::Borrowed(spec.name));
+                match spec.kind {
+                    ComponentKind::Port(_) => root.graph.record_port(trigger_id),
+                    ComponentKind::LogicalAction(_) => root.graph.record_laction(trigger_id),
+                    ComponentKind::Timer(..) => root.graph.record_timer(trigger_id),
+                }
+                let value = (spec.create)(trigger_id, spec.kind);
+                if let Some(handle) = (spec.cleanup_handle)(value.as_ref()) {
+                    root.record_cleanup(trigger_id, handle);
+                }
+                Component {
+                    name: spec.name,
+                    trigger_id,
+                    value,
+                    bind: spec.bind,
+                }
+            })
+            .collect();
+        root.debug_info.set_id_range(id, first_trigger_id..root.cur_trigger);
+
+        // each timer has two synthetic reactions, which
+        // come after those declared by the user
+        let timers: Vec<usize> = (0..components.len())
+            .filter(|&c| matches!(self.components[c].kind, ComponentKind::Timer(..)))
+            .collect();
+        let num_reactions = self.reactions.len() + 2 * timers.len();
+        let reaction_ids: Vec<GlobalReactionId> = (0..num_reactions)
+            .map(|i| GlobalReactionId::new(id, LocalReactionId::from_usize(i)))
+            .collect();
+        root.record_reactions(&reaction_ids, self.reactions.len(), &vec![None; num_reactions]);
+
+        let mut reactions = Vec::with_capacity(num_reactions);
+        let mut reaction_ids = reaction_ids.into_iter();
+        for spec in self.reactions {
+            let rid = reaction_ids.next().unwrap();
+            for trigger in spec.triggers {
+                let trigger_id = match trigger {
+                    TriggerRef::Startup => TriggerId::STARTUP,
+                    TriggerRef::Shutdown => TriggerId::SHUTDOWN,
+                    TriggerRef::Component(c) => components[c].trigger_id,
+                };
+                root.graph.triggers_reaction(trigger_id, rid);
+            }
+            for effect in spec.effects {
+                // actions are scheduled dynamically, only
+                // ports are instantaneous effects
+                if let ComponentKind::Port(_) = self.components[effect].kind {
+                    root.graph.reaction_effects(rid, components[effect].trigger_id);
+                }
+            }
+            reactions.push(Reaction::User(spec.body));
+        }
+        for timer in timers {
+            let timer_id = components[timer].trigger_id;
+            let start = reaction_ids.next().unwrap();
+            root.graph.triggers_reaction(TriggerId::STARTUP, start);
+            root.graph.reaction_effects(start, timer_id);
+            reactions.push(Reaction::StartTimer(timer));
+
+            let reschedule = reaction_ids.next().unwrap();
+            root.graph.triggers_reaction(timer_id, reschedule);
+            reactions.push(Reaction::RescheduleTimer(timer));
+        }
+
+        BuiltReactor {
+            id,
+            reactions,
+            state: ReactorState { name: self.name, components, state: self.state },
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ComponentKind {
+    Port(PortKind),
+    /// A logical action with its minimum delay.
+    LogicalAction(Option<Duration>),
+    /// A timer with its offset and period.
+    Timer(Duration, Duration),
+}
+
+struct ComponentSpec {
+    name: &'static str,
+    kind: ComponentKind,
+    /// Type of the values of the component.
+    value_type: TypeId,
+    type_name: &'static str,
+    create: fn(TriggerId, ComponentKind) -> Box<dyn Any>,
+    bind: BindFn,
+    cleanup_handle: fn(&dyn Any) -> Option<Box<dyn TagCleanup>>,
+}
+
+type BindFn = fn(&mut dyn Any, &mut dyn Any, &mut DepGraph) -> Result<(), AssemblyError>;
+
+fn create_component<T: Sync + 'static>(id: TriggerId, kind: ComponentKind) -> Box<dyn Any> {
+    match kind {
+        ComponentKind::Port(kind) => Box::new(Port::<T>::new(id, kind)),
+        ComponentKind::LogicalAction(min_delay) => Box::new(LogicalAction::<T>::new(id, min_delay, None, SpacingPolicy::Defer)),
+        ComponentKind::Timer(offset, period) => Box::new(Timer::new(id, offset, period)),
+    }
+}
+
+fn bind_ports<T: Sync + 'static>(
+    upstream: &mut dyn Any,
+    downstream: &mut dyn Any,
+    graph: &mut DepGraph,
+) -> Result<(), AssemblyError> {
+    // the types were checked by ProgramBuilder::connect
+    let upstream = upstream.downcast_mut::<Port<T>>().unwrap();
+    let downstream = downstream.downcast_mut::<Port<T>>().unwrap();
+    upstream.forward_to(downstream)?;
+    graph.port_bind(upstream, downstream);
+    Ok(())
+}
+
+/// Ports and logical actions are cleaned up by the scheduler
+/// after the tags where they are present.
+fn cleanup_handle<T: Sync + 'static>(value: &dyn Any) -> Option<Box<dyn TagCleanup>> {
+    if let Some(port) = value.downcast_ref::<Port<T>>() {
+        return Some(Box::new(port.share()));
+    }
+    let action = value.downcast_ref::<LogicalAction<T>>()?;
+    Some(Box::new(action.share()))
+}
+
+enum TriggerRef {
+    Startup,
+    Shutdown,
+    /// Index of a component of the reactor.
+    Component(usize),
+}
+
+struct ReactionSpec {
+    triggers: Vec<TriggerRef>,
+    /// Indices of components of the reactor.
+    effects: Vec<usize>,
+    body: ReactionBody,
+}
+
+type ReactionBody = Box<dyn FnMut(&mut ReactionCtx, &mut ReactorState)>;
+
+/// The components and state of a reactor built with a
+/// [ProgramBuilder], which its reactions have access to.
+/// Components are looked up by name and type.
+pub struct ReactorState {
+    name: &'static str,
+    components: Vec<Component>,
+    state: Box<dyn Any>,
+}
+
+impl ReactorState {
+    /// Panics if there is no port with this name and type.
+    pub fn port<T: Sync + 'static>(&self, name: &str) -> &Port<T> {
+        self.component(name)
+    }
+
+    /// Panics if there is no port with this name and type.
+    pub fn port_mut<T: Sync + 'static>(&mut self, name: &str) -> &mut Port<T> {
+        self.component_mut(name)
+    }
+
+    /// Panics if there is no action with this name and type.
+    pub fn action<T: Sync + 'static>(&self, name: &str) -> &LogicalAction<T> {
+        self.component(name)
+    }
+
+    /// Panics if there is no action with this name and type.
+    pub fn action_mut<T: Sync + 'static>(&mut self, name: &str) -> &mut LogicalAction<T> {
+        self.component_mut(name)
+    }
+
+    /// Panics if there is no timer with this name.
+    pub fn timer(&self, name: &str) -> &Timer {
+        self.component(name)
+    }
+
+    /// The state of the reactor, see [ReactorBuilder::state].
+    ///
+    /// Panics if the state does not have this type.
+    pub fn state<S: 'static>(&self) -> &S {
+        self.state
+            .downcast_ref()
+            .unwrap_or_else(|| panic!("State of reactor {} is not of type {}", self.name, type_name::<S>()))
+    }
+
+    /// The state of the reactor, see [ReactorBuilder::state].
+    ///
+    /// Panics if the state does not have this type.
+    pub fn state_mut<S: 'static>(&mut self) -> &mut S {
+        let name = self.name;
+        self.state
+            .downcast_mut()
+            .unwrap_or_else(|| panic!("State of reactor {} is not of type {}", name, type_name::<S>()))
+    }
+
+    fn component<C: 'static>(&self, name: &str) -> &C {
+        self.components
+            .iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.value.downcast_ref())
+            .unwrap_or_else(|| panic!("Reactor {} has no component {} of type {}", self.name, name, type_name::<C>()))
+    }
+
+    fn component_mut<C: 'static>(&mut self, name: &str) -> &mut C {
+        let reactor = self.name;
+        self.components
+            .iter_mut()
+            .find(|c| c.name == name)
+            .and_then(|c| c.value.downcast_mut())
+            .unwrap_or_else(|| panic!("Reactor {} has no component {} of type {}", reactor, name, type_name::<C>()))
+    }
+}
+
+struct Component {
+    name: &'static str,
+    trigger_id: TriggerId,
+    value: Box<dyn Any>,
+    bind: BindFn,
+}
+
+enum Reaction {
+    User(ReactionBody),
+    /// Synthetic reaction that starts the timer with the given index.
+    StartTimer(usize),
+    /// Synthetic reaction that reschedules the timer with the given index.
+    RescheduleTimer(usize),
+}
+
+/// A reactor assembled by a [ProgramBuilder].
+struct BuiltReactor {
+    id: ReactorId,
+    reactions: Vec<Reaction>,
+    state: ReactorState,
+}
+
+impl ReactorBehavior for BuiltReactor {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match &mut self.reactions[local_rid.index()] {
+            Reaction::User(body) => body(ctx, &mut self.state),
+            Reaction::StartTimer(timer) => {
+                let timer = self.state.components[*timer].value.downcast_mut().unwrap();
+                ctx.bootstrap_timer(timer)
+            }
+            Reaction::RescheduleTimer(timer) => {
+                let timer = self.state.components[*timer].value.downcast_mut().unwrap();
+                ctx.reschedule_timer(timer)
+            }
+        }
+    }
+}
@@ -0,0 +1,372 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Checkpoints of the state of a running program.
+//!
+//! A checkpoint is requested by a reaction with
+//! [ReactionCtx::request_checkpoint], and written at the end of
+//! the tag, after all reactors have cleaned up. It contains the
+//! tag, the pending events, and the state of each reactor. The
+//! program may be resumed from it with [SyncScheduler::try_resume_main].
+//!
+//! Asynchronous events that have not reached the scheduler when
+//! the checkpoint is written are not part of it. Programs that
+//! are federated, or use delayed connections, modes or watchdogs
+//! cannot be checkpointed, as the state of those is not saved.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use index_vec::Idx;
+
+use crate::assembly::TriggerId;
+use crate::*;
+
+const MAGIC: &[u8; 4] = b"LFCP";
+const VERSION: u64 = 1;
+
+/// Implemented by reactors whose state can be saved in a
+/// checkpoint. LFC generates an implementation for each reactor,
+/// which is exposed with [ReactorBehavior::as_checkpointable].
+///
+/// Implementations must write the state variables of the reactor,
+/// the pending values of its actions (see [CheckpointWriter::write_action])
+/// and the ticks of its timers, and read them back in the same order.
+pub trait Checkpointable {
+    /// Write the state of this reactor.
+    fn save_state(&self, out: &mut CheckpointWriter) -> io::Result<()>;
+
+    /// Restore the state of this reactor, as written by [Self::save_state].
+    fn restore_state(&mut self, input: &mut CheckpointReader) -> io::Result<()>;
+}
+
+/// Writes the state of a reactor into a checkpoint. Values are
+/// serialized by the caller, this only takes care of framing.
+pub struct CheckpointWriter<'a> {
+    out: &'a mut dyn Write,
+}
+
+impl<'a> CheckpointWriter<'a> {
+    pub(crate) fn new(out: &'a mut dyn Write) -> Self {
+        Self { out }
+    }
+
+    /// Write the magic number and version that start a file.
+    pub(super) fn write_header(&mut self, magic: &[u8; 4], version: u64) -> io::Result<()> {
+        self.out.write_all(magic)?;
+        self.write_u64(version)
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> io::Result<()> {
+        self.out.write_all(&value.to_le_bytes())
+    }
+
+    /// Write a length-prefixed byte string.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write_u64(bytes.len() as u64)?;
+        self.out.write_all(bytes)
+    }
+
+    pub fn write_tag(&mut self, tag: EventTag) -> io::Result<()> {
+        self.write_u64(tag.offset_from_t0.as_nanos() as u64)?;
+        self.write_u64(tag.microstep.raw() as u64)
+    }
+
+    fn write_opt_tag(&mut self, tag: Option<EventTag>) -> io::Result<()> {
+        match tag {
+            Some(tag) => {
+                self.write_u64(1)?;
+                self.write_tag(tag)
+            }
+            None => self.write_u64(0),
+        }
+    }
+
+    /// Write the values that are pending in the given action,
+    /// serialized with the given function.
+    pub fn write_action<T: Sync>(&mut self, action: &LogicalAction<T>, serialize: impl FnMut(&T) -> Vec<u8>) -> io::Result<()> {
+        self.write_action_impl(action.inner(), serialize)
+    }
+
+    /// Write the values that are pending in the given physical
+    /// action, serialized with the given function.
+    pub fn write_physical_action<T: Sync>(
+        &mut self,
+        action: &PhysicalActionRef<T>,
+        serialize: impl FnMut(&T) -> Vec<u8>,
+    ) -> io::Result<()> {
+        action
+            .use_value(|action| self.write_action_impl(&action.0, serialize))
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Action lock is poisoned")))
+    }
+
+    fn write_action_impl<K, T: Sync>(
+        &mut self,
+        action: impl Deref<Target = Action<K, T>>,
+        mut serialize: impl FnMut(&T) -> Vec<u8>,
+    ) -> io::Result<()> {
+        self.write_opt_tag(action.last_tag())?;
+        let values: Vec<_> = action.pending_values().collect();
+        self.write_u64(values.len() as u64)?;
+        for (tag, value) in values {
+            self.write_tag(tag)?;
+            match value {
+                Some(value) => {
+                    self.write_u64(1)?;
+                    self.write_bytes(&serialize(value))?;
+                }
+                None => self.write_u64(0)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the ticks of the given timer.
+    pub fn write_timer(&mut self, timer: &Timer) -> io::Result<()> {
+        let ticks = timer.ticks();
+        self.write_opt_tag(ticks.prev())?;
+        self.write_opt_tag(ticks.next())?;
+        self.write_u64(ticks.period().as_nanos() as u64)
+    }
+}
+
+/// Reads the state of a reactor from a checkpoint, see [CheckpointWriter].
+pub struct CheckpointReader<'a> {
+    input: &'a mut dyn Read,
+}
+
+impl<'a> CheckpointReader<'a> {
+    pub(crate) fn new(input: &'a mut dyn Read) -> Self {
+        Self { input }
+    }
+
+    /// Check the magic number and version that start a file,
+    /// see [CheckpointWriter::write_header].
+    pub(super) fn read_header(&mut self, magic: &[u8; 4], version: u64) -> io::Result<bool> {
+        let mut buf = [0u8; 4];
+        self.input.read_exact(&mut buf)?;
+        Ok(&buf == magic && self.read_u64()? == version)
+    }
+
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        self.input.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_u64()?;
+        let mut buf = Vec::new();
+        self.input.take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buf)
+    }
+
+    pub fn read_tag(&mut self) -> io::Result<EventTag> {
+        let offset = Duration::from_nanos(self.read_u64()?);
+        let microstep = self.read_u64()?.try_into().map_err(|_| invalid_data("Microstep overflow"))?;
+        Ok(EventTag::offset(offset, microstep))
+    }
+
+    fn read_opt_tag(&mut self) -> io::Result<Option<EventTag>> {
+        match self.read_u64()? {
+            0 => Ok(None),
+            _ => self.read_tag().map(Some),
+        }
+    }
+
+    /// Restore the pending values of the given action, written
+    /// by [CheckpointWriter::write_action].
+    pub fn read_action<T: Sync>(
+        &mut self,
+        action: &mut LogicalAction<T>,
+        deserialize: impl FnMut(Vec<u8>) -> io::Result<T>,
+    ) -> io::Result<()> {
+        self.read_action_impl(&mut *action.inner_mut(), deserialize)
+    }
+
+    /// Restore the pending values of the given physical action,
+    /// written by [CheckpointWriter::write_physical_action].
+    pub fn read_physical_action<T: Sync>(
+        &mut self,
+        action: &PhysicalActionRef<T>,
+        deserialize: impl FnMut(Vec<u8>) -> io::Result<T>,
+    ) -> io::Result<()> {
+        action
+            .use_mut(|action| self.read_action_impl(&mut action.0, deserialize))
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Action lock is poisoned")))
+    }
+
+    fn read_action_impl<K, T: Sync>(
+        &mut self,
+        action: &mut Action<K, T>,
+        mut deserialize: impl FnMut(Vec<u8>) -> io::Result<T>,
+    ) -> io::Result<()> {
+        let last_tag = self.read_opt_tag()?;
+        for _ in 0..self.read_u64()? {
+            let tag = self.read_tag()?;
+            let value = match self.read_u64()? {
+                0 => None,
+                _ => Some(deserialize(self.read_bytes()?)?),
+            };
+            action.schedule_future_value(tag, value);
+        }
+        action.set_last_tag(last_tag);
+        Ok(())
+    }
+
+    /// Restore the ticks of the given timer, written by
+    /// [CheckpointWriter::write_timer].
+    pub fn read_timer(&mut self, timer: &Timer) -> io::Result<()> {
+        let prev = self.read_opt_tag()?;
+        let next = self.read_opt_tag()?;
+        let period = Duration::from_nanos(self.read_u64()?);
+        timer.ticks().restore(prev, next, period);
+        Ok(())
+    }
+}
+
+pub(super) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The contents of a checkpoint file.
+pub(super) struct Checkpoint {
+    /// The tag after which the checkpoint was taken.
+    pub tag: EventTag,
+    /// Pending events: their tag, the triggers that
+    /// caused them, and whether the program terminates.
+    pub events: Vec<(EventTag, Vec<TriggerId>, bool)>,
+    /// The state of each reactor, as written by [Checkpointable::save_state].
+    pub reactors: Vec<(ReactorId, Vec<u8>)>,
+}
+
+impl Checkpoint {
+    /// Write the checkpoint to the given path. The file is
+    /// replaced atomically, so that a crash while writing does
+    /// not lose the previous checkpoint.
+    pub(super) fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        self.write(&mut CheckpointWriter::new(&mut file))?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    pub(super) fn load(path: &Path) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        Self::read(&mut CheckpointReader::new(&mut file))
+    }
+
+    fn write(&self, out: &mut CheckpointWriter) -> io::Result<()> {
+        out.write_header(MAGIC, VERSION)?;
+        out.write_tag(self.tag)?;
+        out.write_u64(self.events.len() as u64)?;
+        for (tag, triggers, terminate) in &self.events {
+            out.write_tag(*tag)?;
+            out.write_u64(*terminate as u64)?;
+            out.write_u64(triggers.len() as u64)?;
+            for trigger in triggers {
+                out.write_u64(trigger.index() as u64)?;
+            }
+        }
+        out.write_u64(self.reactors.len() as u64)?;
+        for (id, state) in &self.reactors {
+            out.write_u64(id.index() as u64)?;
+            out.write_bytes(state)?;
+        }
+        Ok(())
+    }
+
+    fn read(input: &mut CheckpointReader) -> io::Result<Self> {
+        if !input.read_header(MAGIC, VERSION)? {
+            return Err(invalid_data("Not a checkpoint file, or unsupported version"));
+        }
+        let tag = input.read_tag()?;
+        let mut events = Vec::new();
+        for _ in 0..input.read_u64()? {
+            let tag = input.read_tag()?;
+            let terminate = input.read_u64()? != 0;
+            let mut triggers = Vec::new();
+            for _ in 0..input.read_u64()? {
+                triggers.push(TriggerId::from_usize(input.read_u64()? as usize));
+            }
+            events.push((tag, triggers, terminate));
+        }
+        let mut reactors = Vec::new();
+        for _ in 0..input.read_u64()? {
+            let id = ReactorId::from_usize(input.read_u64()? as usize);
+            reactors.push((id, input.read_bytes()?));
+        }
+        Ok(Self { tag, events, reactors })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let checkpoint = Checkpoint {
+            tag: tag!(T0 + 5 ms),
+            events: vec![
+                (tag!(T0 + 10 ms), vec![TriggerId::FIRST_REGULAR], false),
+                (tag!(T0 + 20 ms), vec![], true),
+            ],
+            reactors: vec![(ReactorId::new(0), vec![1, 2, 3])],
+        };
+        let mut buf = Vec::new();
+        checkpoint.write(&mut CheckpointWriter::new(&mut buf)).unwrap();
+        let read = Checkpoint::read(&mut CheckpointReader::new(&mut buf.as_slice())).unwrap();
+
+        assert_eq!(read.tag, checkpoint.tag);
+        assert_eq!(read.events, checkpoint.events);
+        assert_eq!(read.reactors, checkpoint.reactors);
+    }
+
+    #[test]
+    fn test_action_values_roundtrip() {
+        let mut action = LogicalAction::<u64>::new(TriggerId::FIRST_REGULAR, None, None, SpacingPolicy::Defer);
+        action.inner_mut().schedule_future_value(tag!(T0 + 10 ms), Some(42));
+        action.inner_mut().schedule_future_value(tag!(T0 + 20 ms), None);
+
+        let mut buf = Vec::new();
+        CheckpointWriter::new(&mut buf)
+            .write_action(&action, |v| v.to_le_bytes().to_vec())
+            .unwrap();
+
+        let mut restored = LogicalAction::<u64>::new(TriggerId::FIRST_REGULAR, None, None, SpacingPolicy::Defer);
+        CheckpointReader::new(&mut buf.as_slice())
+            .read_action(&mut restored, |bytes| Ok(u64::from_le_bytes(bytes.try_into().unwrap())))
+            .unwrap();
+
+        assert_eq!(restored.inner_mut().forget_value(&tag!(T0 + 10 ms)), Some(42));
+        assert!(restored.inner_mut().cancel(&tag!(T0 + 20 ms)));
+    }
+}
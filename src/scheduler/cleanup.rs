@@ -0,0 +1,85 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Cleanup of the ports and actions that are present at a tag.
+//!
+//! The scheduler records the ports set by reactions and the
+//! triggers of the events of each tag. At the end of the tag,
+//! it clears exactly those through the handles registered at
+//! assembly, so that reactors don't have to clean up their
+//! own components.
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use crate::assembly::TriggerId;
+use crate::*;
+
+/// Handles to the components that must be cleaned up after
+/// a tag where they were present, by their ID.
+pub(crate) type TagCleanups = HashMap<TriggerId, Box<dyn TagCleanup>>;
+
+/// A component whose value must be dropped at the end
+/// of a tag where it is present.
+pub(crate) trait TagCleanup {
+    /// Drop the value of the component at the given tag.
+    fn cleanup(&mut self, tag: EventTag);
+
+    /// The handle itself, so that mutations can bind new ports
+    /// to the existing ones, see [crate::assembly::Mutator].
+    fn as_any(&mut self) -> &mut dyn Any;
+}
+
+/// This must be a handle obtained with [Port::share],
+/// which follows the binding of the port.
+impl<T: Sync + 'static> TagCleanup for Port<T> {
+    fn cleanup(&mut self, _tag: EventTag) {
+        self.clear_value()
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// This must be a handle obtained with [LogicalAction::share].
+impl<T: Sync + 'static> TagCleanup for LogicalAction<T> {
+    fn cleanup(&mut self, tag: EventTag) {
+        self.inner_mut().forget_value(&tag);
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl<T: Sync + 'static> TagCleanup for PhysicalActionRef<T> {
+    fn cleanup(&mut self, tag: EventTag) {
+        self.use_mut(|action| action.0.forget_value(&tag)).ok();
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Sources of physical time.
+
+use std::sync::Mutex;
+
+use super::PhysicalEvent;
+use crate::assembly::TriggerLike;
+use crate::util::reconnectable::Sender;
+use crate::*;
+
+/// A source of physical time for the scheduler. It is
+/// set with [SchedulerOptions::clock](crate::SchedulerOptions::clock),
+/// and defaults to [RealTimeClock].
+///
+/// The scheduler reads physical time to wait for the logical
+/// time of a tag, to timestamp physical actions, and to check
+/// deadlines. Running watchdogs also wait for their deadline.
+pub trait Clock: Send + Sync {
+    /// Returns the current physical time.
+    fn now(&self) -> Instant;
+
+    /// Returns how long the scheduler or a watchdog should wait
+    /// in real time for `target` to be reached, unless it is woken
+    /// up earlier. The caller checks [Self::now] again after that
+    /// time, so the result needs not be exact.
+    ///
+    /// If this returns None, the caller waits until it is woken
+    /// up. The clock must then keep the `waker`, along with those
+    /// of the other waiting callers, and call [ClockWaker::wake]
+    /// when it advances.
+    fn real_time_until(&self, target: Instant, waker: &ClockWaker) -> Option<Duration>;
+}
+
+/// Allows a [Clock] to wake up the scheduler or a watchdog
+/// when it is waiting for physical time to advance.
+#[derive(Clone)]
+pub struct ClockWaker(Waiter);
+
+#[derive(Clone)]
+enum Waiter {
+    /// The scheduler, which is woken up by an event.
+    Scheduler(Sender<PhysicalEvent>),
+    /// The thread waiting for the deadline of a watchdog.
+    Watchdog(Watchdog),
+}
+
+impl ClockWaker {
+    pub(super) fn scheduler(tx: Sender<PhysicalEvent>) -> Self {
+        Self(Waiter::Scheduler(tx))
+    }
+
+    pub(crate) fn watchdog(watchdog: Watchdog) -> Self {
+        Self(Waiter::Watchdog(watchdog))
+    }
+
+    /// Wake up the waiting scheduler or watchdog, so that it
+    /// reads the time again. This does nothing if the scheduler
+    /// has shut down.
+    pub fn wake(&self) {
+        match &self.0 {
+            Waiter::Scheduler(tx) => {
+                tx.send(PhysicalEvent::wake_up(EventTag::ORIGIN)).ok();
+            }
+            Waiter::Watchdog(watchdog) => watchdog.wake(),
+        }
+    }
+
+    /// Whether both wakers wake up the same waiter.
+    fn same_waiter(&self, other: &ClockWaker) -> bool {
+        match (&self.0, &other.0) {
+            (Waiter::Scheduler(_), Waiter::Scheduler(_)) => true,
+            (Waiter::Watchdog(a), Waiter::Watchdog(b)) => a.get_id() == b.get_id(),
+            _ => false,
+        }
+    }
+}
+
+/// The default clock, which reads the monotonic clock
+/// of the system ([Instant::now]).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RealTimeClock;
+
+impl Clock for RealTimeClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn real_time_until(&self, target: Instant, _waker: &ClockWaker) -> Option<Duration> {
+        Some(target.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// A clock that only advances when told to, with [Self::advance].
+/// This makes the physical timestamps of physical actions, the
+/// lag checked by deadlines, and timeouts deterministic, which
+/// is useful in tests.
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use reactor_rt::*;
+/// let clock = Arc::new(VirtualClock::new());
+/// let options = SchedulerOptions { clock: Some(clock.clone()), ..Default::default() };
+/// // start the program with these options on another thread, then
+/// clock.advance(Duration::from_secs(3600));
+/// ```
+///
+/// A virtual clock should only be used by a single scheduler
+/// at a time.
+pub struct VirtualClock {
+    state: Mutex<VirtualClockState>,
+}
+
+struct VirtualClockState {
+    now: Instant,
+    /// Wakers of the scheduler and the watchdogs that are
+    /// waiting for time to advance.
+    wakers: Vec<ClockWaker>,
+}
+
+impl VirtualClock {
+    /// Create a clock that is stopped at the current real time.
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Create a clock that is stopped at the given instant.
+    pub fn starting_at(now: Instant) -> Self {
+        Self {
+            state: Mutex::new(VirtualClockState { now, wakers: Vec::new() }),
+        }
+    }
+
+    /// Advance the clock by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.now += duration;
+            std::mem::take(&mut state.wakers)
+        };
+        // a watchdog may read the time while holding its own lock,
+        // so it is woken up once this lock is released
+        for waker in wakers {
+            waker.wake()
+        }
+    }
+
+    /// Advance the clock to the given instant. Does nothing
+    /// if the clock is already past that instant.
+    pub fn advance_to(&self, instant: Instant) {
+        let now = self.now();
+        if instant > now {
+            self.advance(instant - now)
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn real_time_until(&self, target: Instant, waker: &ClockWaker) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        if state.now >= target {
+            Some(Duration::ZERO)
+        } else {
+            state.wakers.retain(|w| !w.same_waiter(waker));
+            state.wakers.push(waker.clone());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::reconnectable::unbounded;
+
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock_advances_manually() {
+        let start = Instant::now();
+        let clock = VirtualClock::starting_at(start);
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(clock.now(), start + Duration::from_secs(3600));
+
+        clock.advance_to(start);
+        assert_eq!(clock.now(), start + Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_virtual_clock_wakes_up_scheduler() {
+        let (_, rx) = unbounded::<PhysicalEvent>();
+        let start = Instant::now();
+        let clock = VirtualClock::starting_at(start);
+        let target = start + Duration::from_millis(20);
+
+        assert_eq!(clock.real_time_until(target, &ClockWaker::scheduler(rx.new_sender())), None);
+        assert!(rx.try_iter().next().is_none());
+
+        clock.advance(Duration::from_millis(20));
+        assert!(rx.try_iter().next().unwrap().is_wake_up());
+        assert_eq!(
+            clock.real_time_until(target, &ClockWaker::scheduler(rx.new_sender())),
+            Some(Duration::ZERO)
+        );
+    }
+}
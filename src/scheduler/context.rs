@@ -1,15 +1,21 @@
 use std::borrow::Borrow;
+use std::fmt::Display;
 use std::hash::{Hash, Hasher};
+use std::io;
+use std::panic::AssertUnwindSafe;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 
-use crossbeam_channel::reconnectable::{Receiver, SendError, Sender};
 use smallvec::SmallVec;
 
+use super::error::panic_message;
+use super::federate::FederateClient;
 use super::*;
 use crate::assembly::*;
-use crate::scheduler::dependencies::{DataflowInfo, ExecutableReactions, LevelIx};
+use crate::federated::NetworkChannel;
+use crate::scheduler::dependencies::{DataflowInfo, Deadline, ExecutableReactions, LevelIx};
+use crate::util::reconnectable::{Receiver, SendError, Sender};
 use crate::*;
 
 /// The context in which a reaction executes. Its API
@@ -49,6 +55,21 @@ pub struct ReactionCtx<'a, 'x> {
     /// It duplicates [Self::was_terminated_atomic], to avoid an atomic
     /// operation within [Self::is_shutdown].
     was_terminated: bool,
+    /// Connection to the RTI, if this program is a federate.
+    federate: Option<&'a FederateClient>,
+    /// The tag being processed by the scheduler, only in fast mode
+    /// or with a custom clock. See [AsyncCtx::processing_tag].
+    processing_tag: Option<&'a Arc<Mutex<EventTag>>>,
+    /// Source of physical time.
+    clock: &'a Arc<dyn Clock>,
+    /// Records an execution trace, if enabled.
+    tracer: Option<&'a Tracer>,
+    /// What to do when a reaction fails.
+    failure_policy: FailurePolicy,
+    /// Current modes of reactors.
+    modes: &'a ModeInfo,
+    /// Whether the scheduler replays recorded events, see [AsyncCtx::replaying].
+    replaying: bool,
 }
 
 impl<'a, 'x> ReactionCtx<'a, 'x> {
@@ -67,7 +88,7 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
     /// physical time is necessarily greater than the logical time.
     #[inline]
     pub fn get_physical_time(&self) -> Instant {
-        Instant::now()
+        self.clock.now()
     }
 
     /// Returns the current logical time.
@@ -244,6 +265,7 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
             self.check_set_port_is_legal(port)
         }
         port.set_impl(Some(value));
+        self.insides.set_ports.push(port.get_id());
         self.enqueue_now(Cow::Borrowed(self.reactions_triggered_by(port.get_id())));
     }
 
@@ -337,7 +359,7 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
     /// ctx.schedule(action, After(Duration::from_millis(2))); // equivalent to the previous
     /// ```
     #[inline]
-    pub fn schedule<T: Sync>(&mut self, action: &mut impl SchedulableAsAction<T>, offset: Offset) {
+    pub fn schedule<T: Sync>(&mut self, action: &mut impl SchedulableAsAction<T>, offset: Offset) -> Option<ScheduleToken> {
         self.schedule_with_v(action, None, offset)
     }
 
@@ -345,7 +367,10 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
     ///
     /// The action will carry the given value at the time it
     /// is triggered, unless it is overwritten by another call
-    /// to this method. The value can be cleared by using `None`
+    /// to this method (queued actions instead deliver later values
+    /// at successive microsteps). If the action was declared with a minimum
+    /// spacing, events that are too close to the previous one are
+    /// handled according to its [SpacingPolicy]. The value can be cleared by using `None`
     /// as a value. Note that even if the value is absent, the
     /// *action* will still be present at the time it is triggered
     /// (see [Self::is_present]).
@@ -368,19 +393,55 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
     /// // that's equivalent to
     /// ctx.schedule(action, Asap);
     /// ```
+    ///
+    /// The returned token can be used to [cancel](Self::cancel) the
+    /// event. It is None if no new event was scheduled, because of
+    /// the [SpacingPolicy] of the action.
     #[inline]
-    pub fn schedule_with_v<T: Sync>(&mut self, action: &mut impl SchedulableAsAction<T>, value: Option<T>, offset: Offset) {
+    pub fn schedule_with_v<T: Sync>(
+        &mut self,
+        action: &mut impl SchedulableAsAction<T>,
+        value: Option<T>,
+        offset: Offset,
+    ) -> Option<ScheduleToken> {
         action.schedule_with_v(self, value, offset)
     }
 
+    /// Cancel an event previously scheduled on the given action.
+    /// The value of the event is dropped, and the reactions it
+    /// would have triggered are not executed, unless another
+    /// trigger is present at the same tag.
+    ///
+    /// Returns false if the event is not pending anymore, eg
+    /// because its tag has already been processed or it has
+    /// already been cancelled.
+    ///
+    /// ### Examples
+    ///
+    /// ```no_run
+    /// # use reactor_rt::prelude::*;
+    /// # let ctx: &mut ReactionCtx = panic!();
+    /// # let timeout: &mut LogicalAction<()> = panic!();
+    /// let token = ctx.schedule(timeout, after!(2 sec));
+    /// // later, when the expected message arrives
+    /// if let Some(token) = token {
+    ///     ctx.cancel(timeout, token);
+    /// }
+    /// ```
+    #[inline]
+    pub fn cancel<T: Sync>(&mut self, action: &mut impl SchedulableAsAction<T>, token: ScheduleToken) -> bool {
+        action.cancel(self, token)
+    }
+
     /// Add new reactions to execute later (at least 1 microstep later).
     ///
     /// This is used for actions.
     #[inline]
-    pub(crate) fn enqueue_later(&mut self, downstream: &'x ExecutableReactions, tag: EventTag) {
+    pub(crate) fn enqueue_later(&mut self, trigger: TriggerId, tag: EventTag) {
         debug_assert!(tag > self.get_tag());
 
-        let evt = Event::execute(tag, Cow::Borrowed(downstream));
+        let downstream = self.reactions_triggered_by(trigger);
+        let evt = Event::execute(tag, trigger, Cow::Borrowed(downstream));
         self.insides.future_events.push(evt);
     }
 
@@ -435,14 +496,95 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
         F: Send + 'static,
         R: Send + 'static,
     {
-        let tx = self.rx.new_sender();
-        let initial_time = self.initial_time;
-        let was_terminated = self.was_terminated_atomic.clone();
+        let mut link = AsyncCtx::new(
+            self.rx.new_sender(),
+            self.initial_time,
+            self.was_terminated_atomic.clone(),
+            self.processing_tag.cloned(),
+            self.clock.clone(),
+            self.replaying,
+        );
 
-        std::thread::spawn(move || {
-            let mut link = AsyncCtx { tx, initial_time, was_terminated };
-            f(&mut link)
-        })
+        std::thread::spawn(move || f(&mut link))
+    }
+
+    /// Start the given watchdog, or restart it if it is already
+    /// running. If it is not started again or stopped before its
+    /// timeout has elapsed in physical time, counted from the
+    /// current logical time, the reactions it triggers are executed
+    /// at the physical time of its expiry.
+    ///
+    /// Like physical actions, a running watchdog keeps the
+    /// program alive until it expires or is stopped.
+    ///
+    /// ### Example
+    ///
+    /// ```no_run
+    /// # use reactor_rt::prelude::*;
+    /// fn on_heartbeat(ctx: &mut ReactionCtx, watchdog: &Watchdog) {
+    ///     // the watchdog reactions will be executed if no
+    ///     // heartbeat arrives within its timeout
+    ///     ctx.start_watchdog(watchdog);
+    /// }
+    /// ```
+    pub fn start_watchdog(&mut self, watchdog: &Watchdog) {
+        let deadline = self.get_logical_time() + watchdog.timeout;
+        // replayed expiries come from the recording
+        if watchdog.start(deadline) && !self.replaying {
+            let watchdog = watchdog.clone();
+            self.spawn_physical_thread(move |link| link.await_watchdog(&watchdog));
+        }
+    }
+
+    /// Stop the given watchdog, so that it does not expire.
+    /// This does nothing if it is not running.
+    pub fn stop_watchdog(&mut self, watchdog: &Watchdog) {
+        watchdog.stop();
+    }
+
+    /// Request that a checkpoint of the program be written at
+    /// the end of the current tag, to the path set in
+    /// [SchedulerOptions::checkpoint](crate::SchedulerOptions::checkpoint).
+    /// The program may later be resumed from that checkpoint with
+    /// [SyncScheduler::try_resume_main](crate::SyncScheduler::try_resume_main).
+    ///
+    /// All reactors must implement [Checkpointable]. If the
+    /// checkpoint cannot be written, an error is logged and
+    /// execution continues.
+    pub fn request_checkpoint(&mut self) {
+        self.insides.checkpoint_requested = true;
+    }
+
+    /// Add reactors and connections to the running program at
+    /// the end of the current tag. New reactors are children of
+    /// the reactor of the current reaction, see [Mutator]. Their
+    /// startup reactions execute at the next microstep.
+    ///
+    /// If the mutation fails, the program is left unchanged, and
+    /// the failure is handled like a failure of the current reaction,
+    /// see [Self::fail]. The dependency graph of the whole program
+    /// is processed again for each tag with mutations, so they
+    /// should be rare compared to tags.
+    ///
+    /// ```no_run
+    /// # use reactor_rt::prelude::*;
+    /// # use reactor_rt::assembly::*;
+    /// # use reactor_rt::{LocalReactionId, ReactorBehavior, ReactorId};
+    /// # struct Worker { input: Port<u32> }
+    /// # impl ReactorBehavior for Worker { fn id(&self) -> ReactorId { panic!() } fn react(&mut self, ctx: &mut ReactionCtx, rid: LocalReactionId) {} }
+    /// # impl ReactorInitializer for Worker { type Wrapped = (); type Params = (); const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(0); fn assemble(args: (), ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> { panic!() } }
+    /// # let ctx: &mut ReactionCtx = panic!();
+    /// # let requests: Port<u32> = panic!();
+    /// let requests = requests.get_id();
+    /// ctx.mutate(move |m| {
+    ///     m.add_child::<Worker>("worker", (), |m, worker| m.bind_from(requests, &mut worker.input))?;
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn mutate(&mut self, mutation: impl FnOnce(&mut Mutator) -> AssemblyResult<()> + Send + 'static) {
+        let reaction = self.current_reaction.expect("Mutations can only be requested by reactions");
+        let mutation = Box::new(mutation);
+        self.insides.mutations.push(PendingMutation { reaction, mutation });
     }
 
     /// Request that the application shutdown, possibly with
@@ -470,15 +612,90 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
         self.insides.future_events.push(evt);
     }
 
+    /// Switch the reactor of the current reaction to the given
+    /// mode. The transition takes effect at the next microstep,
+    /// so reactions that execute at this tag still see the
+    /// current mode. The mode must belong to the reactor of the
+    /// current reaction.
+    ///
+    /// ```no_run
+    /// # use reactor_rt::prelude::*;
+    /// # let ctx: &mut ReactionCtx = panic!();
+    /// # let running: Mode = panic!();
+    /// ctx.set_mode(running, ModeTransition::Reset);
+    /// ```
+    pub fn set_mode(&mut self, mode: Mode, transition: ModeTransition) {
+        debug_assert_eq!(
+            self.current_reaction.map(|r| r.0.container()),
+            Some(mode.reactor()),
+            "Cannot set the mode of another reactor"
+        );
+        self.insides.mode_changes.push((mode, transition));
+    }
+
+    /// Report that the current reaction failed with the given
+    /// error. What happens then depends on the [FailurePolicy]
+    /// set in [SchedulerOptions::failure_policy](crate::SchedulerOptions::failure_policy).
+    /// By default, this panics. Reactions that panic are
+    /// handled as if they called this method.
+    ///
+    /// ```no_run
+    /// # use reactor_rt::prelude::*;
+    /// # let ctx: &mut ReactionCtx = panic!();
+    /// # let input: Result<u32, std::num::ParseIntError> = "a".parse();
+    /// match input {
+    ///     Ok(_) => { /* ... */ }
+    ///     Err(e) => ctx.fail(e),
+    /// }
+    /// ```
+    pub fn fail(&mut self, error: impl Display) {
+        let reaction = match self.current_reaction {
+            Some(reaction) => reaction,
+            None => panic!("Reaction failed outside of a reaction: {}", error),
+        };
+        let message = error.to_string();
+        match self.failure_policy {
+            FailurePolicy::Abort => panic!("Reaction {} failed: {}", self.debug_info.display_reaction(reaction), message),
+            FailurePolicy::Log => error!("Reaction {} failed: {}", self.debug_info.display_reaction(reaction), message),
+            FailurePolicy::Shutdown => {
+                error!(
+                    "Reaction {} failed, shutting down: {}",
+                    self.debug_info.display_reaction(reaction),
+                    message
+                );
+                self.insides.failures.push(ReactionFailure { reaction, message });
+                self.request_stop(Offset::Asap);
+            }
+        }
+    }
+
+    /// Send a value to another federate, over the given
+    /// channel. The receiving federate observes it at the
+    /// current tag. This is called by network reactions
+    /// synthesized for ports that cross federate boundaries,
+    /// which also serialize the value.
+    ///
+    /// Returns an error if the message could not be sent to
+    /// the RTI, or if this program is not running as a federate.
+    #[doc(hidden)]
+    pub fn send_network_message(&mut self, channel: NetworkChannel, payload: Vec<u8>) -> io::Result<()> {
+        match self.federate {
+            Some(federate) => federate.send_tagged_message(channel, self.tag, payload),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "Not running as a federate")),
+        }
+    }
+
     /// Reschedule a periodic timer if need be.
     /// This is called by a reaction synthesized for each timer.
     // note: reactions can't call this as they're only passed a shared reference to a timer.
     #[doc(hidden)]
     #[inline]
     pub fn reschedule_timer(&mut self, timer: &mut Timer) {
-        if timer.is_periodic() {
-            let downstream = self.reactions_triggered_by(timer.get_id());
-            self.enqueue_later(downstream, self.make_successor_tag(timer.period));
+        if !self.modes.is_timer_active(timer) {
+            return;
+        }
+        if let Some(next) = timer.ticks().fire(self.tag) {
+            self.enqueue_later(timer.get_id(), next);
         }
     }
 
@@ -489,12 +706,87 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
     #[inline]
     pub fn bootstrap_timer(&mut self, timer: &mut Timer) {
         // we're in startup
-        let downstream = self.reactions_triggered_by(timer.get_id());
-        if timer.offset.is_zero() {
+        if !self.modes.is_timer_active(timer) {
+            // the timer will start when its mode is entered
+            return;
+        }
+        if timer.offset().is_zero() {
             // no offset
+            timer.ticks().set_next(self.tag, Some(self.tag));
+            let downstream = self.reactions_triggered_by(timer.get_id());
             self.enqueue_now(Cow::Borrowed(downstream))
         } else {
-            self.enqueue_later(downstream, self.make_successor_tag(timer.offset))
+            let tag = self.make_successor_tag(timer.offset());
+            timer.ticks().set_next(self.tag, Some(tag));
+            self.enqueue_later(timer.get_id(), tag)
+        }
+    }
+
+    /// Stop the given timer. If the timer triggers at the current
+    /// tag, it is still present until the end of the tag, but it
+    /// does not trigger anymore afterwards, until it is restarted
+    /// with [Self::restart_timer].
+    pub fn stop_timer(&mut self, timer: &Timer) {
+        self.set_next_tick(timer, None);
+    }
+
+    /// Restart the given timer, so that it next triggers after the
+    /// given offset from the current tag, then with its current period.
+    /// Like for actions, a zero offset means that the timer triggers
+    /// at the next microstep. Any tick that was scheduled is discarded.
+    ///
+    /// ### Examples
+    ///
+    /// ```no_run
+    /// # use reactor_rt::prelude::*;
+    /// # let ctx: &mut ReactionCtx = panic!();
+    /// # let timer: &Timer = panic!();
+    /// // timer will trigger in 1 sec, then every period
+    /// ctx.restart_timer(timer, Duration::from_secs(1));
+    /// ```
+    pub fn restart_timer(&mut self, timer: &Timer, offset: Duration) {
+        let next = self.make_successor_tag(offset);
+        self.set_next_tick(timer, Some(next));
+    }
+
+    /// Change the period of the given timer. Its next tick is
+    /// rescheduled to be one new period after its last tick, or
+    /// after the current tag if that is already past. If the
+    /// timer is stopped, it stays stopped.
+    ///
+    /// A period of zero means that the timer does not trigger
+    /// anymore after its next tick. Note that timers declared
+    /// without a period never repeat, even if their period is
+    /// changed at runtime.
+    pub fn set_timer_period(&mut self, timer: &Timer, period: Duration) {
+        let next = {
+            let mut ticks = timer.ticks();
+            ticks.set_period(period);
+            if period.is_zero() || ticks.next().is_none() {
+                return;
+            }
+            match ticks.prev().map(|prev| prev.successor(period)) {
+                Some(next) if next > self.tag => next,
+                _ => self.make_successor_tag(period),
+            }
+        };
+        self.set_next_tick(timer, Some(next));
+    }
+
+    /// Replace the next tick of the timer, and cancel the
+    /// event of its previous next tick.
+    fn set_next_tick(&mut self, timer: &Timer, next: Option<EventTag>) {
+        let previous = timer.ticks().set_next(self.tag, next);
+        if previous == next {
+            return;
+        }
+        if let Some(tag) = previous.filter(|tag| *tag > self.tag) {
+            self.insides
+                .cancellations
+                .push(ScheduleToken { trigger: timer.get_id(), tag });
+        }
+        if let Some(tag) = next {
+            self.enqueue_later(timer.get_id(), tag);
         }
     }
 
@@ -507,11 +799,48 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
             self.cur_level
         );
         debug_assert_eq!(reactor.id(), reaction_id.0.container(), "Wrong reactor");
+        debug_assert!(self.modes.is_reaction_active(reaction_id), "Reaction of an inactive mode");
         self.current_reaction.replace(reaction_id);
-        reactor.react(self, reaction_id.0.local());
+        let local_rid = match self.dataflow.deadline_of(reaction_id) {
+            Some(deadline) if self.is_deadline_violated(deadline) => {
+                trace!("    * Deadline violated, executing handler instead");
+                deadline.handler
+            }
+            _ => reaction_id.0.local(),
+        };
+        let executed = GlobalReactionId::new(reaction_id.0.container(), local_rid);
+        self.trace(TracePoint::ReactionStarts(executed));
+        match self.failure_policy {
+            FailurePolicy::Abort => reactor.react(self, local_rid),
+            FailurePolicy::Log | FailurePolicy::Shutdown => {
+                // the state of the reactor may be inconsistent after a panic,
+                // this is documented on FailurePolicy
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| reactor.react(self, local_rid)));
+                if let Err(payload) = result {
+                    self.fail(format!("panicked: {}", panic_message(payload)));
+                }
+            }
+        }
+        self.trace(TracePoint::ReactionEnds(executed));
         self.current_reaction.take();
     }
 
+    #[inline]
+    fn trace(&self, point: TracePoint) {
+        if let Some(tracer) = self.tracer {
+            tracer.record(point, self.tag)
+        }
+    }
+
+    /// Returns true if physical time lags behind the logical
+    /// time of this tag by more than the deadline allows.
+    #[inline]
+    fn is_deadline_violated(&self, deadline: &Deadline) -> bool {
+        let lag = self.get_physical_time().saturating_duration_since(self.get_logical_time());
+        lag > deadline.max_lag
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         rx: &'a Receiver<PhysicalEvent>,
         tag: EventTag,
@@ -521,9 +850,25 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
         debug_info: DebugInfoProvider<'a>,
         was_terminated_atomic: &'a Arc<AtomicBool>,
         was_terminated: bool,
+        federate: Option<&'a FederateClient>,
+        processing_tag: Option<&'a Arc<Mutex<EventTag>>>,
+        clock: &'a Arc<dyn Clock>,
+        tracer: Option<&'a Tracer>,
+        failure_policy: FailurePolicy,
+        modes: &'a ModeInfo,
+        replaying: bool,
     ) -> Self {
         Self {
-            insides: RContextForwardableStuff { todo_now: todo, future_events: Default::default() },
+            insides: RContextForwardableStuff {
+                todo_now: todo,
+                future_events: Default::default(),
+                failures: Default::default(),
+                mode_changes: Default::default(),
+                cancellations: Default::default(),
+                checkpoint_requested: false,
+                set_ports: Vec::new(),
+                mutations: Vec::new(),
+            },
             cur_level: Default::default(),
             tag,
             current_reaction: None,
@@ -533,6 +878,13 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
             was_terminated_atomic,
             debug_info,
             was_terminated,
+            federate,
+            processing_tag,
+            clock,
+            tracer,
+            failure_policy,
+            modes,
+            replaying,
         }
     }
 
@@ -553,6 +905,13 @@ impl<'a, 'x> ReactionCtx<'a, 'x> {
             was_terminated_atomic: self.was_terminated_atomic,
             debug_info: self.debug_info.clone(),
             current_reaction: self.current_reaction,
+            federate: self.federate,
+            processing_tag: self.processing_tag,
+            clock: self.clock,
+            tracer: self.tracer,
+            failure_policy: self.failure_policy,
+            modes: self.modes,
+            replaying: self.replaying,
         }
     }
 }
@@ -573,6 +932,35 @@ pub(super) struct RContextForwardableStuff<'x> {
     /// Events that were produced for a strictly greater
     /// logical time than a current one.
     pub(super) future_events: SmallVec<[Event<'x>; 4]>,
+
+    /// Failures reported by reactions, with [FailurePolicy::Shutdown].
+    pub(super) failures: Vec<ReactionFailure>,
+
+    /// Mode transitions requested by reactions, which take
+    /// effect at the end of the tag.
+    pub(super) mode_changes: Vec<(Mode, ModeTransition)>,
+
+    /// Events cancelled by reactions. They are removed from
+    /// the event queue at the end of the tag.
+    pub(super) cancellations: Vec<ScheduleToken>,
+
+    /// Whether a reaction requested a checkpoint at the end
+    /// of the tag.
+    pub(super) checkpoint_requested: bool,
+
+    /// Ports set by reactions during the tag, which the
+    /// scheduler clears at the end of the tag.
+    pub(super) set_ports: Vec<TriggerId>,
+
+    /// Mutations requested by reactions, which are applied
+    /// at the end of the tag.
+    pub(super) mutations: Vec<PendingMutation>,
+}
+
+/// A failure reported by a reaction, see [ReactionCtx::fail].
+pub(super) struct ReactionFailure {
+    pub(super) reaction: GlobalReactionId,
+    pub(super) message: String,
 }
 
 #[cfg(feature = "parallel-runtime")]
@@ -585,6 +973,12 @@ impl RContextForwardableStuff<'_> {
     pub(super) fn absorb(&mut self, mut other: Self) {
         self.todo_now = ExecutableReactions::merge_cows(self.todo_now.take(), other.todo_now);
         self.future_events.append(&mut other.future_events);
+        self.failures.append(&mut other.failures);
+        self.mode_changes.append(&mut other.mode_changes);
+        self.cancellations.append(&mut other.cancellations);
+        self.checkpoint_requested |= other.checkpoint_requested;
+        self.set_ports.append(&mut other.set_ports);
+        self.mutations.append(&mut other.mutations);
     }
 }
 
@@ -600,9 +994,39 @@ pub struct AsyncCtx {
     initial_time: Instant,
     /// Whether the scheduler has been terminated.
     was_terminated: Arc<AtomicBool>,
+    /// The tag being processed by the scheduler, only in fast
+    /// mode or with a custom clock, see [SchedulerOptions::clock].
+    /// Logical time may then be ahead of physical time,
+    /// and events must not be scheduled in the past. The lock
+    /// is held until the event is sent, so that the scheduler
+    /// does not start a later tag in the meantime.
+    processing_tag: Option<Arc<Mutex<EventTag>>>,
+    /// Source of physical time.
+    clock: Arc<dyn Clock>,
+    /// Whether the scheduler replays recorded events, in which
+    /// case the events of this context are ignored.
+    replaying: bool,
 }
 
 impl AsyncCtx {
+    pub(super) fn new(
+        tx: Sender<PhysicalEvent>,
+        initial_time: Instant,
+        was_terminated: Arc<AtomicBool>,
+        processing_tag: Option<Arc<Mutex<EventTag>>>,
+        clock: Arc<dyn Clock>,
+        replaying: bool,
+    ) -> Self {
+        Self {
+            tx,
+            initial_time,
+            was_terminated,
+            processing_tag,
+            clock,
+            replaying,
+        }
+    }
+
     /// Returns true if the scheduler has been shutdown. When
     /// that's true, calls to other methods of this type will
     /// fail with [SendError].
@@ -620,9 +1044,12 @@ impl AsyncCtx {
     /// or its shutdown might be programmed for a logical
     /// time which precedes the current physical time.
     pub fn request_stop(&mut self, offset: Offset) -> Result<(), SendError<()>> {
-        // physical time must be ahead of logical time so
-        // this event is scheduled for the future
-        let tag = EventTag::absolute(self.initial_time, Instant::now() + offset.to_duration());
+        if self.replaying {
+            trace!("Ignoring stop request while replaying a recording");
+            return Ok(());
+        }
+        let guard = self.processing_tag.as_ref().map(|tag| tag.lock().unwrap());
+        let tag = self.physical_tag(offset, guard.as_deref().copied());
 
         let evt = PhysicalEvent::terminate_at(tag);
         self.tx.send(evt).map_err(|e| {
@@ -671,12 +1098,21 @@ impl AsyncCtx {
         value: Option<T>,
         offset: Offset,
     ) -> Result<(), SendError<Option<T>>> {
-        // physical time must be ahead of logical time so
-        // this event is scheduled for the future
+        if self.replaying {
+            trace!("Ignoring physical action while replaying a recording");
+            return Ok(());
+        }
+        let guard = self.processing_tag.as_ref().map(|tag| tag.lock().unwrap());
+        let processing_tag = guard.as_deref().copied();
         action
             .use_mut_p(value, |action, value| {
-                let tag = EventTag::absolute(self.initial_time, Instant::now() + offset.to_duration());
-                action.0.schedule_future_value(tag, value);
+                let tag = self.physical_tag(offset, processing_tag);
+                // without a processing tag, events up to the current physical time may already be processed
+                let now = processing_tag.unwrap_or_else(|| EventTag::absolute(self.initial_time, self.clock.now()));
+                let tag = match action.0.schedule_with_spacing(tag, now, value) {
+                    Some(tag) => tag,
+                    None => return Ok(()),
+                };
 
                 let evt = PhysicalEvent::trigger(tag, action.get_id());
                 self.tx.send(evt).map_err(|e| {
@@ -686,33 +1122,97 @@ impl AsyncCtx {
             })
             .unwrap_or_else(|value| Err(SendError(value)))
     }
+
+    /// Wait for the deadlines of a watchdog until it is stopped
+    /// or expires, and send its expiry to the scheduler unless it
+    /// was restarted in the meantime.
+    fn await_watchdog(&mut self, watchdog: &Watchdog) {
+        while let Some(generation) = watchdog.wait_for_expiry(self.clock.as_ref(), &self.was_terminated) {
+            let guard = self.processing_tag.as_ref().map(|tag| tag.lock().unwrap());
+            let tag = self.physical_tag(Offset::Asap, guard.as_deref().copied());
+            if watchdog.record_expiry(generation, tag) {
+                let evt = PhysicalEvent::trigger(tag, watchdog.get_id());
+                self.tx.send(evt).ok();
+            }
+        }
+    }
+
+    /// Returns the tag of an event scheduled now with the given
+    /// offset. Physical time must be ahead of logical time, so
+    /// this event is scheduled for the future. In fast mode, or
+    /// with a custom clock, this is not the case, so the tag is
+    /// at least the successor of the tag being processed.
+    fn physical_tag(&self, offset: Offset, processing_tag: Option<EventTag>) -> EventTag {
+        let tag = EventTag::absolute(self.initial_time, self.clock.now() + offset.to_duration());
+        match processing_tag {
+            Some(processing_tag) => tag.max(processing_tag.successor(offset.to_duration())),
+            None => tag,
+        }
+    }
 }
 
 /// Implemented by LogicalAction and PhysicalAction references
 /// to give access to [ReactionCtx::schedule] and variants.
 pub trait SchedulableAsAction<T: Sync> {
     #[doc(hidden)]
-    fn schedule_with_v(&mut self, ctx: &mut ReactionCtx, value: Option<T>, offset: Offset);
+    fn schedule_with_v(&mut self, ctx: &mut ReactionCtx, value: Option<T>, offset: Offset) -> Option<ScheduleToken>;
+
+    #[doc(hidden)]
+    fn cancel(&mut self, ctx: &mut ReactionCtx, token: ScheduleToken) -> bool;
 }
 
 impl<T: Sync> SchedulableAsAction<T> for LogicalAction<T> {
-    fn schedule_with_v(&mut self, ctx: &mut ReactionCtx, value: Option<T>, offset: Offset) {
-        let eta = ctx.make_successor_tag(self.0.min_delay + offset.to_duration());
-        self.0.schedule_future_value(eta, value);
-        let downstream = ctx.dataflow.reactions_triggered_by(&self.get_id());
-        ctx.enqueue_later(downstream, eta);
+    fn schedule_with_v(&mut self, ctx: &mut ReactionCtx, value: Option<T>, offset: Offset) -> Option<ScheduleToken> {
+        let eta = ctx.make_successor_tag(self.inner().min_delay + offset.to_duration());
+        ctx.trace(TracePoint::ScheduleCalled { action: self.get_id(), delay: offset.to_duration() });
+        let eta = self.inner_mut().schedule_with_spacing(eta, ctx.get_tag(), value)?;
+        ctx.enqueue_later(self.get_id(), eta);
+        Some(ScheduleToken { trigger: self.get_id(), tag: eta })
+    }
+
+    fn cancel(&mut self, ctx: &mut ReactionCtx, token: ScheduleToken) -> bool {
+        debug_assert_eq!(token.trigger, self.get_id(), "Token belongs to another action");
+        let cancelled = token.tag > ctx.get_tag() && self.inner_mut().cancel(&token.tag);
+        if cancelled {
+            ctx.insides.cancellations.push(token);
+        }
+        cancelled
     }
 }
 
 impl<T: Sync> SchedulableAsAction<T> for PhysicalActionRef<T> {
-    fn schedule_with_v(&mut self, ctx: &mut ReactionCtx, value: Option<T>, offset: Offset) {
+    fn schedule_with_v(&mut self, ctx: &mut ReactionCtx, value: Option<T>, offset: Offset) -> Option<ScheduleToken> {
         self.use_mut_p(value, |action, value| {
-            let tag = EventTag::absolute(ctx.initial_time, Instant::now() + offset.to_duration());
-            action.0.schedule_future_value(tag, value);
-            let downstream = ctx.dataflow.reactions_triggered_by(&action.get_id());
-            ctx.enqueue_later(downstream, tag);
+            let mut tag = EventTag::absolute(ctx.initial_time, ctx.get_physical_time() + offset.to_duration());
+            if ctx.processing_tag.is_some() {
+                // logical time may be ahead of physical time, eg in fast mode
+                tag = tag.max(ctx.make_successor_tag(offset.to_duration()));
+            }
+            ctx.trace(TracePoint::ScheduleCalled {
+                action: action.get_id(),
+                delay: offset.to_duration(),
+            });
+            let tag = action.0.schedule_with_spacing(tag, ctx.get_tag(), value)?;
+            ctx.enqueue_later(action.get_id(), tag);
+            Some(ScheduleToken { trigger: action.get_id(), tag })
+        })
+        .ok()
+        .flatten()
+    }
+
+    /// Note that this cannot cancel events scheduled from an
+    /// asynchronous thread, as those may not have reached the
+    /// scheduler yet.
+    fn cancel(&mut self, ctx: &mut ReactionCtx, token: ScheduleToken) -> bool {
+        self.use_mut(|action| {
+            debug_assert_eq!(token.trigger, action.get_id(), "Token belongs to another action");
+            let cancelled = token.tag > ctx.get_tag() && action.0.cancel(&token.tag);
+            if cancelled {
+                ctx.insides.cancellations.push(token);
+            }
+            cancelled
         })
-        .ok();
+        .unwrap_or(false)
     }
 }
 
@@ -774,10 +1274,8 @@ impl Hash for Offset {
     }
 }
 
-/// Cleans up a tag
-/// TODO get rid of this!
-///  At least for multiports it's really bad
-///  Maybe we can keep a set of the ports that are present in ReactionCtx
+/// Cleans up a tag. The scheduler now clears the ports and
+/// actions present at a tag itself, see [ReactorBehavior::cleanup_tag].
 #[doc(hidden)]
 pub struct CleanupCtx {
     /// Tag we're cleaning up
@@ -797,7 +1295,7 @@ impl CleanupCtx {
     }
 
     pub fn cleanup_logical_action<T: Sync>(&self, action: &mut LogicalAction<T>) {
-        action.0.forget_value(&self.tag);
+        action.inner_mut().forget_value(&self.tag);
     }
 
     pub fn cleanup_physical_action<T: Sync>(&self, action: &mut PhysicalActionRef<T>) {
@@ -39,6 +39,7 @@ use crate::{GlobalReactionId, ReactorId};
 /// At runtime, this is only used to format debug messages and
 /// perform debug assertions, so compactness is more important
 /// than speed of the methods.
+#[derive(Clone)]
 pub(crate) struct DebugInfoRegistry {
     /// Maps reactor ids to their debug info.
     reactor_infos: IndexVec<ReactorId, ReactorDebugInfo>,
@@ -151,6 +152,45 @@ impl DebugInfoRegistry {
         }
     }
 
+    /// Returns whether the reactor is the given ancestor or one of
+    /// its descendants. Unlike [Self::get_container], this may be
+    /// called while the program is being assembled.
+    pub(crate) fn is_within(&self, mut reactor: ReactorId, ancestor: ReactorId) -> bool {
+        while reactor != ancestor {
+            match self.reactor_container.get(&reactor) {
+                Some(container) => reactor = *container,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Returns whether the child is directly contained in the
+    /// parent. This may be called while the program is being assembled.
+    pub(crate) fn is_child_of(&self, child: ReactorId, parent: ReactorId) -> bool {
+        self.reactor_container.get(&child) == Some(&parent)
+    }
+
+    /// Returns the ids of all reactors.
+    pub(crate) fn reactor_ids(&self) -> impl Iterator<Item = ReactorId> {
+        self.reactor_infos.indices()
+    }
+
+    /// Returns the ids of all triggers, including startup and shutdown.
+    pub(crate) fn trigger_ids(&self) -> impl Iterator<Item = TriggerId> {
+        self.trigger_infos.indices()
+    }
+
+    /// Returns whether the trigger was allocated.
+    pub(crate) fn has_trigger(&self, id: TriggerId) -> bool {
+        id.index() < self.trigger_infos.len()
+    }
+
+    /// Returns the ids of the reactions that have a label.
+    pub(crate) fn labeled_reactions(&self) -> impl Iterator<Item = GlobalReactionId> + '_ {
+        self.reaction_labels.keys().copied()
+    }
+
     #[inline]
     pub fn is_main(&self, id: ReactorId) -> bool {
         self.main_reactor.unwrap() == id
@@ -226,6 +266,7 @@ impl DebugInfoRegistry {
 }
 
 /// Debug information for a single reactor.
+#[derive(Clone)]
 pub(crate) struct ReactorDebugInfo {
     /// Type name
     #[allow(unused)]
@@ -263,8 +304,12 @@ impl ReactorDebugInfo {
     }
 
     pub(crate) fn derive<R: ReactorInitializer>(&self, inst_name: &'static str) -> Self {
+        self.derive_untyped(type_name::<R::Wrapped>(), inst_name)
+    }
+
+    pub(crate) fn derive_untyped(&self, type_name: &'static str, inst_name: &'static str) -> Self {
         Self {
-            type_name: type_name::<R::Wrapped>(),
+            type_name,
             inst_name,
             inst_path: format!("{}{}/", self.inst_path, inst_name),
         }
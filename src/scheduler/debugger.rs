@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Interactive step debugger for the event loop.
+//!
+//! With [SchedulerOptions::debugger], the scheduler pauses before
+//! the first tag, and reads commands from stdin. Execution can then
+//! be resumed tag by tag, level by level, or reaction by reaction,
+//! or until a breakpoint is hit. While paused, the pending events,
+//! the reactions planned for the tag, and the ports set so far can
+//! be inspected. Type `help` for the list of commands.
+//!
+//! Reactions are executed one by one while debugging, even with
+//! the `parallel-runtime` feature. Physical time keeps advancing
+//! while the program is paused, so deadlines may be violated,
+//! unless the program uses a [VirtualClock].
+
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+
+use super::{DebugInfoProvider, EventQueue, ReactionPlan};
+use crate::assembly::TriggerId;
+use crate::*;
+
+const HELP: &str = "\
+Commands:
+  step, s          run until the next tag
+  level, l         run until the next level of reactions
+  reaction, r      run until the next reaction
+  continue, c      run until the next breakpoint
+  queue, q         show the pending events
+  plan, p          show the reactions planned for the current tag
+  ports            show the ports set so far in the current tag
+  break PATH, b    pause before the reaction, or before the tags
+                   where the trigger is present, with the given path
+  delete PATH, d   remove a breakpoint
+  breakpoints      list the breakpoints
+  help, h          show this message";
+
+/// Options of the step debugger, see [SchedulerOptions::debugger].
+#[derive(Clone, Debug, Default)]
+pub struct DebuggerOptions {
+    /// Initial breakpoints. Those are paths of reactions or
+    /// triggers, as they are displayed in logs, eg `/sink/0`
+    /// or `/sink/in`. Execution pauses before a reaction with a
+    /// breakpoint is executed, and before a tag at which an
+    /// action, timer, or watchdog with a breakpoint is triggered.
+    pub breakpoints: Vec<String>,
+}
+
+/// How far to run before pausing again.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Step {
+    Reaction,
+    Level,
+    Tag,
+    Continue,
+}
+
+/// What the debugger may inspect when it pauses.
+pub(super) struct DebugView<'a, 'x> {
+    pub tag: EventTag,
+    pub queue: &'a EventQueue<'x>,
+    pub plan: &'a ReactionPlan<'x>,
+    pub set_ports: &'a [TriggerId],
+    pub debug: DebugInfoProvider<'a>,
+}
+
+pub(super) struct Debugger {
+    breakpoints: Vec<String>,
+    step: Step,
+    /// Set when the input is closed, in which case
+    /// the program runs to completion.
+    detached: bool,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Debugger {
+    /// Create a debugger that reads commands from stdin
+    /// and writes to stderr.
+    pub(super) fn stdio(options: DebuggerOptions) -> Self {
+        Self::new(options, Box::new(io::BufReader::new(io::stdin())), Box::new(io::stderr()))
+    }
+
+    fn new(options: DebuggerOptions, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            breakpoints: options.breakpoints,
+            step: Step::Tag,
+            detached: false,
+            input,
+            output,
+        }
+    }
+
+    /// Called before the reactions of a tag are executed. The
+    /// triggers are those of the event that is processed.
+    pub(super) fn before_tag(&mut self, triggers: &[TriggerId], view: &DebugView) {
+        let breakpoint = triggers
+            .iter()
+            .map(|t| view.debug.id_registry.fmt_component(*t).to_string())
+            .find(|path| self.breakpoints.contains(path));
+        if self.step == Step::Continue && breakpoint.is_none() {
+            return;
+        }
+        let triggers: Vec<_> = triggers
+            .iter()
+            .map(|t| view.debug.id_registry.fmt_component(*t).to_string())
+            .collect();
+        let location = format!("Tag {}, triggered by [{}]", view.tag, triggers.join(", "));
+        self.pause(location, breakpoint, view)
+    }
+
+    /// Called before the reactions of a level are executed.
+    pub(super) fn before_level(&mut self, level: impl Display, view: &DebugView) {
+        if matches!(self.step, Step::Level | Step::Reaction) {
+            let location = format!("Tag {}, level {}", view.tag, level);
+            self.pause(location, None, view)
+        }
+    }
+
+    /// Called before a reaction is executed.
+    pub(super) fn before_reaction(&mut self, reaction: GlobalReactionId, view: &DebugView) {
+        let path = view.debug.display_reaction(reaction).to_string();
+        let breakpoint = self
+            .breakpoints
+            .iter()
+            .find(|b| path == **b || path.strip_prefix(b.as_str()).map_or(false, |label| label.starts_with('@')))
+            .cloned();
+        if self.step == Step::Reaction || breakpoint.is_some() {
+            let location = format!("Tag {}, reaction {}", view.tag, path);
+            self.pause(location, breakpoint, view)
+        }
+    }
+
+    /// Read and execute commands until one resumes execution.
+    fn pause(&mut self, location: String, breakpoint: Option<String>, view: &DebugView) {
+        if self.detached {
+            return;
+        }
+        if let Some(breakpoint) = breakpoint {
+            self.print(format_args!("Breakpoint {}", breakpoint));
+        }
+        self.print(format_args!("Paused before {}", location));
+        loop {
+            let line = match self.read_command() {
+                Some(line) => line,
+                None => {
+                    info!("Debugger input is closed, running to completion");
+                    self.detached = true;
+                    return;
+                }
+            };
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let arg = words.next();
+            let step = match (command, arg) {
+                ("" | "step" | "s", _) => Some(Step::Tag),
+                ("level" | "l", _) => Some(Step::Level),
+                ("reaction" | "r", _) => Some(Step::Reaction),
+                ("continue" | "c", _) => Some(Step::Continue),
+                _ => None,
+            };
+            if let Some(step) = step {
+                self.step = step;
+                return;
+            }
+            match (command, arg) {
+                ("queue" | "q", _) => {
+                    let events: Vec<_> = view.queue.iter().map(|evt| view.debug.display_event(evt)).collect();
+                    self.print(format_args!("{} pending events", events.len()));
+                    for evt in events {
+                        self.print(format_args!("  {}", evt));
+                    }
+                }
+                ("plan" | "p", _) => self.print(format_args!("{}", view.debug.display_reactions(view.plan))),
+                ("ports", _) => {
+                    let ports: Vec<_> = view
+                        .set_ports
+                        .iter()
+                        .map(|p| view.debug.id_registry.fmt_component(*p).to_string())
+                        .collect();
+                    self.print(format_args!("[{}]", ports.join(", ")))
+                }
+                ("break" | "b", Some(path)) => {
+                    if !self.breakpoints.iter().any(|b| b == path) {
+                        self.breakpoints.push(path.to_string())
+                    }
+                }
+                ("delete" | "d", Some(path)) => self.breakpoints.retain(|b| b != path),
+                ("breakpoints", _) => {
+                    let breakpoints = self.breakpoints.join(", ");
+                    self.print(format_args!("[{}]", breakpoints))
+                }
+                ("help" | "h", _) => self.print(format_args!("{}", HELP)),
+                _ => self.print(format_args!(
+                    "Unknown command '{}', type 'help' for a list of commands",
+                    line.trim()
+                )),
+            }
+        }
+    }
+
+    /// Returns None if the input is closed.
+    fn read_command(&mut self) -> Option<String> {
+        write!(self.output, "(debug) ").and_then(|_| self.output.flush()).ok();
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(line),
+            Err(e) => {
+                error!("Could not read debugger command: {}", e);
+                None
+            }
+        }
+    }
+
+    fn print(&mut self, args: std::fmt::Arguments) {
+        writeln!(self.output, "{}", args).ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Output of the debugger, shared with the test.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_debugger(breakpoints: &[&str], input: &'static str, f: impl FnOnce(&mut Debugger, &DebugView)) -> String {
+        let output = SharedOutput::default();
+        let options = DebuggerOptions {
+            breakpoints: breakpoints.iter().map(|b| b.to_string()).collect(),
+        };
+        let mut debugger = Debugger::new(options, Box::new(io::Cursor::new(input)), Box::new(output.clone()));
+
+        let mut id_registry = DebugInfoRegistry::new();
+        let main = ReactorId::new(0);
+        id_registry.record_reactor(main, ReactorDebugInfo::test_named("main"));
+        id_registry.set_id_range(main, TriggerId::FIRST_REGULAR..TriggerId::FIRST_REGULAR);
+        let queue = EventQueue::default();
+        let view = DebugView {
+            tag: EventTag::ORIGIN,
+            queue: &queue,
+            plan: &None,
+            set_ports: &[],
+            debug: DebugInfoProvider { id_registry: &id_registry },
+        };
+        f(&mut debugger, &view);
+
+        let output = output.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_step_commands() {
+        let output = run_debugger(&[], "queue\nlevel\nc\n", |debugger, view| {
+            // pauses before the first tag, the queue command does not resume
+            debugger.before_tag(&[TriggerId::STARTUP], view);
+            assert_eq!(debugger.step, Step::Level);
+            debugger.before_level(0, view);
+            assert_eq!(debugger.step, Step::Continue);
+            // no breakpoint, so this does not pause
+            debugger.before_tag(&[TriggerId::SHUTDOWN], view);
+        });
+
+        assert!(output.contains("triggered by [main/startup]"), "{}", output);
+        assert!(output.contains("0 pending events"), "{}", output);
+        assert!(!output.contains("shutdown"), "{}", output);
+    }
+
+    #[test]
+    fn test_trigger_breakpoint() {
+        let output = run_debugger(&["main/shutdown"], "c\nc\n", |debugger, view| {
+            debugger.before_tag(&[TriggerId::STARTUP], view);
+            debugger.before_tag(&[TriggerId::SHUTDOWN], view);
+            // the input is closed, the debugger does not pause anymore
+            debugger.before_tag(&[TriggerId::SHUTDOWN], view);
+            assert!(debugger.detached);
+        });
+
+        assert!(output.contains("Breakpoint main/shutdown"), "{}", output);
+    }
+}
@@ -33,10 +33,10 @@ use std::sync::Arc;
 use index_vec::{Idx, IndexVec};
 use petgraph::graph::{DiGraph, NodeIndex};
 use petgraph::visit::EdgeRef;
-use petgraph::Direction::Outgoing;
+use petgraph::Direction::{Incoming, Outgoing};
 use vecmap::{Entry as VEntry, KeyRef, VecMap};
 
-use super::ReactionPlan;
+use super::{ModeInfo, ReactionPlan};
 use crate::assembly::*;
 use crate::impl_types::GlobalIdImpl;
 use crate::scheduler::dependencies::NodeKind::MultiportUpstream;
@@ -44,9 +44,9 @@ use crate::*;
 
 type GraphIx = NodeIndex<GlobalIdImpl>;
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 enum NodeKind {
-    /// startup/shutdown
+    /// startup/shutdown/modes
     Special,
     MultiportUpstream,
     Port,
@@ -56,6 +56,7 @@ enum NodeKind {
 }
 
 /// Weight of graph nodes.
+#[derive(Clone)]
 struct GraphNode {
     kind: NodeKind,
     id: GraphId,
@@ -95,7 +96,9 @@ type DepGraphImpl = DiGraph<GraphNode, EdgeWeight, GlobalIdImpl>;
 /// One global instance is built during the assembly process (see [RootAssembler]).
 /// Initialization completes when that instance is turned into
 /// a [DataflowInfo], which is the data structure used at runtime.
-///
+/// The scheduler keeps it to add reactors to the running program,
+/// see [ReactionCtx::mutate].
+#[derive(Clone)]
 pub(super) struct DepGraph {
     /// Instantaneous data flow. Must be acyclic. Edges from
     /// reactions to actions are not represented, as they are
@@ -120,6 +123,14 @@ pub(super) struct DepGraph {
     multiport_containment: HashMap<GraphId, TriggerId>,
     /// Map of multiport ID -> range of IDs for its channels
     multiport_ranges: VecMap<TriggerId, Range<TriggerId>>,
+
+    /// Deadlines declared on reactions. These are not part of
+    /// the graph, they're just forwarded to the [DataflowInfo].
+    deadlines: HashMap<GlobalReactionId, Deadline>,
+
+    /// Modes declared in the program. These are not part of
+    /// the graph either, they're taken by the scheduler.
+    pub(super) modes: ModeInfo,
 }
 
 impl Debug for GraphNode {
@@ -140,6 +151,8 @@ impl DepGraph {
             ix_by_id: Default::default(),
             multiport_containment: Default::default(),
             multiport_ranges: Default::default(),
+            deadlines: Default::default(),
+            modes: Default::default(),
         };
         ich.record_special(TriggerId::STARTUP);
         ich.record_special(TriggerId::SHUTDOWN);
@@ -220,10 +233,20 @@ impl DepGraph {
         self.record(GraphId::Trigger(id), NodeKind::Action);
     }
 
+    pub(super) fn record_watchdog(&mut self, id: TriggerId) {
+        self.record(GraphId::Trigger(id), NodeKind::Action);
+    }
+
     pub(super) fn record_timer(&mut self, id: TriggerId) {
         self.record(GraphId::Trigger(id), NodeKind::Timer);
     }
 
+    /// A mode triggers the reactions that execute
+    /// when it is entered, like startup.
+    pub(super) fn record_mode(&mut self, id: TriggerId) {
+        self.record_special(id);
+    }
+
     pub(super) fn record_reaction(&mut self, id: GlobalReactionId) {
         self.record(GraphId::Reaction(id), NodeKind::Reaction);
     }
@@ -243,6 +266,24 @@ impl DepGraph {
         );
     }
 
+    /// Returns the port that shares its value with the given
+    /// port through bindings, and that can be set explicitly.
+    /// This is the given port if it is not bound.
+    pub(super) fn binding_root(&self, port: TriggerId) -> TriggerId {
+        let mut ix = self.get_ix(port.into());
+        while let Some(upstream) = self
+            .dataflow
+            .neighbors_directed(ix, Incoming)
+            .find(|&n| self.dataflow[n].kind == NodeKind::Port)
+        {
+            ix = upstream;
+        }
+        match self.dataflow[ix].id {
+            GraphId::Trigger(id) => id,
+            GraphId::Reaction(_) => unreachable!("Port node has a reaction id"),
+        }
+    }
+
     #[cfg(test)]
     pub fn port_bind_untyped(&mut self, p1: TriggerId, p2: TriggerId) {
         // upstream (settable) -> downstream (bound)
@@ -250,6 +291,19 @@ impl DepGraph {
             .add_edge(self.get_ix(p1.into()), self.get_ix(p2.into()), EdgeWeight::Default);
     }
 
+    /// Returns whether a reaction or another port may set
+    /// the given port.
+    pub(super) fn has_upstream(&self, port: TriggerId) -> bool {
+        let ix = self.get_ix(port.into());
+        self.dataflow
+            .neighbors_directed(ix, Incoming)
+            .any(|n| match self.dataflow[n].kind {
+                // the fake node of a multiport only forwards what sets the whole multiport
+                MultiportUpstream => self.dataflow.neighbors_directed(n, Incoming).next().is_some(),
+                _ => true,
+            })
+    }
+
     pub fn triggers_reaction(&mut self, trigger: TriggerId, reaction: GlobalReactionId) {
         self.trigger_to_reaction_edge(trigger, reaction, EdgeWeight::Default);
     }
@@ -274,6 +328,13 @@ impl DepGraph {
         self.dataflow.add_edge(trigger_ix, reaction_ix, weight);
     }
 
+    /// Records that the given reaction has a deadline. The
+    /// handler is a reaction of the same reactor.
+    pub fn reaction_deadline(&mut self, reaction: GlobalReactionId, max_lag: Duration, handler: LocalReactionId) {
+        let prev = self.deadlines.insert(reaction, Deadline { max_lag, handler });
+        debug_assert!(prev.is_none(), "Declared several deadlines for reaction {:?}", reaction);
+    }
+
     pub fn reaction_effects(&mut self, reaction: GlobalReactionId, trigger: TriggerId) {
         // reaction -> trigger
         self.dataflow
@@ -370,6 +431,18 @@ impl ReactionLevelInfo {
     }
 }
 
+/// A deadline on a reaction. If the reaction is executed
+/// with a lag (physical time - logical time) greater than
+/// `max_lag`, the handler is executed instead of the reaction body.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) struct Deadline {
+    /// Maximum lag of physical time behind logical time.
+    pub max_lag: Duration,
+    /// Local id of the reaction that handles a violation.
+    /// It belongs to the same reactor as the constrained reaction.
+    pub handler: LocalReactionId,
+}
+
 /// Pre-calculated dependency information,
 /// using the dependency graph
 pub(super) struct DataflowInfo {
@@ -377,18 +450,23 @@ pub(super) struct DataflowInfo {
     /// to be scheduled when it is triggered.
     /// Todo: many of those are never asked for, eg those of bound ports
     trigger_to_plan: IndexVec<TriggerId, Arc<ExecutableReactions<'static>>>,
+
+    /// Deadlines of reactions. Most programs have none, so
+    /// we check for emptiness before hashing anything.
+    deadlines: HashMap<GlobalReactionId, Deadline>,
 }
 
 impl DataflowInfo {
-    pub fn new(mut graph: DepGraph) -> Result<Self, AssemblyError> {
+    pub fn new(graph: &DepGraph) -> Result<Self, AssemblyError> {
         let level_info = ReactionLevelInfo::new(graph.number_reactions_by_level()?);
-        let trigger_to_plan = Self::collect_trigger_to_plan(&mut graph, &level_info);
+        let trigger_to_plan = Self::collect_trigger_to_plan(graph, &level_info);
+        let deadlines = graph.deadlines.clone();
 
-        Ok(DataflowInfo { trigger_to_plan })
+        Ok(DataflowInfo { trigger_to_plan, deadlines })
     }
 
     fn collect_trigger_to_plan(
-        DepGraph { dataflow, .. }: &mut DepGraph,
+        DepGraph { dataflow, .. }: &DepGraph,
         level_info: &ReactionLevelInfo,
     ) -> IndexVec<TriggerId, Arc<ExecutableReactions<'static>>> {
         let mut result = IndexVec::with_capacity(dataflow.node_count() / 2);
@@ -458,6 +536,16 @@ impl DataflowInfo {
     pub fn reactions_triggered_by(&self, trigger: &TriggerId) -> &ExecutableReactions<'static> {
         &self.trigger_to_plan[*trigger]
     }
+
+    /// Returns the deadline declared on the given reaction, if any.
+    #[inline]
+    pub fn deadline_of(&self, reaction: GlobalReactionId) -> Option<&Deadline> {
+        if self.deadlines.is_empty() {
+            None
+        } else {
+            self.deadlines.get(&reaction)
+        }
+    }
 }
 
 cfg_if! {
@@ -516,6 +604,10 @@ impl Level {
     pub fn iter(&self) -> impl Iterator<Item = GlobalReactionId> + '_ {
         self.0.iter().cloned()
     }
+
+    fn retain(&mut self, mut f: impl FnMut(GlobalReactionId) -> bool) {
+        self.0.retain(|id| f(*id))
+    }
 }
 
 impl<'a> IntoIterator for &'a Level {
@@ -655,6 +747,23 @@ impl<'x> ExecutableReactions<'x> {
         }
     }
 
+    /// Keep only the reactions for which the predicate returns
+    /// true. Levels that become empty are removed, and borrowed
+    /// levels are only cloned if they are modified.
+    pub fn retain(&mut self, mut f: impl FnMut(GlobalReactionId) -> bool) {
+        self.levels.retain(|_, level| {
+            if !level.iter().all(&mut f) {
+                level.to_mut().retain(&mut f);
+            }
+            !level.is_empty()
+        });
+    }
+
+    /// Whether there are no reactions to execute.
+    pub fn is_empty(&self) -> bool {
+        self.levels.max_key().is_none()
+    }
+
     pub fn insert(&mut self, reaction: GlobalReactionId, level_ix: LevelIx) {
         match self.levels.entry(level_ix) {
             VEntry::Vacant(e) => {
@@ -713,6 +822,7 @@ impl Display for ExecutableReactions<'_> {
 pub mod test {
     use super::*;
     use crate::impl_types::{ReactionIdImpl, ReactorIdImpl};
+    use crate::scheduler::events::{Event, EventQueue};
 
     struct TestGraphFixture {
         graph: DepGraph,
@@ -944,6 +1054,94 @@ pub mod test {
         assert_eq!(levels.len(), 120);
     }
 
+    #[test]
+    fn test_cancelled_trigger_is_removed_from_event() {
+        let mut test = TestGraphFixture::new();
+
+        let mut builder = test.new_reactor("main");
+        let [n1, n2] = builder.new_reactions();
+        let [a, b] = builder.new_ports(["a", "b"]);
+        drop(builder);
+
+        test.graph.triggers_reaction(a, n1);
+        test.graph.triggers_reaction(b, n2);
+
+        let dataflow = DataflowInfo::new(&test.graph).map_err(|e| e.lift(&test.debug_info)).unwrap();
+        let tag = EventTag::offset(Duration::from_millis(1), 0);
+        let mut queue = EventQueue::default();
+        queue.push(Event::execute(tag, a, Cow::Borrowed(dataflow.reactions_triggered_by(&a))));
+        queue.push(Event::execute(tag, b, Cow::Borrowed(dataflow.reactions_triggered_by(&b))));
+
+        assert!(queue.cancel(tag, a, &dataflow));
+        assert!(!queue.cancel(tag, a, &dataflow));
+        let plan = queue.take_earliest().unwrap().reactions.unwrap();
+        let reactions: Vec<_> = plan.batches().flat_map(|(_, level)| level.iter()).collect();
+        assert_eq!(vec![n2], reactions);
+
+        queue.push(Event::execute(tag, a, Cow::Borrowed(dataflow.reactions_triggered_by(&a))));
+        assert!(queue.cancel(tag, a, &dataflow));
+        assert!(queue.take_earliest().is_none());
+    }
+
+    #[test]
+    fn test_cancelling_the_last_trigger_of_a_tag() {
+        let mut test = TestGraphFixture::new();
+
+        let mut builder = test.new_reactor("main");
+        let [n1, n2] = builder.new_reactions();
+        let [a, b] = builder.new_ports(["a", "b"]);
+        drop(builder);
+
+        test.graph.triggers_reaction(a, n1);
+        test.graph.triggers_reaction(b, n2);
+
+        let dataflow = DataflowInfo::new(&test.graph).map_err(|e| e.lift(&test.debug_info)).unwrap();
+        let [t1, t2, t3] = [1, 2, 3].map(|ms| EventTag::offset(Duration::from_millis(ms), 0));
+        let mut queue = EventQueue::default();
+        queue.push(Event::execute(t1, a, Cow::Borrowed(dataflow.reactions_triggered_by(&a))));
+        queue.push(Event::execute(t2, b, Cow::Borrowed(dataflow.reactions_triggered_by(&b))));
+        queue.push(Event::execute(t3, a, Cow::Borrowed(dataflow.reactions_triggered_by(&a))));
+        queue.push(Event::terminate_at(t3));
+
+        // the earliest tag is removed, the next one becomes the earliest
+        assert!(queue.cancel(t1, a, &dataflow));
+        assert_eq!(queue.earliest_tag(), Some(t2));
+        assert!(!queue.cancel(t1, a, &dataflow));
+
+        // the tag where the program terminates is kept, without reactions
+        assert!(queue.cancel(t3, a, &dataflow));
+        assert_eq!(queue.take_earliest().map(|evt| evt.tag), Some(t2));
+        let evt = queue.take_earliest().unwrap();
+        assert_eq!(evt.tag, t3);
+        assert!(evt.terminate);
+        assert!(evt.reactions.is_none());
+        assert!(queue.take_earliest().is_none());
+    }
+
+    #[test]
+    fn test_deadline_is_forwarded_to_dataflow() {
+        let mut test = TestGraphFixture::new();
+
+        let mut builder = test.new_reactor("main");
+        let [n1, n2, handler] = builder.new_reactions();
+        let [p0] = builder.new_ports(["p0"]);
+        drop(builder);
+
+        test.graph.reaction_effects(n1, p0);
+        test.graph.triggers_reaction(p0, n2);
+        test.graph.reaction_deadline(n2, Duration::from_millis(2), handler.0.local());
+
+        let dataflow = DataflowInfo::new(&test.graph).map_err(|e| e.lift(&test.debug_info)).unwrap();
+        assert_eq!(
+            dataflow.deadline_of(n2),
+            Some(&Deadline {
+                max_lag: Duration::from_millis(2),
+                handler: handler.0.local()
+            })
+        );
+        assert_eq!(dataflow.deadline_of(n1), None);
+    }
+
     #[test]
     fn test_graph_dump() {
         let mut test = TestGraphFixture::new();
@@ -0,0 +1,106 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Errors that prevent a program from running.
+
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::assembly::AssemblyFailure;
+
+/// An error that prevents a program from running to
+/// completion, see [SyncScheduler::try_run_main](crate::SyncScheduler::try_run_main).
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The program could not be assembled, eg because
+    /// its dependency graph is cyclic.
+    Assembly(AssemblyFailure),
+    /// The connection to the RTI could not be established,
+    /// or was lost, for a federate.
+    Federation(io::Error),
+    /// A file requested in the [SchedulerOptions](crate::SchedulerOptions)
+    /// could not be written, eg the trace file.
+    Io(io::Error),
+    /// The scheduler thread panicked. Contains the message
+    /// of the panic, if it has one.
+    Panicked(String),
+    /// A reaction failed, and the program was shut down
+    /// because of [FailurePolicy::Shutdown](crate::FailurePolicy::Shutdown).
+    ReactionFailed { reaction: String, message: String },
+    /// The checkpoint to resume from could not be read,
+    /// or does not match the program.
+    Checkpoint(io::Error),
+    /// The recording to replay could not be read, or does
+    /// not match the program.
+    Replay(io::Error),
+}
+
+impl RuntimeError {
+    /// Convert the payload of a panic into an error.
+    pub(crate) fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        RuntimeError::Panicked(panic_message(payload))
+    }
+}
+
+/// Returns the message of a panic, given its payload.
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast_ref::<&'static str>() {
+            Some(message) => message.to_string(),
+            None => "<non-string panic payload>".to_string(),
+        },
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::Assembly(e) => write!(f, "Error while assembling the program: {}", e),
+            RuntimeError::Federation(e) => write!(f, "Connection to the RTI failed: {}", e),
+            RuntimeError::Io(e) => write!(f, "I/O error: {}", e),
+            RuntimeError::Panicked(message) => write!(f, "The scheduler panicked: {}", message),
+            RuntimeError::ReactionFailed { reaction, message } => write!(f, "Reaction {} failed: {}", reaction, message),
+            RuntimeError::Checkpoint(e) => write!(f, "Could not resume from checkpoint: {}", e),
+            RuntimeError::Replay(e) => write!(f, "Could not replay recording: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RuntimeError::Assembly(e) => Some(e),
+            RuntimeError::Federation(e) | RuntimeError::Io(e) | RuntimeError::Checkpoint(e) | RuntimeError::Replay(e) => Some(e),
+            RuntimeError::Panicked(_) | RuntimeError::ReactionFailed { .. } => None,
+        }
+    }
+}
+
+impl From<AssemblyFailure> for RuntimeError {
+    fn from(e: AssemblyFailure) -> Self {
+        RuntimeError::Assembly(e)
+    }
+}
@@ -1,8 +1,12 @@
 use std::borrow::Cow;
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt::{Display, Formatter};
 use std::time::Instant;
 
+use smallvec::{smallvec, SmallVec};
+
 use super::ReactionPlan;
 use crate::scheduler::dependencies::{DataflowInfo, ExecutableReactions};
 use crate::triggers::TriggerId;
@@ -102,14 +106,6 @@ impl EventTag {
             microstep: self.microstep + 1,
         }
     }
-
-    #[inline]
-    pub(crate) fn now(t0: Instant) -> Self {
-        Self {
-            offset_from_t0: Instant::now() - t0,
-            microstep: MicroStep::ZERO,
-        }
-    }
 }
 
 impl Display for EventTag {
@@ -132,13 +128,17 @@ impl Display for EventTag {
 /// [self::AsyncCtx] may only communicate with
 /// the scheduler by sending events.
 #[derive(Debug)]
-pub(super) struct Event<'x> {
+pub struct Event<'x> {
     /// The tag at which the reactions to this event must be executed.
     /// This is always > to the latest *processed* tag, by construction
     /// of the reactor application.
     pub(super) tag: EventTag,
     /// A set of reactions to execute.
     pub reactions: ReactionPlan<'x>,
+    /// The triggers whose reactions make up [Self::reactions].
+    /// This is used to recompute the plan when one of them
+    /// is cancelled.
+    pub triggers: SmallVec<[TriggerId; 1]>,
     /// Whether we should terminate the application at
     /// the tag of this event (after processing the tag).
     pub terminate: bool,
@@ -148,14 +148,25 @@ impl<'x> Event<'x> {
     pub fn absorb(&mut self, other: Event<'x>) {
         debug_assert_eq!(self.tag, other.tag);
         self.reactions = ExecutableReactions::merge_cows(self.reactions.take(), other.reactions);
+        self.triggers.extend(other.triggers);
         self.terminate |= other.terminate;
     }
 
-    pub fn execute(tag: EventTag, reactions: Cow<'x, ExecutableReactions<'x>>) -> Self {
-        Self { tag, reactions: Some(reactions), terminate: false }
+    pub fn execute(tag: EventTag, trigger: TriggerId, reactions: Cow<'x, ExecutableReactions<'x>>) -> Self {
+        Self {
+            tag,
+            reactions: Some(reactions),
+            triggers: smallvec![trigger],
+            terminate: false,
+        }
     }
     pub fn terminate_at(tag: EventTag) -> Self {
-        Self { tag, reactions: None, terminate: true }
+        Self {
+            tag,
+            reactions: None,
+            triggers: SmallVec::new(),
+            terminate: true,
+        }
     }
 }
 
@@ -173,13 +184,18 @@ pub(super) struct PhysicalEvent {
 
 impl PhysicalEvent {
     /// Turn a [PhysicalEvent] into an [Event] within the scheduler.
-    pub(super) fn make_executable(self, dataflow: &DataflowInfo) -> Event {
+    /// Returns None if this is a [wake-up](Self::wake_up) event.
+    pub(super) fn make_executable(self, dataflow: &DataflowInfo) -> Option<Event> {
+        if self.is_wake_up() {
+            return None;
+        }
         let PhysicalEvent { tag, trigger_id, terminate } = self;
-        Event {
+        Some(Event {
             tag,
             terminate,
             reactions: trigger_id.map(|id| Cow::Borrowed(dataflow.reactions_triggered_by(&id))),
-        }
+            triggers: trigger_id.into_iter().collect(),
+        })
     }
 
     pub fn trigger(tag: EventTag, trigger: TriggerId) -> Self {
@@ -188,46 +204,183 @@ impl PhysicalEvent {
     pub fn terminate_at(tag: EventTag) -> Self {
         Self { tag, trigger_id: None, terminate: true }
     }
+    /// An event that does nothing but interrupt the scheduler
+    /// if it's waiting, eg when the RTI grants a new tag.
+    pub fn wake_up(tag: EventTag) -> Self {
+        Self { tag, trigger_id: None, terminate: false }
+    }
+
+    pub fn is_wake_up(&self) -> bool {
+        self.trigger_id.is_none() && !self.terminate
+    }
 }
 
 /// A queue of pending [Event]s. Events are ordered by tag,
 /// so this is not a FIFO queue.
+///
+/// Events are indexed by tag, so that an event pushed at the tag
+/// of a pending event is merged into it (see [Event::absorb]),
+/// and a binary heap orders the tags. Pushing, removing and
+/// taking the earliest event are O(log n).
 #[derive(Default)]
-pub(super) struct EventQueue<'x> {
-    /// This list is sorted by the tag of each event (in ascending order).
-    ///
-    /// But insertion is at worse O(n)... And it's easy to build
-    /// a pathological program where this worse case is always hit.
-    /// Theoretically using a tree/ heap would be useful here.
-    ///        .
-    ///       ..
-    ///      ...
-    ///     ....
-    value_list: VecDeque<Event<'x>>,
+pub struct EventQueue<'x> {
+    /// Tags of the pending events, the earliest at the top.
+    /// When an event is removed, its tag stays in the heap
+    /// until it reaches the top, where it is discarded. So the
+    /// top is always the tag of a pending event, but a tag may
+    /// occur several times.
+    tags: BinaryHeap<Reverse<EventTag>>,
+    /// Pending events, by tag.
+    events: HashMap<EventTag, Event<'x>>,
 }
 
 impl<'x> EventQueue<'x> {
     /// Removes and returns the earliest tag
     pub fn take_earliest(&mut self) -> Option<Event<'x>> {
-        self.value_list.pop_front()
+        let Reverse(tag) = self.tags.pop()?;
+        let evt = self.events.remove(&tag);
+        debug_assert!(evt.is_some(), "Tag at the top of the heap has no event");
+        self.discard_removed_tags();
+        evt
     }
 
-    // todo perf: we could make a more optimal function to push a
-    //  lot of events at once. Consider the following algorithm:
-    //  - start with a sorted `self.value_list` and a (non-sorted) `new_evts: Vec<Event>`
-    //  - sort the new events in place (in a Cow maybe). They'll
-    //  probably come in already sorted but we can't assume this.
-    //  Use an algorithm that best-cases for sorted data. (eg https://crates.io/crates/dmsort)
-    //  - take the earliest new event and binary search to insert it.
-    //  - then do the same thing but only on the remaining (to the right)
-    //  portion of `self.value_list`. Basically the routine of an insertion
-    //  sort.
+    /// Returns the tag of the earliest event, if any.
+    pub fn earliest_tag(&self) -> Option<EventTag> {
+        self.tags.peek().map(|Reverse(tag)| *tag)
+    }
 
     /// Push an event into the heap.
-    pub(super) fn push(&mut self, evt: Event<'x>) {
-        match self.value_list.binary_search_by_key(&evt.tag, |e| e.tag) {
-            Ok(idx) => self.value_list[idx].absorb(evt),
-            Err(idx) => self.value_list.insert(idx, evt),
+    pub fn push(&mut self, evt: Event<'x>) {
+        match self.events.entry(evt.tag) {
+            Entry::Occupied(mut e) => e.get_mut().absorb(evt),
+            Entry::Vacant(e) => {
+                self.tags.push(Reverse(evt.tag));
+                e.insert(evt);
+            }
+        }
+    }
+
+    /// Push all the given events. This is faster than pushing
+    /// them one by one if many of them have new tags, as the
+    /// heap is then rebuilt at once.
+    pub fn extend(&mut self, events: impl IntoIterator<Item = Event<'x>>) {
+        let mut new_tags = Vec::new();
+        for evt in events {
+            match self.events.entry(evt.tag) {
+                Entry::Occupied(mut e) => e.get_mut().absorb(evt),
+                Entry::Vacant(e) => {
+                    new_tags.push(Reverse(evt.tag));
+                    e.insert(evt);
+                }
+            }
+        }
+        self.tags.extend(new_tags);
+    }
+
+    /// Remove and return the event at the given tag, if any.
+    pub fn remove(&mut self, tag: EventTag) -> Option<Event<'x>> {
+        let evt = self.events.remove(&tag)?;
+        self.discard_removed_tags();
+        Some(evt)
+    }
+
+    /// Returns the pending events, in order. This sorts the
+    /// events, it is meant for debugging and tests.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Event<'x>> + '_ {
+        let mut events: Vec<&Event<'x>> = self.events.values().collect();
+        events.sort_unstable_by_key(|evt| evt.tag);
+        events.into_iter()
+    }
+
+    /// Cancel the triggering of the given trigger at the given
+    /// tag. The reactions of the event at that tag are recomputed
+    /// from its remaining triggers, and the event is removed
+    /// altogether if none remain. Returns whether an event was
+    /// affected.
+    pub(super) fn cancel(&mut self, tag: EventTag, trigger: TriggerId, dataflow: &'x DataflowInfo) -> bool {
+        let evt = match self.events.get_mut(&tag) {
+            Some(evt) => evt,
+            None => return false,
+        };
+        let len_before = evt.triggers.len();
+        evt.triggers.retain(|t| *t != trigger);
+        if evt.triggers.len() == len_before {
+            return false;
+        }
+
+        if evt.triggers.is_empty() && !evt.terminate {
+            self.remove(tag);
+        } else {
+            evt.reactions = Self::plan_triggers(&evt.triggers, dataflow);
+        }
+        true
+    }
+
+    /// Recompute the reactions of the pending events from their
+    /// triggers, after the dependency information has changed.
+    /// Events without triggers are left as they are.
+    pub(super) fn replan(&mut self, dataflow: &'x DataflowInfo) {
+        for evt in self.events.values_mut().filter(|evt| !evt.triggers.is_empty()) {
+            evt.reactions = Self::plan_triggers(&evt.triggers, dataflow);
+        }
+    }
+
+    fn plan_triggers(triggers: &[TriggerId], dataflow: &'x DataflowInfo) -> ReactionPlan<'x> {
+        triggers.iter().fold(None, |plan, t| {
+            let reactions = Some(Cow::Borrowed(dataflow.reactions_triggered_by(t)));
+            ExecutableReactions::merge_cows(plan, reactions)
+        })
+    }
+
+    /// Pop the tags of removed events from the top of the heap.
+    fn discard_removed_tags(&mut self) {
+        while let Some(Reverse(tag)) = self.tags.peek() {
+            if self.events.contains_key(tag) {
+                break;
+            }
+            self.tags.pop();
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn take_tags(queue: &mut EventQueue) -> Vec<EventTag> {
+        std::iter::from_fn(|| queue.take_earliest()).map(|evt| evt.tag).collect()
+    }
+
+    #[test]
+    fn test_events_are_ordered_and_merged() {
+        let mut queue = EventQueue::default();
+        queue.push(Event::terminate_at(tag!(T0 + 20 ms)));
+        queue.push(Event::terminate_at(tag!(T0 + 5 ms)));
+        queue.push(Event::terminate_at(tag!(T0 + 20 ms)));
+        queue.extend([tag!(T0 + 10 ms), tag!(T0 + 5 ms), tag!(T0 + 30 ms)].map(Event::terminate_at));
+
+        assert_eq!(queue.earliest_tag(), Some(tag!(T0 + 5 ms)));
+        let tags: Vec<_> = queue.iter().map(|evt| evt.tag).collect();
+        assert_eq!(
+            tags,
+            vec![tag!(T0 + 5 ms), tag!(T0 + 10 ms), tag!(T0 + 20 ms), tag!(T0 + 30 ms)]
+        );
+        assert_eq!(take_tags(&mut queue), tags);
+        assert_eq!(queue.earliest_tag(), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut queue = EventQueue::default();
+        queue.extend([tag!(T0 + 5 ms), tag!(T0 + 10 ms), tag!(T0 + 15 ms)].map(Event::terminate_at));
+
+        assert!(queue.remove(tag!(T0 + 5 ms)).is_some());
+        assert!(queue.remove(tag!(T0 + 5 ms)).is_none());
+        assert_eq!(queue.earliest_tag(), Some(tag!(T0 + 10 ms)));
+
+        // the tag of the removed event is still in the heap
+        queue.remove(tag!(T0 + 15 ms));
+        queue.push(Event::terminate_at(tag!(T0 + 15 ms)));
+        assert_eq!(take_tags(&mut queue), vec![tag!(T0 + 10 ms), tag!(T0 + 15 ms)]);
+    }
+}
@@ -0,0 +1,244 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Connection of a federate to the RTI, see [crate::federated].
+
+use std::collections::HashMap;
+use std::io;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::PhysicalEvent;
+use crate::assembly::TriggerLike;
+use crate::federated::protocol::Message;
+use crate::federated::{FederateId, FederateOptions, NetworkChannel, FOREVER};
+use crate::util::reconnectable::{SendError, Sender};
+use crate::*;
+
+/// Network inputs of a federate, by port number.
+pub(crate) type NetworkInputs = HashMap<u32, PhysicalActionRef<Vec<u8>>>;
+
+/// The connection of a federate to the RTI.
+pub(crate) struct FederateClient {
+    id: FederateId,
+    /// Used to send messages to the RTI. Messages may be sent
+    /// concurrently by reactions and by the scheduler.
+    writer: Mutex<TcpStream>,
+    /// State shared with the thread receiving messages from the RTI.
+    state: Arc<Mutex<ClientState>>,
+}
+
+#[derive(Default)]
+struct ClientState {
+    /// Latest tag granted by the RTI.
+    granted: Option<EventTag>,
+    /// Latest tag granted provisionally by the RTI. Messages
+    /// may still arrive for that tag, so only the tags strictly
+    /// before it may be processed.
+    provisionally_granted: Option<EventTag>,
+    /// Latest tag the scheduler started processing.
+    started: Option<EventTag>,
+    /// Latest next event tag sent to the RTI.
+    next_event: Option<EventTag>,
+    /// The error that closed the connection to the RTI, if
+    /// it was closed.
+    connection_error: Option<io::Error>,
+}
+
+impl FederateClient {
+    /// Connect to the RTI and wait for the start of the
+    /// federation. Returns the start time, which is the
+    /// same for all federates.
+    pub(super) fn connect(options: &FederateOptions) -> io::Result<(Self, Instant)> {
+        info!("Connecting to the RTI at {}...", options.rti_address);
+        let mut stream = TcpStream::connect(options.rti_address)?;
+        stream.set_nodelay(true)?;
+
+        Message::Register {
+            federate: options.id,
+            upstream: options.upstream.clone(),
+        }
+        .write_to(&mut stream)?;
+        let start_time = match Message::read_from(&mut stream)? {
+            Message::StartTime(nanos) => UNIX_EPOCH + Duration::from_nanos(nanos),
+            msg => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected start time from the RTI, got {:?}", msg),
+                ))
+            }
+        };
+
+        // convert the start time to the monotonic clock of this process
+        let now = Instant::now();
+        let initial_time = match start_time.duration_since(SystemTime::now()) {
+            Ok(ahead) => now + ahead,
+            Err(e) => now.checked_sub(e.duration()).unwrap_or(now),
+        };
+        info!("Registered as {}", options.id);
+
+        let client = Self {
+            id: options.id,
+            writer: Mutex::new(stream),
+            state: Default::default(),
+        };
+        Ok((client, initial_time))
+    }
+
+    /// Spawn the thread that receives messages from the RTI.
+    /// Tagged messages schedule the network input they target,
+    /// grants are recorded and wake up the scheduler.
+    pub(super) fn spawn_receiver(&self, tx: Sender<PhysicalEvent>, inputs: NetworkInputs) -> io::Result<()> {
+        let mut stream = self.writer.lock().unwrap().try_clone()?;
+        let state = self.state.clone();
+        let id = self.id;
+
+        std::thread::spawn(move || loop {
+            let sent = match Message::read_from(&mut stream) {
+                Ok(Message::TagAdvanceGrant(tag)) => {
+                    trace!("{} was granted tag {}", id, tag);
+                    let mut state = state.lock().unwrap();
+                    state.granted = state.granted.max(Some(tag));
+                    tx.send(PhysicalEvent::wake_up(tag)).map_err(|_| ())
+                }
+                Ok(Message::ProvisionalTagAdvanceGrant(tag)) => {
+                    trace!("{} was granted tag {} provisionally", id, tag);
+                    let mut state = state.lock().unwrap();
+                    state.provisionally_granted = state.provisionally_granted.max(Some(tag));
+                    tx.send(PhysicalEvent::wake_up(tag)).map_err(|_| ())
+                }
+                Ok(Message::TaggedMessage { channel, tag, payload }) => match inputs.get(&channel.port) {
+                    Some(input) => deliver_message(&state, &tx, input, tag, payload).map_err(|_| ()),
+                    None => {
+                        warn!("{} received a message for unknown network input {}", id, channel.port);
+                        Ok(())
+                    }
+                },
+                Ok(msg) => {
+                    warn!("{} received unexpected message {:?}", id, msg);
+                    Ok(())
+                }
+                Err(e) => {
+                    debug!("Connection to the RTI was closed: {}", e);
+                    state.lock().unwrap().connection_error = Some(e);
+                    // wake up the scheduler if it waits for a grant
+                    tx.send(PhysicalEvent::wake_up(FOREVER)).ok();
+                    Err(())
+                }
+            };
+            if sent.is_err() {
+                break;
+            }
+        });
+        Ok(())
+    }
+
+    /// Whether the RTI has granted the given tag. A provisional
+    /// grant only covers the tags before the granted one, as
+    /// network inputs are not known to be absent at that tag.
+    pub(super) fn is_granted(&self, tag: EventTag) -> bool {
+        let state = self.state.lock().unwrap();
+        state.granted.map_or(false, |granted| granted >= tag)
+            || state.provisionally_granted.map_or(false, |granted| granted > tag)
+    }
+
+    /// Returns the error that closed the connection to the
+    /// RTI, if it was closed. It is only returned once.
+    pub(super) fn take_connection_error(&self) -> Option<io::Error> {
+        self.state.lock().unwrap().connection_error.take()
+    }
+
+    /// Record that the scheduler starts processing the given
+    /// tag. Messages received for that tag or an earlier one
+    /// from now on are delayed to the next microstep.
+    pub(super) fn start_tag(&self, tag: EventTag) {
+        self.state.lock().unwrap().started = Some(tag);
+    }
+
+    /// Send the next tag this federate wants to process (NET).
+    pub(super) fn send_next_event_tag(&self, tag: EventTag) {
+        let mut state = self.state.lock().unwrap();
+        if state.next_event != Some(tag) {
+            state.next_event = Some(tag);
+            drop(state);
+            self.send(Message::NextEventTag(tag))
+        }
+    }
+
+    /// Notify the RTI that the given tag has been processed (LTC).
+    pub(super) fn send_logical_tag_complete(&self, tag: EventTag) {
+        self.send(Message::LogicalTagComplete(tag))
+    }
+
+    /// Send a message to another federate, through the RTI.
+    pub(super) fn send_tagged_message(&self, channel: NetworkChannel, tag: EventTag, payload: Vec<u8>) -> io::Result<()> {
+        self.write(Message::TaggedMessage { channel, tag, payload })
+    }
+
+    /// Notify the RTI that this federate has shut down.
+    pub(super) fn resign(&self) {
+        self.send(Message::Resign)
+    }
+
+    /// Send a message to the RTI, logging errors.
+    fn send(&self, msg: Message) {
+        if let Err(e) = self.write(msg) {
+            error!("{} could not send message to the RTI: {}", self.id, e)
+        }
+    }
+
+    fn write(&self, msg: Message) -> io::Result<()> {
+        msg.write_to(&mut *self.writer.lock().unwrap())
+    }
+}
+
+/// Schedule the network input with a message received from
+/// another federate, and notify the scheduler.
+fn deliver_message(
+    state: &Mutex<ClientState>,
+    tx: &Sender<PhysicalEvent>,
+    input: &PhysicalActionRef<Vec<u8>>,
+    tag: EventTag,
+    payload: Vec<u8>,
+) -> Result<(), SendError<PhysicalEvent>> {
+    // hold the lock so that the scheduler cannot start processing the tag meanwhile
+    let state = state.lock().unwrap();
+    let tag = match state.started {
+        Some(started) if tag <= started => {
+            let delayed = started.next_microstep();
+            warn!(
+                "Received message for tag {} after starting tag {}, delaying it to {}",
+                tag, started, delayed
+            );
+            delayed
+        }
+        _ => tag,
+    };
+    let id = input.get_id();
+    input
+        .use_mut_p(payload, |action, payload| action.0.schedule_future_value(tag, Some(payload)))
+        .ok();
+    tx.send(PhysicalEvent::trigger(tag, id))
+}
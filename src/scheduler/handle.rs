@@ -0,0 +1,137 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Handle to a program running in the background.
+
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use crate::assembly::ReactorInitializer;
+use crate::util::reconnectable::SendError;
+use crate::*;
+
+/// Summary of the execution of a program, see [SchedulerHandle::join].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RunSummary {
+    /// The last tag that was processed, which is the shutdown tag.
+    pub final_tag: EventTag,
+    /// Number of tags that were processed, including startup and shutdown.
+    pub num_tags: u64,
+    /// Number of reactions that were executed.
+    pub num_reactions: u64,
+}
+
+/// A handle to a program that runs on a background thread,
+/// created with [SyncScheduler::start]. This makes it possible
+/// to embed a reactor program into a larger application.
+///
+/// ```no_run
+/// # use reactor_rt::*;
+/// # use reactor_rt::assembly::ReactorInitializer;
+/// # fn example<R: ReactorInitializer + 'static>(params: R::Params) where R::Params: Send {
+/// let mut handle = SyncScheduler::start::<R>(SchedulerOptions::default(), params).unwrap();
+/// // ... schedule physical actions with handle.ctx() ...
+/// handle.request_stop(Offset::Asap).unwrap();
+/// let summary = handle.join().unwrap();
+/// println!("Shut down at {}", summary.final_tag);
+/// # }
+/// ```
+pub struct SchedulerHandle {
+    ctx: AsyncCtx,
+    thread: JoinHandle<Result<RunSummary, RuntimeError>>,
+}
+
+impl SchedulerHandle {
+    /// Start the scheduler on a new thread, and wait until
+    /// the program is assembled.
+    pub(super) fn spawn<R: ReactorInitializer + 'static>(options: SchedulerOptions, args: R::Params) -> Result<Self, RuntimeError>
+    where
+        R::Params: Send,
+    {
+        let (tx, rx) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("reactor-scheduler".into())
+            .spawn(move || {
+                SyncScheduler::run::<R>(options, args, None, move |ctx| {
+                    tx.send(ctx).ok();
+                })
+            })
+            .map_err(RuntimeError::Io)?;
+
+        match rx.recv() {
+            Ok(ctx) => Ok(Self { ctx, thread }),
+            // the scheduler thread stopped before starting
+            Err(_) => Err(Self::join_thread(thread)
+                .err()
+                .unwrap_or_else(|| RuntimeError::Panicked("The scheduler stopped before starting".to_string()))),
+        }
+    }
+
+    /// Returns a context linked to the scheduler, which can
+    /// schedule physical actions. It may be cloned and sent
+    /// to other threads.
+    pub fn ctx(&mut self) -> &mut AsyncCtx {
+        &mut self.ctx
+    }
+
+    /// Schedule a physical action of the program.
+    /// See [AsyncCtx::schedule_physical_with_v].
+    pub fn schedule_physical<T: Sync>(
+        &mut self,
+        action: &PhysicalActionRef<T>,
+        value: Option<T>,
+        offset: Offset,
+    ) -> Result<(), SendError<Option<T>>> {
+        self.ctx.schedule_physical_with_v(action, value, offset)
+    }
+
+    /// Request that the program shut down.
+    /// See [AsyncCtx::request_stop].
+    pub fn request_stop(&mut self, offset: Offset) -> Result<(), SendError<()>> {
+        self.ctx.request_stop(offset)
+    }
+
+    /// Returns true if the program has shut down.
+    pub fn is_terminated(&self) -> bool {
+        self.ctx.was_terminated()
+    }
+
+    /// Wait for the program to shut down. Returns an error
+    /// if the program could not run to completion, or if
+    /// the scheduler thread panicked.
+    ///
+    /// This drops the context of the handle, so the program
+    /// also shuts down once it has no more events to process,
+    /// unless other asynchronous contexts are still alive.
+    pub fn join(self) -> Result<RunSummary, RuntimeError> {
+        let SchedulerHandle { ctx, thread } = self;
+        // the scheduler waits for asynchronous events while ctx is alive
+        drop(ctx);
+        Self::join_thread(thread)
+    }
+
+    fn join_thread(thread: JoinHandle<Result<RunSummary, RuntimeError>>) -> Result<RunSummary, RuntimeError> {
+        thread.join().map_err(RuntimeError::from_panic)?
+    }
+}
@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Unit tests of single reactors, see [ReactorTestHarness].
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::assembly_impl::RootAssembler;
+use super::dependencies::DataflowInfo;
+use crate::assembly::{ReactorInitializer, TriggerId, TriggerLike};
+use crate::*;
+
+/// Drives a single reactor tag by tag, to test it in isolation.
+///
+/// The harness assembles the reactor as the main reactor of a
+/// program. The test may then set its input ports and schedule
+/// its actions, advance to a given tag, and check the values of
+/// its ports and the events it has scheduled.
+///
+/// Port values set during a tag stay readable until the next call
+/// that changes the state of the program, so that outputs can be
+/// checked after the tag has been processed. Physical time does
+/// not advance, and physical actions are scheduled like logical
+/// actions, relative to the current tag.
+///
+/// ### Example
+///
+/// ```no_run
+/// # use reactor_rt::prelude::*;
+/// # use reactor_rt::{assembly::ReactorInitializer, Port, ReactorTestHarness};
+/// # fn test<Doubler: ReactorInitializer<Params = ()> + 'static>(
+/// #     input: fn(&mut Doubler) -> &mut Port<u32>,
+/// #     output: fn(&Doubler) -> &Port<u32>,
+/// # ) {
+/// let mut harness = ReactorTestHarness::<Doubler>::new(()).unwrap();
+/// harness.set_input(input, 21);
+/// harness.advance_to(EventTag::ORIGIN);
+/// assert_eq!(harness.get(output), Some(42));
+/// # }
+/// ```
+pub struct ReactorTestHarness<R: ReactorInitializer + 'static> {
+    scheduler: SyncScheduler<'static>,
+    reactor: Rc<SharedReactor<R>>,
+    /// Sets the input ports given since the last tag, when
+    /// the next tag is processed.
+    inputs: Vec<SetInput<R>>,
+    initial_time: Instant,
+}
+
+impl<R: ReactorInitializer + 'static> ReactorTestHarness<R> {
+    /// Assemble the reactor with the given parameters. Its startup
+    /// reactions are executed with the first processed tag.
+    pub fn new(args: R::Params) -> Result<Self, RuntimeError> {
+        let mut reactor = None;
+        let (reactors, mut graph, id_registry, network_inputs, delayed_connections, recorded_triggers, tag_cleanups, watchdogs) =
+            RootAssembler::assemble_tree_with::<R>(args, |main| {
+                let shared = Rc::new(SharedReactor { id: main.id(), reactor: RefCell::new(main) });
+                reactor = Some(shared.clone());
+                Box::new(HarnessedReactor(shared))
+            })?;
+        let reactor = reactor.expect("Main reactor was not assembled");
+
+        let modes = std::mem::take(&mut graph.modes)
+            .finish(&id_registry)
+            .map_err(|e| e.lift(&id_registry))?;
+        let dataflow = DataflowInfo::new(&graph).map_err(|e| e.lift(&id_registry))?;
+        // The scheduler borrows the dataflow info for its whole
+        // lifetime. A harness only lives as long as a test, so
+        // we leak it instead of borrowing it from a scope.
+        let dataflow: &'static DataflowInfo = Box::leak(Box::new(dataflow));
+
+        let clock = Arc::new(VirtualClock::new());
+        let initial_time = clock.now();
+        let options = SchedulerOptions {
+            fast: true,
+            clock: Some(clock.clone()),
+            ..Default::default()
+        };
+        let mut scheduler = SyncScheduler::new(
+            options,
+            id_registry,
+            dataflow,
+            graph,
+            reactors,
+            initial_time,
+            None,
+            network_inputs,
+            delayed_connections,
+            modes,
+            clock,
+            None,
+            recorded_triggers,
+            tag_cleanups,
+            watchdogs,
+            None,
+            None,
+        )?;
+        scheduler.defer_cleanup();
+        scheduler.queue_startup();
+
+        Ok(Self {
+            scheduler,
+            reactor,
+            inputs: Vec::new(),
+            initial_time,
+        })
+    }
+
+    /// The reactor under test.
+    pub fn reactor(&self) -> Ref<'_, R> {
+        self.reactor.reactor.borrow()
+    }
+
+    /// The reactor under test, eg to change its state variables.
+    pub fn reactor_mut(&mut self) -> RefMut<'_, R> {
+        self.reactor.reactor.borrow_mut()
+    }
+
+    /// The latest tag that has been processed, if any.
+    pub fn current_tag(&self) -> Option<EventTag> {
+        self.scheduler.current_tag()
+    }
+
+    /// The tag of the earliest pending event, if any.
+    pub fn next_tag(&self) -> Option<EventTag> {
+        self.scheduler.next_event_tag()
+    }
+
+    /// Set an input port of the reactor. The port is present
+    /// at the tag given to the next call to [Self::advance_to],
+    /// but not at the pending tags processed before it.
+    pub fn set_input<T: Sync + 'static>(&mut self, port: impl FnOnce(&mut R) -> &mut Port<T> + 'static, value: T) {
+        self.inputs.push(Box::new(move |reactor| {
+            let port = port(reactor);
+            port.set_impl(Some(value));
+            port.get_id()
+        }));
+    }
+
+    /// Schedule an action of the reactor, as a reaction would
+    /// at the current tag.
+    pub fn schedule<T: Sync, A: SchedulableAsAction<T>>(
+        &mut self,
+        action: impl FnOnce(&mut R) -> &mut A,
+        value: Option<T>,
+        offset: Offset,
+    ) -> Option<ScheduleToken> {
+        self.scheduler.cleanup_tag();
+        let mut reactor = self.reactor.reactor.borrow_mut();
+        let action = action(&mut reactor);
+        self.scheduler.with_ctx(|ctx| ctx.schedule_with_v(action, value, offset))
+    }
+
+    /// Cancel an event scheduled on an action of the reactor,
+    /// see [ReactionCtx::cancel].
+    pub fn cancel<T: Sync, A: SchedulableAsAction<T>>(
+        &mut self,
+        action: impl FnOnce(&mut R) -> &mut A,
+        token: ScheduleToken,
+    ) -> bool {
+        let mut reactor = self.reactor.reactor.borrow_mut();
+        let action = action(&mut reactor);
+        self.scheduler.with_ctx(|ctx| ctx.cancel(action, token))
+    }
+
+    /// Process the pending events up to the given tag, and then
+    /// that tag, where the inputs set since the last tag are
+    /// present. Returns false if the program has shut down, eg
+    /// because a reaction requested it.
+    ///
+    /// Panics if the tag has already been processed.
+    pub fn advance_to(&mut self, tag: EventTag) -> bool {
+        let inputs = std::mem::take(&mut self.inputs);
+        let reactor = &self.reactor;
+        self.scheduler.step_to(tag, || {
+            let mut reactor = reactor.reactor.borrow_mut();
+            inputs.into_iter().map(|set_input| set_input(&mut reactor)).collect()
+        })
+    }
+
+    /// Process the tag of the next pending event. Returns that
+    /// tag, or None if there is no pending event or the program
+    /// has shut down.
+    pub fn step(&mut self) -> Option<EventTag> {
+        let tag = self.next_tag()?;
+        self.advance_to(tag).then(|| tag)
+    }
+
+    /// Process the pending events up to the given tag, and run
+    /// the shutdown reactions at that tag.
+    pub fn shutdown_at(&mut self, tag: EventTag) {
+        self.scheduler.stop_at(tag);
+        self.advance_to(tag);
+    }
+
+    /// Returns whether an event is pending for the given
+    /// trigger of the reactor, eg an action, at the given tag.
+    pub fn is_scheduled<C: TriggerLike>(&self, trigger: impl FnOnce(&R) -> &C, tag: EventTag) -> bool {
+        let id = trigger(&self.reactor()).get_id();
+        self.scheduler
+            .pending_events()
+            .any(|evt| evt.tag == tag && evt.triggers.contains(&id))
+    }
+
+    /// Returns whether the given port or action of the reactor
+    /// is present at the current tag.
+    pub fn is_present<T, C: ReactionTrigger<T>>(&self, trigger: impl FnOnce(&R) -> &C) -> bool {
+        let tag = self.current_tag().unwrap_or(EventTag::ORIGIN);
+        trigger(&self.reactor()).is_present(&tag, &self.initial_time)
+    }
+
+    /// Returns the value of the given port or action of the
+    /// reactor at the current tag, see [ReactionCtx::get].
+    pub fn get<T: Copy, C: ReactionTrigger<T>>(&self, trigger: impl FnOnce(&R) -> &C) -> Option<T> {
+        let tag = self.current_tag().unwrap_or(EventTag::ORIGIN);
+        trigger(&self.reactor()).get_value(&tag, &self.initial_time)
+    }
+
+    /// Calls the given function with a reference to the value of
+    /// the given port or action at the current tag, see [ReactionCtx::use_ref].
+    pub fn use_ref<T, C: ReactionTrigger<T>, O>(&self, trigger: impl FnOnce(&R) -> &C, f: impl FnOnce(Option<&T>) -> O) -> O {
+        let tag = self.current_tag().unwrap_or(EventTag::ORIGIN);
+        trigger(&self.reactor()).use_value_ref(&tag, &self.initial_time, f)
+    }
+}
+
+/// Sets an input port of the reactor, and returns its ID.
+type SetInput<R> = Box<dyn FnOnce(&mut R) -> TriggerId>;
+
+/// The reactor under test, shared between the harness
+/// and the scheduler.
+struct SharedReactor<R> {
+    id: ReactorId,
+    reactor: RefCell<R>,
+}
+
+/// The reactor under test, as seen by the scheduler.
+struct HarnessedReactor<R>(Rc<SharedReactor<R>>);
+
+impl<R: ReactorBehavior> ReactorBehavior for HarnessedReactor<R> {
+    fn id(&self) -> ReactorId {
+        self.0.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        self.0.reactor.borrow_mut().react(ctx, local_rid)
+    }
+}
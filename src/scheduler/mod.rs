@@ -23,31 +23,97 @@
  */
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::Display;
 
+pub use builder::{ProgramBuilder, ReactorBuilder, ReactorState};
+pub use checkpoint::{CheckpointReader, CheckpointWriter, Checkpointable};
+pub use clock::*;
 pub use context::*;
-pub use events::*;
+pub use debugger::DebuggerOptions;
+pub use error::*;
+pub use events::EventTag;
+pub use handle::*;
+pub use harness::ReactorTestHarness;
 use index_vec::IndexVec;
+pub use modes::{Mode, ModeTransition};
 pub use scheduler_impl::*;
+pub use trace::{TraceFormat, TraceOptions};
 
-use self::dependencies::ExecutableReactions;
+use self::checkpoint::Checkpoint;
+use self::cleanup::TagCleanups;
+use self::debugger::{DebugView, Debugger};
+use self::dependencies::{DepGraph, ExecutableReactions};
+use self::events::{Event, EventQueue, PhysicalEvent};
+use self::modes::ModeInfo;
+use self::mutations::PendingMutation;
+use self::replay::{RecordedTriggers, Recorder, Replay};
+use self::trace::{TracePoint, Tracer};
+use crate::assembly::TriggerId;
 use crate::*;
 
 pub(crate) mod assembly_impl;
+mod builder;
+mod checkpoint;
+mod cleanup;
+mod clock;
 mod context;
 pub(crate) mod debug;
+mod debugger;
 mod dependencies;
+mod error;
 mod events;
+mod federate;
+mod handle;
+mod harness;
+mod modes;
+pub(crate) mod mutations;
+mod replay;
 mod scheduler_impl;
+mod trace;
 
 #[cfg(feature = "public-internals")]
 pub mod internals {
     pub use super::dependencies::{ExecutableReactions, Level, LevelIx, ReactionLevelInfo};
+    pub use super::events::{Event, EventQueue};
 }
 
 type ReactionPlan<'x> = Option<Cow<'x, ExecutableReactions<'x>>>;
 type ReactorBox<'a> = Box<dyn ReactorBehavior + 'a>;
 type ReactorVec<'a> = IndexVec<ReactorId, ReactorBox<'a>>;
+type DelayedConnectionVec = Vec<Box<dyn DelayedConnectionBehavior>>;
+
+/// The delayed connections of a program, indexed by the
+/// ports they read and set, so that the scheduler only
+/// visits those whose ports are present at a tag.
+struct DelayedConnections {
+    connections: DelayedConnectionVec,
+    /// Indices of the connections whose upstream port is bound
+    /// to the key, which is the port of its binding class that
+    /// can be set by reactions.
+    by_upstream: HashMap<TriggerId, Vec<usize>>,
+    /// Index of the connection that sets the key.
+    by_downstream: HashMap<TriggerId, usize>,
+}
+
+impl DelayedConnections {
+    fn new(connections: DelayedConnectionVec, graph: &DepGraph) -> Self {
+        let mut by_upstream = HashMap::<TriggerId, Vec<usize>>::new();
+        let mut by_downstream = HashMap::new();
+        for (i, connection) in connections.iter().enumerate() {
+            by_upstream
+                .entry(graph.binding_root(connection.upstream_id()))
+                .or_default()
+                .push(i);
+            by_downstream.insert(connection.downstream_id(), i);
+        }
+        Self { connections, by_upstream, by_downstream }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+}
 
 /// Can format stuff for trace messages.
 #[derive(Clone)]
@@ -57,7 +123,7 @@ struct DebugInfoProvider<'a> {
 
 impl DebugInfoProvider<'_> {
     pub(self) fn display_event(&self, evt: &Event) -> String {
-        let Event { tag, reactions, terminate } = evt;
+        let Event { tag, reactions, terminate, .. } = evt;
         let mut str = format!("at {}: run {}", tag, self.display_reactions(reactions));
 
         if *terminate {
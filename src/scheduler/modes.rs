@@ -0,0 +1,379 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Modal reactors.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use super::ReactionPlan;
+use crate::assembly::{AssemblyError, AssemblyErrorImpl, AssemblyResult, TriggerId, TriggerLike};
+use crate::*;
+
+/// A mode of a reactor, created with
+/// [ComponentCreator::new_mode](crate::assembly::ComponentCreator::new_mode).
+/// Reactions, timers and child reactors may be declared in a mode,
+/// in which case they are only active while their reactor is in
+/// that mode. See [ReactionCtx::set_mode].
+///
+/// A mode is also a trigger, which triggers reactions when the
+/// mode is entered with [ModeTransition::Reset], like the `reset`
+/// trigger of LF.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Mode {
+    id: TriggerId,
+    reactor: ReactorId,
+}
+
+impl Mode {
+    pub(crate) fn new(id: TriggerId, reactor: ReactorId) -> Self {
+        Self { id, reactor }
+    }
+
+    /// Returns the ID of the reactor this mode belongs to.
+    pub fn reactor(&self) -> ReactorId {
+        self.reactor
+    }
+}
+
+impl TriggerLike for Mode {
+    fn get_id(&self) -> TriggerId {
+        self.id
+    }
+}
+
+/// How a mode is entered, see [ReactionCtx::set_mode].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ModeTransition {
+    /// The mode starts over. Its timers restart from their
+    /// offset, nested modal reactors go back to their initial
+    /// mode, and the reactions triggered by the mode execute.
+    Reset,
+    /// The mode resumes where it was left. Its timers are
+    /// delayed by the time the mode was inactive, and nested
+    /// modal reactors keep their current mode.
+    History,
+}
+
+/// A timer that was declared in a mode.
+#[derive(Clone)]
+struct ModalTimer {
+    id: TriggerId,
+    offset: Duration,
+    /// Shared with the [Timer].
+    ticks: Arc<RwLock<TimerTicks>>,
+}
+
+/// Modes of the program. They are declared during assembly,
+/// then the current mode of each reactor is only changed by
+/// the scheduler, between tags.
+#[derive(Clone, Default)]
+pub(super) struct ModeInfo {
+    /// Number of initial modes of each modal reactor,
+    /// which must be exactly one.
+    num_initial: HashMap<ReactorId, usize>,
+    /// Initial mode of each modal reactor.
+    initial: HashMap<ReactorId, Mode>,
+    /// Mode in which a reaction was declared, if any.
+    reactions: HashMap<GlobalReactionId, Mode>,
+    /// Mode in which a child reactor was declared, if any.
+    children: HashMap<ReactorId, Mode>,
+    /// Timers declared in each mode.
+    timers: HashMap<Mode, Vec<ModalTimer>>,
+    /// Mode in which a timer was declared, if any.
+    timer_modes: HashMap<TriggerId, Mode>,
+
+    /// Innermost mode that contains each reactor, if any.
+    /// Computed by [Self::finish].
+    enclosing: HashMap<ReactorId, Mode>,
+    /// Modal reactors directly contained in each mode.
+    /// Computed by [Self::finish].
+    nested: HashMap<Mode, Vec<ReactorId>>,
+
+    /// Current mode of each modal reactor.
+    active: HashMap<ReactorId, Mode>,
+    /// Time at which modes were last exited.
+    exited_at: HashMap<Mode, EventTag>,
+}
+
+impl ModeInfo {
+    pub(super) fn record_mode(&mut self, mode: Mode, initial: bool) {
+        *self.num_initial.entry(mode.reactor).or_default() += usize::from(initial);
+        if initial {
+            self.initial.insert(mode.reactor, mode);
+            self.active.insert(mode.reactor, mode);
+        }
+    }
+
+    pub(super) fn reaction_mode(&mut self, reaction: GlobalReactionId, mode: Mode) {
+        self.reactions.insert(reaction, mode);
+    }
+
+    pub(super) fn child_mode(&mut self, child: ReactorId, mode: Mode) {
+        self.children.insert(child, mode);
+    }
+
+    pub(super) fn timer_mode(&mut self, timer: &mut Timer, mode: Mode) {
+        self.timer_modes.insert(timer.get_id(), mode);
+        self.timers.entry(mode).or_default().push(ModalTimer {
+            id: timer.get_id(),
+            offset: timer.offset(),
+            ticks: timer.share_ticks(),
+        });
+    }
+
+    /// Compute the nesting of modes, once all reactors are assembled.
+    /// Fails if a modal reactor does not have exactly one initial mode.
+    pub(super) fn finish(mut self, debug: &DebugInfoRegistry) -> AssemblyResult<Self> {
+        if let Some((reactor, _)) = self.num_initial.iter().find(|(_, n)| **n != 1) {
+            return Err(AssemblyError(AssemblyErrorImpl::InvalidInitialMode(*reactor)));
+        }
+        if self.initial.is_empty() {
+            return Ok(self);
+        }
+        for reactor in debug.reactor_ids() {
+            let mut cur = Some(reactor);
+            while let Some(r) = cur {
+                if let Some(mode) = self.children.get(&r) {
+                    self.enclosing.insert(reactor, *mode);
+                    break;
+                }
+                cur = debug.get_container(r);
+            }
+        }
+        for reactor in self.initial.keys() {
+            if let Some(mode) = self.enclosing.get(reactor) {
+                self.nested.entry(*mode).or_default().push(*reactor);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Whether the program has no modal reactors.
+    pub(super) fn is_empty(&self) -> bool {
+        self.initial.is_empty()
+    }
+
+    /// Whether modes were declared, even without an initial mode.
+    pub(super) fn has_modes(&self) -> bool {
+        !self.num_initial.is_empty()
+    }
+
+    /// Place reactors added to the running program by the given
+    /// parent in the innermost mode that contains the parent, if any.
+    pub(super) fn adopt(&mut self, parent: ReactorId, reactors: impl Iterator<Item = ReactorId>) {
+        if let Some(mode) = self.enclosing.get(&parent).copied() {
+            self.enclosing.extend(reactors.map(|r| (r, mode)));
+        }
+    }
+
+    /// Returns whether the reaction may execute.
+    #[inline]
+    pub(super) fn is_reaction_active(&self, reaction: GlobalReactionId) -> bool {
+        if self.initial.is_empty() {
+            // most programs have no modes
+            return true;
+        }
+        match self.reactions.get(&reaction) {
+            Some(mode) => self.is_mode_active(*mode),
+            None => self.is_reactor_active(reaction.0.container()),
+        }
+    }
+
+    /// Remove the reactions of inactive modes from the plan.
+    /// The plan is only cloned if it contains such reactions.
+    pub(super) fn filter_plan<'x>(&self, plan: ReactionPlan<'x>) -> ReactionPlan<'x> {
+        if self.is_empty() {
+            return plan;
+        }
+        let mut plan = plan?;
+        if plan
+            .batches()
+            .any(|(_, level)| !level.iter().all(|r| self.is_reaction_active(r)))
+        {
+            plan.to_mut().retain(|r| self.is_reaction_active(r));
+        }
+        if plan.is_empty() {
+            None
+        } else {
+            Some(plan)
+        }
+    }
+
+    /// Returns whether the timer belongs to an active mode.
+    pub(super) fn is_timer_active(&self, timer: &Timer) -> bool {
+        match self.timer_modes.get(&timer.get_id()) {
+            Some(mode) => self.is_mode_active(*mode),
+            None => true,
+        }
+    }
+
+    fn is_mode_active(&self, mode: Mode) -> bool {
+        self.active.get(&mode.reactor) == Some(&mode) && self.is_reactor_active(mode.reactor)
+    }
+
+    fn is_reactor_active(&self, reactor: ReactorId) -> bool {
+        match self.enclosing.get(&reactor) {
+            Some(mode) => self.is_mode_active(*mode),
+            None => true,
+        }
+    }
+
+    /// Switch to the given mode at the end of the tag. Returns
+    /// the triggers that must be scheduled, with their tag.
+    pub(super) fn set_mode(&mut self, mode: Mode, transition: ModeTransition, tag: EventTag) -> Vec<(TriggerId, EventTag)> {
+        let mut triggers = Vec::new();
+        let entry = tag.next_microstep();
+        if transition == ModeTransition::History && self.active.get(&mode.reactor) == Some(&mode) {
+            return triggers;
+        }
+        if let Some(previous) = self.active.insert(mode.reactor, mode) {
+            self.exit(previous, entry);
+        }
+        self.enter(mode, transition, entry, &mut triggers);
+        triggers
+    }
+
+    fn exit(&mut self, mode: Mode, entry: EventTag) {
+        self.exited_at.insert(mode, entry);
+        for reactor in self.nested.get(&mode).cloned().unwrap_or_default() {
+            self.exit(self.active[&reactor], entry);
+        }
+    }
+
+    fn enter(&mut self, mode: Mode, transition: ModeTransition, entry: EventTag, triggers: &mut Vec<(TriggerId, EventTag)>) {
+        let exited_at = self.exited_at.remove(&mode);
+        for timer in self.timers.get(&mode).map(Vec::as_slice).unwrap_or_default() {
+            let mut ticks = timer.ticks.write().unwrap();
+            let next_tick = match (transition, exited_at) {
+                (ModeTransition::History, Some(exited_at)) => {
+                    // the next tick is delayed by the time the mode was inactive
+                    let inactive = entry.offset_from_t0 - exited_at.offset_from_t0;
+                    ticks.next().map(|t| t.offset_from_t0 + inactive)
+                }
+                _ => Some(entry.offset_from_t0 + timer.offset),
+            };
+            let next_tick = next_tick.map(|t| {
+                if t == entry.offset_from_t0 {
+                    entry
+                } else {
+                    EventTag::offset(t, 0)
+                }
+            });
+            // the event of the previous next tick, if any, is now stale
+            ticks.set_next(entry, next_tick);
+            if let Some(tag) = next_tick {
+                triggers.push((timer.id, tag));
+            }
+        }
+        if transition == ModeTransition::Reset {
+            triggers.push((mode.id, entry));
+        }
+
+        for reactor in self.nested.get(&mode).cloned().unwrap_or_default() {
+            let nested_mode = match transition {
+                ModeTransition::Reset => self.initial[&reactor],
+                ModeTransition::History => self.active[&reactor],
+            };
+            self.active.insert(reactor, nested_mode);
+            self.enter(nested_mode, transition, entry, triggers);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reactions_of_inactive_modes_are_filtered() {
+        let reactor = ReactorId::new(0);
+        let idle = Mode::new(TriggerId::new(10), reactor);
+        let running = Mode::new(TriggerId::new(11), reactor);
+        let idle_reaction = GlobalReactionId::new(reactor, LocalReactionId::new(0));
+        let running_reaction = GlobalReactionId::new(reactor, LocalReactionId::new(1));
+        let other_reaction = GlobalReactionId::new(reactor, LocalReactionId::new(2));
+
+        let mut modes = ModeInfo::default();
+        modes.record_mode(idle, true);
+        modes.record_mode(running, false);
+        modes.reaction_mode(idle_reaction, idle);
+        modes.reaction_mode(running_reaction, running);
+        let mut modes = modes.finish(&DebugInfoRegistry::new()).ok().unwrap();
+
+        assert!(modes.is_reaction_active(idle_reaction));
+        assert!(!modes.is_reaction_active(running_reaction));
+        assert!(modes.is_reaction_active(other_reaction));
+
+        let triggers = modes.set_mode(running, ModeTransition::Reset, tag!(T0 + 5 ms));
+        assert_eq!(triggers, vec![(running.get_id(), tag!(T0 + 5 ms).next_microstep())]);
+        assert!(!modes.is_reaction_active(idle_reaction));
+        assert!(modes.is_reaction_active(running_reaction));
+        assert!(modes.is_reaction_active(other_reaction));
+    }
+
+    #[test]
+    fn test_history_transition_delays_timers() {
+        let reactor = ReactorId::new(0);
+        let running = Mode::new(TriggerId::new(10), reactor);
+        let paused = Mode::new(TriggerId::new(11), reactor);
+        let mut timer = Timer::new(TriggerId::new(12), Duration::ZERO, Duration::from_millis(10));
+        timer.ticks().set_next(EventTag::ORIGIN, Some(tag!(T0)));
+        for tag in [tag!(T0), tag!(T0 + 10 ms), tag!(T0 + 20 ms)] {
+            timer.ticks().fire(tag);
+        }
+
+        let mut modes = ModeInfo::default();
+        modes.record_mode(running, true);
+        modes.record_mode(paused, false);
+        modes.timer_mode(&mut timer, running);
+        let mut modes = modes.finish(&DebugInfoRegistry::new()).ok().unwrap();
+        let start = Instant::now();
+
+        assert!(modes.is_timer_active(&timer) && timer.is_present(&tag!(T0 + 20 ms), &start));
+        modes.set_mode(paused, ModeTransition::Reset, tag!(T0 + 25 ms));
+        assert!(!modes.is_timer_active(&timer));
+
+        // the tick at 30 ms is delayed by the 15 ms spent paused
+        let triggers = modes.set_mode(running, ModeTransition::History, tag!(T0 + 40 ms));
+        assert_eq!(triggers, vec![(timer.get_id(), tag!(T0 + 45 ms))]);
+        assert!(timer.is_present(&tag!(T0 + 45 ms), &start));
+        assert!(!timer.is_present(&tag!(T0 + 30 ms), &start));
+        assert!(!timer.is_present(&tag!(T0 + 50 ms), &start));
+        assert_eq!(timer.ticks().fire(tag!(T0 + 45 ms)), Some(tag!(T0 + 55 ms)));
+        assert!(modes.is_timer_active(&timer) && timer.is_present(&tag!(T0 + 55 ms), &start));
+
+        // a reset restarts the timer at the transition
+        let triggers = modes.set_mode(running, ModeTransition::Reset, tag!(T0 + 57 ms));
+        assert_eq!(
+            triggers,
+            vec![
+                (timer.get_id(), tag!(T0 + 57 ms).next_microstep()),
+                (running.get_id(), tag!(T0 + 57 ms).next_microstep())
+            ]
+        );
+        assert_eq!(timer.ticks().fire(tag!(T0 + 57 ms).next_microstep()), Some(tag!(T0 + 67 ms)));
+        assert!(timer.is_present(&tag!(T0 + 67 ms), &start));
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Mutations of a running program, see [ReactionCtx::mutate].
+
+use super::assembly_impl::RootAssembler;
+use super::cleanup::TagCleanups;
+use crate::assembly::*;
+use crate::*;
+
+/// A function that mutates the program, see [ReactionCtx::mutate].
+type MutationFn = Box<dyn FnOnce(&mut Mutator) -> AssemblyResult<()> + Send>;
+
+/// A mutation requested by a reaction, which the scheduler
+/// applies at the end of the tag.
+pub(super) struct PendingMutation {
+    pub(super) reaction: GlobalReactionId,
+    pub(super) mutation: MutationFn,
+}
+
+/// Adds reactors and connections to a running program, see
+/// [ReactionCtx::mutate]. New reactors are children of the
+/// reactor whose reaction requested the mutation.
+///
+/// A mutation cannot capture the ports of existing reactors,
+/// it refers to them by their ID instead, see [Self::bind_from]
+/// and [Self::bind_to].
+pub struct Mutator<'a> {
+    root: &'a mut RootAssembler,
+    /// Handles to the ports of the running program.
+    existing: &'a mut TagCleanups,
+    /// Reactor of the reaction that requested the mutation.
+    parent: ReactorId,
+}
+
+impl<'a> Mutator<'a> {
+    pub(super) fn new(root: &'a mut RootAssembler, existing: &'a mut TagCleanups, parent: ReactorId) -> Self {
+        Self { root, existing, parent }
+    }
+
+    /// Assemble a new child reactor. The `connect` function
+    /// receives the child before it is registered, to bind
+    /// its ports, or to add other children and bind their
+    /// ports together. Returns the ID of the child.
+    pub fn add_child<R: ReactorInitializer + 'static>(
+        &mut self,
+        inst_name: &'static str,
+        args: R::Params,
+        connect: impl FnOnce(&mut Self, &mut R) -> AssemblyResult<()>,
+    ) -> AssemblyResult<ReactorId> {
+        let debug = self.root.debug_info.get_debug_info(self.parent).derive::<R>(inst_name);
+        let mut child = self.root.assemble_reactor::<R>(args, debug)?;
+        let id = child.id();
+        self.root.debug_info.record_reactor_container(self.parent, id);
+        connect(self, &mut child)?;
+        self.root.register_boxed(id, Box::new(child));
+        Ok(id)
+    }
+
+    /// Bind two ports of new reactors.
+    pub fn bind_ports<T: Sync>(&mut self, upstream: &mut Port<T>, downstream: &mut Port<T>) -> AssemblyResult<()> {
+        upstream.forward_to(downstream)?;
+        self.root.graph.port_bind(upstream, downstream);
+        Ok(())
+    }
+
+    /// Bind a port of the running program, given by its ID,
+    /// to a port of a new reactor.
+    pub fn bind_from<T: Sync + 'static>(&mut self, upstream: TriggerId, downstream: &mut Port<T>) -> AssemblyResult<()> {
+        let mut upstream_port = self.existing_port::<T>(upstream, (upstream, downstream.get_id()))?;
+        self.bind_ports(&mut upstream_port, downstream)
+    }
+
+    /// Bind a port of a new reactor to a port of the running
+    /// program, given by its ID. That port must not be bound
+    /// yet, nor be set by reactions.
+    pub fn bind_to<T: Sync + 'static>(&mut self, upstream: &mut Port<T>, downstream: TriggerId) -> AssemblyResult<()> {
+        let mut downstream_port = self.existing_port::<T>(downstream, (upstream.get_id(), downstream))?;
+        if self.root.graph.has_upstream(downstream) {
+            return Err(AssemblyError(AssemblyErrorImpl::CannotBind(upstream.get_id(), downstream)));
+        }
+        self.bind_ports(upstream, &mut downstream_port)
+    }
+
+    /// Returns a handle to a port of the running program. The
+    /// binding is the pair of ports to report if the types of
+    /// the ports don't match.
+    fn existing_port<T: Sync + 'static>(&mut self, id: TriggerId, binding: (TriggerId, TriggerId)) -> AssemblyResult<Port<T>> {
+        let handle = self
+            .existing
+            .get_mut(&id)
+            .ok_or(AssemblyError(AssemblyErrorImpl::UnknownPort(id)))?;
+        match handle.as_any().downcast_mut::<Port<T>>() {
+            Some(port) => Ok(port.share()),
+            None => Err(AssemblyError(AssemblyErrorImpl::PortTypeMismatch(binding.0, binding.1))),
+        }
+    }
+}
@@ -0,0 +1,351 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Record and replay of physical events.
+//!
+//! The only sources of non-determinism in a program are the
+//! events sent by asynchronous threads: physical actions,
+//! watchdogs and [AsyncCtx::request_stop]. With
+//! [SchedulerOptions::record], each of those events is written to
+//! a file when it reaches the scheduler. With [SchedulerOptions::replay],
+//! the scheduler processes the recorded events at their recorded
+//! tags instead, and asynchronous contexts do not send any event,
+//! so that the execution is the same as the recorded one.
+//!
+//! The values of physical actions are only recorded if they are
+//! registered with [ComponentCreator::record_physical_action](crate::assembly::ComponentCreator::record_physical_action).
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use index_vec::Idx;
+
+use super::checkpoint::invalid_data;
+use super::{CheckpointReader, CheckpointWriter, PhysicalEvent};
+use crate::assembly::TriggerId;
+use crate::*;
+
+const MAGIC: &[u8; 4] = b"LFRR";
+const VERSION: u64 = 1;
+
+const RECORD_TRIGGER: u64 = 0;
+const RECORD_TERMINATE: u64 = 1;
+
+/// Triggers whose presence or value at a tag must be restored
+/// when replaying their events, by their ID.
+pub(crate) type RecordedTriggers = HashMap<TriggerId, Box<dyn RecordedTrigger>>;
+
+/// A trigger of physical events, whose state at the tag of
+/// an event is recorded with the event.
+pub(crate) trait RecordedTrigger {
+    /// Returns the serialized value of the trigger at the given tag, if any.
+    fn save_value(&self, tag: EventTag) -> Option<Vec<u8>>;
+
+    /// Make the trigger present at the given tag, with the given
+    /// value, as returned by [Self::save_value].
+    fn replay(&self, tag: EventTag, value: Option<Vec<u8>>) -> io::Result<()>;
+}
+
+/// Records the values of a physical action.
+pub(crate) struct RecordedAction<T: Sync> {
+    pub(crate) action: PhysicalActionRef<T>,
+    pub(crate) serialize: fn(&T) -> Vec<u8>,
+    pub(crate) deserialize: fn(Vec<u8>) -> io::Result<T>,
+}
+
+impl<T: Sync> RecordedTrigger for RecordedAction<T> {
+    fn save_value(&self, tag: EventTag) -> Option<Vec<u8>> {
+        self.action
+            .use_value(|action| {
+                action
+                    .0
+                    .pending_values()
+                    .find(|(t, _)| *t == tag)
+                    .and_then(|(_, value)| value.map(self.serialize))
+            })
+            .ok()
+            .flatten()
+    }
+
+    fn replay(&self, tag: EventTag, value: Option<Vec<u8>>) -> io::Result<()> {
+        let value = value.map(self.deserialize).transpose()?;
+        self.action
+            .use_mut(|action| action.0.schedule_future_value(tag, value))
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Action lock is poisoned"))
+    }
+}
+
+impl RecordedTrigger for Watchdog {
+    fn save_value(&self, _tag: EventTag) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn replay(&self, tag: EventTag, _value: Option<Vec<u8>>) -> io::Result<()> {
+        self.replay_expiry(tag);
+        Ok(())
+    }
+}
+
+/// An event of the recording.
+#[derive(Debug, Eq, PartialEq)]
+struct Record {
+    tag: EventTag,
+    /// None if this is a termination request.
+    trigger: Option<TriggerId>,
+    value: Option<Vec<u8>>,
+}
+
+impl Record {
+    fn write(&self, out: &mut CheckpointWriter) -> io::Result<()> {
+        out.write_tag(self.tag)?;
+        match self.trigger {
+            Some(trigger) => {
+                out.write_u64(RECORD_TRIGGER)?;
+                out.write_u64(trigger.index() as u64)?;
+            }
+            None => out.write_u64(RECORD_TERMINATE)?,
+        }
+        match &self.value {
+            Some(value) => {
+                out.write_u64(1)?;
+                out.write_bytes(value)
+            }
+            None => out.write_u64(0),
+        }
+    }
+
+    /// Returns None at the end of the recording.
+    fn read(input: &mut CheckpointReader) -> io::Result<Option<Self>> {
+        let tag = match input.read_tag() {
+            Ok(tag) => tag,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let trigger = match input.read_u64()? {
+            RECORD_TRIGGER => Some(TriggerId::from_usize(input.read_u64()? as usize)),
+            RECORD_TERMINATE => None,
+            _ => return Err(invalid_data("Unknown kind of record")),
+        };
+        let value = match input.read_u64()? {
+            0 => None,
+            _ => Some(input.read_bytes()?),
+        };
+        Ok(Some(Self { tag, trigger, value }))
+    }
+
+    fn to_event(&self) -> PhysicalEvent {
+        match self.trigger {
+            Some(trigger) => PhysicalEvent::trigger(self.tag, trigger),
+            None => PhysicalEvent::terminate_at(self.tag),
+        }
+    }
+}
+
+/// Writes the physical events received by the scheduler
+/// to a file, see [SchedulerOptions::record].
+pub(super) struct Recorder {
+    sink: Mutex<RecordSink>,
+}
+
+struct RecordSink {
+    out: BufWriter<File>,
+    /// Triggers whose values are not recorded, and for
+    /// which we already warned about it.
+    unrecorded: HashSet<TriggerId>,
+}
+
+impl Recorder {
+    pub(super) fn new(path: &Path) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        CheckpointWriter::new(&mut out).write_header(MAGIC, VERSION)?;
+        out.flush()?;
+        info!("Recording physical events to {}", path.display());
+        Ok(Self {
+            sink: Mutex::new(RecordSink { out, unrecorded: HashSet::new() }),
+        })
+    }
+
+    /// Record an event sent by an asynchronous thread.
+    /// Wake-up events are not recorded.
+    pub(super) fn record(&self, evt: &PhysicalEvent, triggers: &RecordedTriggers, id_registry: &DebugInfoRegistry) {
+        if evt.is_wake_up() {
+            return;
+        }
+        let mut sink = self.sink.lock().unwrap();
+        let value = match evt.trigger_id {
+            Some(id) => match triggers.get(&id) {
+                Some(trigger) => trigger.save_value(evt.tag),
+                None => {
+                    if sink.unrecorded.insert(id) {
+                        warn!(
+                            "Values of {} are not recorded, it will be replayed without values",
+                            id_registry.fmt_component(id)
+                        );
+                    }
+                    None
+                }
+            },
+            None => None,
+        };
+        let record = Record { tag: evt.tag, trigger: evt.trigger_id, value };
+        // the recording is flushed after each event so that it
+        // is complete if the program crashes
+        let RecordSink { out, .. } = &mut *sink;
+        let result = record.write(&mut CheckpointWriter::new(out)).and_then(|_| out.flush());
+        if let Err(e) = result {
+            error!("Could not record physical event: {}", e)
+        }
+    }
+}
+
+/// Feeds recorded physical events to the scheduler,
+/// see [SchedulerOptions::replay].
+pub(super) struct Replay {
+    /// Records that have not been replayed yet, ordered by tag.
+    records: VecDeque<Record>,
+}
+
+impl Replay {
+    pub(super) fn load(path: &Path, triggers: &RecordedTriggers, id_registry: &DebugInfoRegistry) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut records = Self::read(&mut CheckpointReader::new(&mut file))?;
+        // events may reach the scheduler out of order
+        records.sort_by_key(|record| record.tag);
+
+        let num_triggers = id_registry.trigger_ids().count();
+        let mut unreplayed = HashSet::new();
+        for trigger in records.iter().filter_map(|record| record.trigger) {
+            if trigger.index() >= num_triggers {
+                return Err(invalid_data("Recording does not match the program"));
+            }
+            if !triggers.contains_key(&trigger) && unreplayed.insert(trigger) {
+                warn!("Values of {} were not recorded", id_registry.fmt_component(trigger));
+            }
+        }
+        info!("Replaying {} physical events from {}", records.len(), path.display());
+        Ok(Self { records: records.into() })
+    }
+
+    fn read(input: &mut CheckpointReader) -> io::Result<Vec<Record>> {
+        if !input.read_header(MAGIC, VERSION)? {
+            return Err(invalid_data("Not a recording of physical events, or unsupported version"));
+        }
+        let mut records = Vec::new();
+        while let Some(record) = Record::read(input)? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// The recorded events that are after the given tag.
+    pub(super) fn events_after(&self, tag: Option<EventTag>) -> impl Iterator<Item = PhysicalEvent> + '_ {
+        self.records
+            .iter()
+            .filter(move |record| Some(record.tag) > tag)
+            .map(Record::to_event)
+    }
+
+    /// Restore the values of the triggers of the events recorded
+    /// for the given tag. Events recorded for earlier tags are
+    /// discarded.
+    pub(super) fn replay_values(&mut self, tag: EventTag, triggers: &RecordedTriggers) -> io::Result<()> {
+        while self.records.front().map_or(false, |record| record.tag <= tag) {
+            let record = self.records.pop_front().unwrap();
+            if record.tag < tag {
+                continue;
+            }
+            if let Some(trigger) = record.trigger.and_then(|id| triggers.get(&id)) {
+                trigger.replay(record.tag, record.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recording_roundtrip() {
+        let records = vec![
+            Record {
+                tag: tag!(T0 + 10 ms),
+                trigger: Some(TriggerId::FIRST_REGULAR),
+                value: Some(vec![1, 2]),
+            },
+            Record { tag: tag!(T0 + 20 ms), trigger: None, value: None },
+        ];
+        let mut buf = Vec::new();
+        let mut out = CheckpointWriter::new(&mut buf);
+        out.write_header(MAGIC, VERSION).unwrap();
+        for record in &records {
+            record.write(&mut out).unwrap();
+        }
+
+        let read = Replay::read(&mut CheckpointReader::new(&mut buf.as_slice())).unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_replay_restores_action_values() {
+        let action = PhysicalActionRef::<u64>::new(TriggerId::FIRST_REGULAR, None, None, SpacingPolicy::Defer);
+        let recorded = RecordedAction {
+            action: action.clone(),
+            serialize: |v| v.to_le_bytes().to_vec(),
+            deserialize: |bytes| Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+        };
+        action
+            .use_mut(|a| a.0.schedule_future_value(tag!(T0 + 10 ms), Some(42)))
+            .unwrap();
+        let value = recorded.save_value(tag!(T0 + 10 ms));
+        assert!(value.is_some());
+
+        let mut triggers = RecordedTriggers::new();
+        triggers.insert(TriggerId::FIRST_REGULAR, Box::new(recorded));
+        let mut replay = Replay {
+            records: vec![
+                Record {
+                    tag: tag!(T0 + 5 ms),
+                    trigger: Some(TriggerId::FIRST_REGULAR),
+                    value: None,
+                },
+                Record {
+                    tag: tag!(T0 + 20 ms),
+                    trigger: Some(TriggerId::FIRST_REGULAR),
+                    value,
+                },
+            ]
+            .into(),
+        };
+        replay.replay_values(tag!(T0 + 20 ms), &triggers).unwrap();
+
+        assert!(replay.records.is_empty());
+        assert_eq!(action.use_mut(|a| a.0.forget_value(&tag!(T0 + 20 ms))).unwrap(), Some(42));
+        assert_eq!(action.use_mut(|a| a.0.forget_value(&tag!(T0 + 5 ms))).unwrap(), None);
+    }
+}
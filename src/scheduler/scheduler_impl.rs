@@ -24,15 +24,19 @@
 
 //! Home of the scheduler component.
 
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use crossbeam_channel::reconnectable::*;
-
-use super::assembly_impl::RootAssembler;
+use super::assembly_impl::{AssembledTree, RootAssembler};
+use super::federate::{FederateClient, NetworkInputs};
+use super::mutations::Mutator;
 use super::*;
 use crate::assembly::*;
+use crate::federated::{FederateOptions, FOREVER};
 use crate::scheduler::dependencies::DataflowInfo;
+use crate::util::reconnectable::*;
 use crate::*;
 
 /// Construction parameters for the scheduler.
@@ -61,6 +65,87 @@ pub struct SchedulerOptions {
     /// If true, dump the dependency graph to a file before
     /// starting execution.
     pub dump_graph: bool,
+
+    /// If set, this program is a federate of a federated program,
+    /// and connects to the RTI before starting execution. See
+    /// [crate::federated].
+    pub federate: Option<FederateOptions>,
+
+    /// If true, don't wait for physical time to catch up with
+    /// the logical time of each tag, and execute tags as fast
+    /// as possible. This corresponds to the `fast` target
+    /// property of LF. Physical actions are still processed in
+    /// order, at a tag that is never earlier than the one being
+    /// processed when they are scheduled.
+    pub fast: bool,
+
+    /// Source of physical time. If None, uses a [RealTimeClock].
+    /// A [VirtualClock] makes the timestamps of physical actions,
+    /// deadlines and timeouts deterministic, which is useful in tests.
+    /// As the time of such a clock may not advance while a tag is
+    /// processed, physical actions are then scheduled like in fast
+    /// mode, at a tag that is never earlier than the one being processed.
+    /// The clock is ignored when connecting to the RTI of a federation.
+    pub clock: Option<Arc<dyn Clock>>,
+
+    /// If set, record a trace of the execution to a file.
+    /// It contains the start and end of reactions, time
+    /// advances of the scheduler, the scheduling of actions
+    /// and the arrival of asynchronous events.
+    pub trace: Option<TraceOptions>,
+
+    /// What to do when a reaction panics, or reports an
+    /// error with [ReactionCtx::fail].
+    pub failure_policy: FailurePolicy,
+
+    /// Path of the file where checkpoints requested with
+    /// [ReactionCtx::request_checkpoint] are written. Each
+    /// checkpoint replaces the previous one.
+    pub checkpoint: Option<PathBuf>,
+
+    /// If set, record the events sent by asynchronous threads
+    /// to a file, so that the execution can be replayed with
+    /// [Self::replay]. The values of physical actions are only
+    /// recorded if they are registered with
+    /// [ComponentCreator::record_physical_action](crate::assembly::ComponentCreator::record_physical_action).
+    pub record: Option<PathBuf>,
+
+    /// If set, replay the events recorded in the given file
+    /// with [Self::record], at their recorded tags. Events sent
+    /// by asynchronous threads are ignored, so the execution is
+    /// the same as the recorded one. Not supported for federates.
+    pub replay: Option<PathBuf>,
+
+    /// If set, pause the execution before each tag, and read
+    /// debugging commands from stdin. See [DebuggerOptions].
+    pub debugger: Option<DebuggerOptions>,
+}
+
+/// What the scheduler does when a reaction fails, that is,
+/// when it panics or calls [ReactionCtx::fail].
+///
+/// When a panic is caught, the reactor of the reaction may
+/// be left in an inconsistent state, eg if the panic occurred
+/// while it was updating its state variables. Other reactions
+/// of the tag still execute.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FailurePolicy {
+    /// Failures are not caught: the panic unwinds through the
+    /// scheduler, which stops without executing shutdown reactions.
+    /// This is the default.
+    Abort,
+    /// Failures are logged, and execution continues.
+    Log,
+    /// Failures are logged, and the program shuts down at the
+    /// next microstep, executing shutdown reactions. The first
+    /// failure is then returned by [SyncScheduler::try_run_main].
+    Shutdown,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy::Abort
+    }
 }
 
 // Macros are placed a bit out of order to avoid exporting them
@@ -74,6 +159,21 @@ macro_rules! debug_info {
     };
 }
 
+/// Turns a [PhysicalEvent] into an [Event], and records its
+/// arrival if tracing or recording is enabled.
+macro_rules! make_executable {
+    ($scheduler:expr, $evt:expr) => {{
+        let evt: PhysicalEvent = $evt;
+        if let Some(tracer) = &$scheduler.tracer {
+            tracer.record_physical_event(&evt);
+        }
+        if let Some(recorder) = &$scheduler.recorder {
+            recorder.record(&evt, &$scheduler.recorded_triggers, &$scheduler.id_registry);
+        }
+        evt.make_executable($scheduler.dataflow)
+    }};
+}
+
 macro_rules! push_event {
     ($scheduler:expr, $evt:expr) => {{
         trace!("Pushing {}", debug_info!($scheduler).display_event(&$evt));
@@ -81,6 +181,16 @@ macro_rules! push_event {
     }};
 }
 
+/// Push all the events of an iterator at once, see [EventQueue::extend].
+macro_rules! push_events {
+    ($scheduler:expr, $evts:expr) => {{
+        let debug = debug_info!($scheduler);
+        $scheduler
+            .event_queue
+            .extend($evts.inspect(|evt| trace!("Pushing {}", debug.display_event(evt))));
+    }};
+}
+
 /// The runtime scheduler.
 ///
 /// Lifetime parameters: 'x and 't are carried around everywhere,
@@ -95,9 +205,21 @@ pub struct SyncScheduler<'x> {
     /// order reactions properly for each tag.
     dataflow: &'x DataflowInfo,
 
+    /// The dependency graph from which [Self::dataflow] is
+    /// computed, kept to add reactors to the running program,
+    /// see [Self::apply_mutations].
+    graph: DepGraph,
+
     /// All reactors.
     reactors: ReactorVec<'x>,
 
+    /// Connections with an after-delay, which forward
+    /// values of their upstream port to a later tag.
+    delayed_connections: DelayedConnections,
+
+    /// Modes of reactors, and which ones are active.
+    modes: ModeInfo,
+
     /// Pending events/ tags to process.
     event_queue: EventQueue<'x>,
 
@@ -125,13 +247,135 @@ pub struct SyncScheduler<'x> {
 
     /// Debug information.
     id_registry: DebugInfoRegistry,
+
+    /// Connection to the RTI, if this program is a federate.
+    federate: Option<FederateClient>,
+
+    /// The tag being processed, shared with asynchronous
+    /// threads so that they schedule physical actions at
+    /// a later tag. Only set in fast mode, where logical
+    /// time may be ahead of physical time, and with a custom
+    /// clock, whose time may not advance during a tag.
+    processing_tag: Option<Arc<Mutex<EventTag>>>,
+
+    /// Whether to skip waiting for physical time, see [SchedulerOptions::fast].
+    fast: bool,
+
+    /// Source of physical time.
+    clock: Arc<dyn Clock>,
+
+    /// Records an execution trace, if enabled.
+    tracer: Option<Tracer>,
+
+    /// Number of tags processed so far.
+    num_tags: u64,
+    /// Number of reactions executed so far.
+    num_reactions: u64,
+
+    /// What to do when a reaction fails.
+    failure_policy: FailurePolicy,
+    /// Where to write checkpoints, see [SchedulerOptions::checkpoint].
+    checkpoint_path: Option<PathBuf>,
+    /// Triggers whose events can be recorded and replayed.
+    recorded_triggers: RecordedTriggers,
+    /// Handles to clear the ports and physical actions
+    /// present at a tag, see [Self::cleanup_tag].
+    tag_cleanups: TagCleanups,
+    /// All watchdogs of the program.
+    watchdogs: Vec<Watchdog>,
+    /// The ports and actions that are present at the latest
+    /// processed tag, and have not been cleaned up yet.
+    present: Vec<TriggerId>,
+    /// Whether cleanup is deferred to the next processed tag,
+    /// see [Self::defer_cleanup].
+    cleanup_deferred: bool,
+    /// Records physical events, see [SchedulerOptions::record].
+    recorder: Option<Recorder>,
+    /// Recorded events to replay, see [SchedulerOptions::replay].
+    replay: Option<Replay>,
+    /// Step debugger, see [SchedulerOptions::debugger].
+    debugger: Option<Debugger>,
+    /// The first failure of a reaction that caused the
+    /// program to shut down, see [FailurePolicy::Shutdown],
+    /// or the loss of the connection to the RTI.
+    failure: Option<RuntimeError>,
 }
 
 impl<'x> SyncScheduler<'x> {
+    /// Assemble the program and run it in this thread,
+    /// until it shuts down. Panics if the program cannot
+    /// be started, see [Self::try_run_main] to handle
+    /// such errors.
     pub fn run_main<R: ReactorInitializer + 'static>(options: SchedulerOptions, args: R::Params) {
+        if let Err(e) = Self::try_run_main::<R>(options, args) {
+            panic!("{}", e)
+        }
+    }
+
+    /// Assemble the program and run it in this thread,
+    /// until it shuts down. Returns an error if the program
+    /// cannot be assembled or started.
+    pub fn try_run_main<R: ReactorInitializer + 'static>(
+        options: SchedulerOptions,
+        args: R::Params,
+    ) -> Result<RunSummary, RuntimeError> {
+        Self::run::<R>(options, args, None, |_| {})
+    }
+
+    /// Assemble the program and resume it in this thread from
+    /// the given checkpoint, written by a previous execution of
+    /// the same program (see [ReactionCtx::request_checkpoint]).
+    /// Startup reactions are not executed again.
+    ///
+    /// Physical time is not saved in the checkpoint: the origin
+    /// of the logical timeline is moved back so that execution
+    /// continues from the current physical time.
+    pub fn try_resume_main<R: ReactorInitializer + 'static>(
+        options: SchedulerOptions,
+        args: R::Params,
+        checkpoint: &Path,
+    ) -> Result<RunSummary, RuntimeError> {
+        Self::run::<R>(options, args, Some(checkpoint), |_| {})
+    }
+
+    /// Assemble the program and run it on a new thread.
+    /// The returned handle can be used to interact with
+    /// the program, and wait for its end. Returns an error
+    /// if the program cannot be assembled or started.
+    pub fn start<R: ReactorInitializer + 'static>(
+        options: SchedulerOptions,
+        args: R::Params,
+    ) -> Result<SchedulerHandle, RuntimeError>
+    where
+        R::Params: Send,
+    {
+        SchedulerHandle::spawn::<R>(options, args)
+    }
+
+    /// Assemble the program and run it in this thread, or resume
+    /// it from a checkpoint. The `on_start` callback receives a
+    /// context linked to the scheduler, before execution starts.
+    pub(super) fn run<R: ReactorInitializer + 'static>(
+        options: SchedulerOptions,
+        args: R::Params,
+        resume: Option<&Path>,
+        on_start: impl FnOnce(AsyncCtx),
+    ) -> Result<RunSummary, RuntimeError> {
+        Self::run_assembled(options, || RootAssembler::assemble_tree::<R>(args), resume, on_start)
+    }
+
+    /// Like [Self::run], for a program assembled by the given
+    /// function instead of from a main reactor type.
+    pub(super) fn run_assembled(
+        mut options: SchedulerOptions,
+        assemble: impl FnOnce() -> Result<AssembledTree, AssemblyFailure>,
+        resume: Option<&Path>,
+        on_start: impl FnOnce(AsyncCtx),
+    ) -> Result<RunSummary, RuntimeError> {
         let start = Instant::now();
         info!("Starting assembly...");
-        let (reactors, graph, id_registry) = RootAssembler::assemble_tree::<R>(args);
+        let (reactors, mut graph, id_registry, network_inputs, delayed_connections, recorded_triggers, tag_cleanups, watchdogs) =
+            assemble()?;
         let time = Instant::now() - start;
         info!("Assembly done in {} µs...", time.as_micros());
 
@@ -143,12 +387,16 @@ impl<'x> SyncScheduler<'x> {
 
             File::create(path.clone())
                 .and_then(|mut dot_file| writeln!(dot_file, "{}", graph.format_dot(&id_registry)))
-                .expect("Error while writing DOT file");
+                .map_err(RuntimeError::Io)?;
             eprintln!("Wrote dot file to {}", path.to_string_lossy());
         }
 
+        let modes = std::mem::take(&mut graph.modes)
+            .finish(&id_registry)
+            .map_err(|e| e.lift(&id_registry))?;
+
         // collect dependency information
-        let dataflow_info = DataflowInfo::new(graph).map_err(|e| e.lift(&id_registry)).unwrap();
+        let dataflow_info = DataflowInfo::new(&graph).map_err(|e| e.lift(&id_registry))?;
 
         // Using thread::scope here introduces an unnamed lifetime for
         // the scope, which is captured as 't by the SyncScheduler.
@@ -156,12 +404,75 @@ impl<'x> SyncScheduler<'x> {
         // dataflow_info outlives 't, so that physical contexts
         // can be spawned in threads that capture references
         // to 'x.
-        let initial_time = Instant::now();
+        let clock = options.clock.clone().unwrap_or_else(|| Arc::new(RealTimeClock));
+        let (federate, initial_time) = match options.federate.take() {
+            Some(federate) => {
+                let (client, initial_time) = FederateClient::connect(&federate).map_err(RuntimeError::Federation)?;
+                (Some(client), initial_time)
+            }
+            None => (None, clock.now()),
+        };
+        let checkpoint = resume.map(Checkpoint::load).transpose().map_err(RuntimeError::Checkpoint)?;
+        let initial_time = match &checkpoint {
+            // the tag of the checkpoint is now
+            Some(checkpoint) => initial_time.checked_sub(checkpoint.tag.offset_from_t0).ok_or_else(|| {
+                RuntimeError::Checkpoint(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Tag of the checkpoint is too far in time",
+                ))
+            })?,
+            None => initial_time,
+        };
+        let tracer = options
+            .trace
+            .take()
+            .map(|trace| Tracer::new(&trace, &id_registry, initial_time, clock.clone()))
+            .transpose()
+            .map_err(RuntimeError::Io)?;
+        if options.replay.is_some() && (options.record.is_some() || federate.is_some()) {
+            return Err(RuntimeError::Replay(io::Error::new(
+                io::ErrorKind::Other,
+                "Cannot replay a federate, or record a replayed execution",
+            )));
+        }
+        let recorder = options
+            .record
+            .take()
+            .map(|path| Recorder::new(&path))
+            .transpose()
+            .map_err(RuntimeError::Io)?;
+        let replay = options
+            .replay
+            .take()
+            .map(|path| Replay::load(&path, &recorded_triggers, &id_registry))
+            .transpose()
+            .map_err(RuntimeError::Replay)?;
         #[cfg(feature = "parallel-runtime")]
         let rayon_thread_pool = rayon::ThreadPoolBuilder::new().num_threads(options.threads).build().unwrap();
 
-        let scheduler = SyncScheduler::new(options, id_registry, &dataflow_info, reactors, initial_time);
-
+        let scheduler = SyncScheduler::new(
+            options,
+            id_registry,
+            &dataflow_info,
+            graph,
+            reactors,
+            initial_time,
+            federate,
+            network_inputs,
+            delayed_connections,
+            modes,
+            clock,
+            tracer,
+            recorded_triggers,
+            tag_cleanups,
+            watchdogs,
+            recorder,
+            replay,
+        )?;
+        on_start(scheduler.async_ctx());
+
+        #[allow(clippy::needless_late_init)]
+        let summary;
         cfg_if::cfg_if! {
             if #[cfg(feature = "parallel-runtime")] {
                 /// The unsafe impl is safe if scheduler instances
@@ -172,25 +483,36 @@ impl<'x> SyncScheduler<'x> {
                 unsafe impl Send for SyncScheduler<'_> {}
 
                 // install makes calls to parallel iterators use that thread pool
-                rayon_thread_pool.install(|| scheduler.launch_event_loop());
+                summary = rayon_thread_pool.install(|| scheduler.launch_event_loop(checkpoint));
             } else {
-                scheduler.launch_event_loop();
+                summary = scheduler.launch_event_loop(checkpoint);
             }
         }
+        summary
     }
 
-    /// Launch the event loop in this thread.
-    fn launch_event_loop(mut self) {
+    /// Launch the event loop in this thread, starting
+    /// from the checkpoint if there is one.
+    fn launch_event_loop(mut self, checkpoint: Option<Checkpoint>) -> Result<RunSummary, RuntimeError> {
         /************************************************
          * This is the main event loop of the scheduler *
          ************************************************/
 
-        self.startup();
+        match checkpoint {
+            Some(checkpoint) => self.resume(checkpoint).map_err(RuntimeError::Checkpoint)?,
+            None => self.startup(),
+        }
+        if let Some(replay) = &self.replay {
+            for evt in replay.events_after(self.latest_processed_tag) {
+                if let Some(evt) = evt.make_executable(self.dataflow) {
+                    push_event!(self, evt);
+                }
+            }
+        }
 
         loop {
             // flush pending events, this doesn't block
-            for evt in self.rx.try_iter() {
-                let evt = evt.make_executable(self.dataflow);
+            for evt in self.rx.try_iter().filter_map(|evt| make_executable!(self, evt)) {
                 push_event!(self, evt);
             }
 
@@ -200,33 +522,49 @@ impl<'x> SyncScheduler<'x> {
                     break;
                 }
                 trace!("Processing event {}", self.debug().display_event(&evt));
-                match self.catch_up_physical_time(evt.tag.to_logical_time(self.initial_time)) {
-                    Ok(_) => {}
-                    Err(async_event) => {
-                        let async_event = async_event.make_executable(self.dataflow);
-                        // an asynchronous event woke our sleep
-                        if async_event.tag < evt.tag {
-                            // reinsert both events to order them and try again.
-                            push_event!(self, evt);
-                            push_event!(self, async_event);
-                            continue;
-                        } else {
-                            // we can process this event first and not care about the async event
-                            push_event!(self, async_event);
-                        }
+                self.trace(TracePoint::AdvancingTimeStarts, evt.tag);
+                let ready = self
+                    .wait_for_tag_advance_grant(evt.tag)
+                    .and_then(|_| self.catch_up_physical_time(evt.tag.to_logical_time(self.initial_time)))
+                    .and_then(|_| self.start_tag(evt.tag));
+                self.trace(TracePoint::AdvancingTimeEnds, evt.tag);
+                if let Err(async_event) = ready {
+                    // an asynchronous event woke our sleep,
+                    // reinsert both events to order them and try again.
+                    push_event!(self, evt);
+                    if let Some(async_event) = make_executable!(self, async_event) {
+                        push_event!(self, async_event);
                     }
-                };
+                    continue;
+                }
                 // at this point we're at the correct time
+                if let Some(replay) = &mut self.replay {
+                    replay
+                        .replay_values(evt.tag, &self.recorded_triggers)
+                        .map_err(RuntimeError::Replay)?;
+                }
 
                 if evt.terminate || self.shutdown_time == Some(evt.tag) {
-                    return self.shutdown(evt.tag, evt.reactions);
+                    self.shutdown(evt.tag, &evt.triggers, evt.reactions);
+                    return self.finish();
                 }
 
-                self.process_tag(false, evt.tag, evt.reactions);
+                self.process_tag(false, evt.tag, &evt.triggers, evt.reactions);
+                self.notify_tag_complete(evt.tag);
+            } else if self.federate.as_ref().map_or(false, |federate| federate.is_granted(FOREVER)) {
+                info!("No more messages can reach this federate, shutting down.");
+                break;
+            } else if let Some(error) = self.federate.as_ref().and_then(FederateClient::take_connection_error) {
+                self.lose_rti_connection(self.latest_processed_tag.unwrap_or(EventTag::ORIGIN), error);
+                break;
+            } else if self.replay.is_some() {
+                info!("All recorded events have been replayed, shutting down.");
+                break;
             } else if let Some(evt) = self.receive_event() {
-                let evt = evt.make_executable(self.dataflow);
                 // this may block
-                push_event!(self, evt);
+                if let Some(evt) = make_executable!(self, evt) {
+                    push_event!(self, evt);
+                }
                 continue;
             } else {
                 // all senders have hung up, or timeout
@@ -235,8 +573,14 @@ impl<'x> SyncScheduler<'x> {
             }
         } // end loop
 
-        let shutdown_tag = self.shutdown_time.unwrap_or_else(|| EventTag::now(self.initial_time));
-        self.shutdown(shutdown_tag, None);
+        let now = EventTag::absolute(self.initial_time, self.clock.now());
+        let shutdown_tag = self.shutdown_time.unwrap_or_else(|| match self.latest_processed_tag {
+            // in fast mode, logical time may be ahead of physical time
+            Some(latest) if latest >= now => latest.next_microstep(),
+            _ => now,
+        });
+        self.shutdown(shutdown_tag, &[], None);
+        self.finish()
 
         // self destructor is called here
     }
@@ -244,13 +588,26 @@ impl<'x> SyncScheduler<'x> {
     /// Creates a new scheduler. An empty scheduler doesn't
     /// do anything unless some events are pushed to the queue.
     /// See [Self::launch_event_loop].
-    fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn new(
         options: SchedulerOptions,
         id_registry: DebugInfoRegistry,
         dependency_info: &'x DataflowInfo,
+        graph: DepGraph,
         reactors: ReactorVec<'x>,
         initial_time: Instant,
-    ) -> Self {
+        federate: Option<FederateClient>,
+        network_inputs: NetworkInputs,
+        delayed_connections: DelayedConnections,
+        modes: ModeInfo,
+        clock: Arc<dyn Clock>,
+        tracer: Option<Tracer>,
+        recorded_triggers: RecordedTriggers,
+        tag_cleanups: TagCleanups,
+        watchdogs: Vec<Watchdog>,
+        recorder: Option<Recorder>,
+        replay: Option<Replay>,
+    ) -> Result<Self, RuntimeError> {
         if !cfg!(feature = "parallel-runtime") && options.threads != 0 {
             warn!("'workers' runtime parameter has no effect unless feature 'parallel-runtime' is enabled")
         }
@@ -260,11 +617,21 @@ impl<'x> SyncScheduler<'x> {
         }
 
         let (_, rx) = unbounded::<PhysicalEvent>();
-        Self {
+        match &federate {
+            Some(federate) => federate
+                .spawn_receiver(rx.new_sender(), network_inputs)
+                .map_err(RuntimeError::Federation)?,
+            None if !network_inputs.is_empty() => warn!("Network inputs have no effect unless running as a federate"),
+            None => {}
+        }
+
+        Ok(Self {
             rx,
 
             event_queue: Default::default(),
             reactors,
+            delayed_connections,
+            modes,
 
             initial_time,
             latest_processed_tag: None,
@@ -274,11 +641,175 @@ impl<'x> SyncScheduler<'x> {
                 shutdown_tag
             }),
             dataflow: dependency_info,
+            graph,
             id_registry,
             was_terminated: Default::default(),
+            federate,
+            processing_tag: (options.fast || options.clock.is_some()).then(|| Arc::new(Mutex::new(EventTag::ORIGIN))),
+            fast: options.fast,
+            clock,
+            tracer,
+            num_tags: 0,
+            num_reactions: 0,
+            failure_policy: options.failure_policy,
+            checkpoint_path: options.checkpoint,
+            recorded_triggers,
+            tag_cleanups,
+            watchdogs,
+            present: Vec::new(),
+            cleanup_deferred: false,
+            recorder,
+            replay,
+            debugger: options.debugger.map(Debugger::stdio),
+            failure: None,
+        })
+    }
+
+    /// Create a context to interact with the scheduler
+    /// from another thread.
+    fn async_ctx(&self) -> AsyncCtx {
+        AsyncCtx::new(
+            self.rx.new_sender(),
+            self.initial_time,
+            self.was_terminated.clone(),
+            self.processing_tag.clone(),
+            self.clock.clone(),
+            self.replay.is_some(),
+        )
+    }
+
+    /// Returns the result of the execution, once the
+    /// scheduler has shut down.
+    fn finish(&mut self) -> Result<RunSummary, RuntimeError> {
+        match self.failure.take() {
+            Some(failure) => Err(failure),
+            None => Ok(RunSummary {
+                final_tag: self.latest_processed_tag.unwrap_or(EventTag::ORIGIN),
+                num_tags: self.num_tags,
+                num_reactions: self.num_reactions,
+            }),
         }
     }
 
+    /// Queue the startup reactions, to drive the scheduler tag
+    /// by tag with [Self::step_to] instead of the event loop.
+    pub(super) fn queue_startup(&mut self) {
+        let startup_reactions = self.dataflow.reactions_triggered_by(&TriggerId::STARTUP);
+        let evt = Event::execute(EventTag::ORIGIN, TriggerId::STARTUP, Cow::Borrowed(startup_reactions));
+        push_event!(self, evt);
+    }
+
+    /// Process the pending events up to the given tag, and then
+    /// that tag. When that tag starts, `set_inputs` is called to
+    /// set the inputs present at that tag, and returns their IDs.
+    /// Events sent by asynchronous threads in the meantime are
+    /// processed too. Returns false if the program has shut down.
+    pub(super) fn step_to(&mut self, target: EventTag, set_inputs: impl FnOnce() -> Vec<TriggerId>) -> bool {
+        assert!(
+            self.latest_processed_tag.map_or(true, |latest| latest < target),
+            "Tag {} has already been processed",
+            target
+        );
+        let mut set_inputs = Some(set_inputs);
+        loop {
+            if self.was_terminated.load(Ordering::SeqCst) {
+                return false;
+            }
+            for evt in self.rx.try_iter().filter_map(|evt| make_executable!(self, evt)) {
+                push_event!(self, evt);
+            }
+
+            let next_tag = self.event_queue.earliest_tag();
+            let mut evt = match (next_tag, &set_inputs) {
+                (Some(tag), _) if tag <= target => self.event_queue.take_earliest().unwrap(),
+                (_, Some(_)) => Event {
+                    tag: target,
+                    reactions: None,
+                    triggers: Default::default(),
+                    terminate: false,
+                },
+                (_, None) => return true,
+            };
+            if let Some(shutdown_tag) = self.shutdown_time.filter(|&shutdown_tag| shutdown_tag < evt.tag) {
+                self.shutdown(shutdown_tag, &[], None);
+                return false;
+            }
+            if let Err(async_event) = self.start_tag(evt.tag) {
+                push_event!(self, evt);
+                if let Some(async_event) = make_executable!(self, async_event) {
+                    push_event!(self, async_event);
+                }
+                continue;
+            }
+            if evt.tag == target {
+                if let Some(set_inputs) = set_inputs.take() {
+                    // the previous tag must be cleaned up before the inputs are set
+                    self.cleanup_tag();
+                    for trigger in set_inputs() {
+                        let reactions = Cow::Borrowed(self.dataflow.reactions_triggered_by(&trigger));
+                        evt.absorb(Event::execute(target, trigger, reactions));
+                    }
+                }
+            }
+
+            if evt.terminate || self.shutdown_time == Some(evt.tag) {
+                self.shutdown(evt.tag, &evt.triggers, evt.reactions);
+                return false;
+            }
+            self.process_tag(false, evt.tag, &evt.triggers, evt.reactions);
+        }
+    }
+
+    /// Shut down the program at the given tag, when it is reached
+    /// with [Self::step_to].
+    pub(super) fn stop_at(&mut self, tag: EventTag) {
+        self.shutdown_time = Some(tag);
+    }
+
+    /// The latest tag that has been processed, if any.
+    pub(super) fn current_tag(&self) -> Option<EventTag> {
+        self.latest_processed_tag
+    }
+
+    /// The tag of the earliest pending event, if any.
+    pub(super) fn next_event_tag(&self) -> Option<EventTag> {
+        self.event_queue.earliest_tag()
+    }
+
+    /// Events that are pending in the queue, ordered by tag.
+    pub(super) fn pending_events(&self) -> impl Iterator<Item = &Event<'x>> {
+        self.event_queue.iter()
+    }
+
+    /// Run the given function with a reaction context at the
+    /// latest processed tag, to schedule or cancel actions from
+    /// outside of a reaction.
+    pub(super) fn with_ctx<O>(&mut self, f: impl FnOnce(&mut ReactionCtx<'_, 'x>) -> O) -> O {
+        let tag = self.latest_processed_tag.unwrap_or(EventTag::ORIGIN);
+        let mut ctx = self.new_reaction_ctx(
+            tag,
+            None,
+            &self.rx,
+            debug_info!(self),
+            &self.was_terminated,
+            false,
+            self.federate.as_ref(),
+            self.processing_tag.as_ref(),
+            &self.clock,
+            self.tracer.as_ref(),
+            self.failure_policy,
+            &self.modes,
+            self.replay.is_some(),
+        );
+        let result = f(&mut ctx);
+
+        push_events!(self, ctx.insides.future_events.drain(..));
+        for ScheduleToken { trigger, tag } in ctx.insides.cancellations.drain(..) {
+            self.event_queue.cancel(tag, trigger, self.dataflow);
+        }
+        result
+    }
+
     /// Fix the origin of the logical timeline to the current
     /// physical time, and runs the startup reactions
     /// of all reactors.
@@ -287,22 +818,217 @@ impl<'x> SyncScheduler<'x> {
         debug_assert!(!self.reactors.is_empty(), "No registered reactors");
 
         let startup_reactions = self.dataflow.reactions_triggered_by(&TriggerId::STARTUP);
-        self.process_tag(false, EventTag::ORIGIN, Some(Cow::Borrowed(startup_reactions)))
+        let mut triggers = vec![TriggerId::STARTUP];
+        let startup_reactions = self.acquire_tag(EventTag::ORIGIN, &mut triggers, Some(Cow::Borrowed(startup_reactions)));
+        self.process_tag(false, EventTag::ORIGIN, &triggers, startup_reactions);
+        self.notify_tag_complete(EventTag::ORIGIN);
     }
 
-    fn shutdown(&mut self, shutdown_tag: EventTag, reactions: ReactionPlan<'x>) {
+    /// Restore the state of reactors and the event queue from
+    /// a checkpoint, instead of running startup reactions.
+    fn resume(&mut self, checkpoint: Checkpoint) -> io::Result<()> {
+        info!("Resuming from checkpoint at {}...", checkpoint.tag);
+        let mismatch = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        for (id, state) in checkpoint.reactors {
+            let reactor = self
+                .reactors
+                .get_mut(id)
+                .and_then(|reactor| reactor.as_checkpointable())
+                .ok_or_else(|| mismatch("Checkpoint does not match the program"))?;
+            reactor.restore_state(&mut CheckpointReader::new(&mut state.as_slice()))?;
+        }
+        for (tag, triggers, terminate) in checkpoint.events {
+            let reactions = triggers.iter().fold(None, |plan, trigger| {
+                let reactions = Some(Cow::Borrowed(self.dataflow.reactions_triggered_by(trigger)));
+                ExecutableReactions::merge_cows(plan, reactions)
+            });
+            let evt = Event {
+                tag,
+                reactions,
+                triggers: triggers.into_iter().collect(),
+                terminate,
+            };
+            push_event!(self, evt)
+        }
+        self.latest_processed_tag = Some(checkpoint.tag);
+        if let Some(processing_tag) = &self.processing_tag {
+            *processing_tag.lock().unwrap() = checkpoint.tag;
+        }
+        Ok(())
+    }
+
+    /// Write a checkpoint of the program at the end of the given tag.
+    fn save_checkpoint(&mut self, tag: EventTag) -> io::Result<()> {
+        let path = self
+            .checkpoint_path
+            .clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No checkpoint path set in the scheduler options"))?;
+        if self.federate.is_some() || !self.delayed_connections.is_empty() || !self.modes.is_empty() || !self.watchdogs.is_empty()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Federates, delayed connections, modes and watchdogs are not supported in checkpoints",
+            ));
+        }
+
+        let mut reactors = Vec::with_capacity(self.reactors.len());
+        for reactor in &mut self.reactors {
+            let id = reactor.id();
+            let reactor = reactor.as_checkpointable().ok_or_else(|| {
+                let name = self.id_registry.get_debug_info(id).to_string();
+                io::Error::new(io::ErrorKind::Other, format!("Reactor {} does not support checkpoints", name))
+            })?;
+            let mut state = Vec::new();
+            reactor.save_state(&mut CheckpointWriter::new(&mut state))?;
+            reactors.push((id, state));
+        }
+        let events = self
+            .event_queue
+            .iter()
+            .map(|evt| (evt.tag, evt.triggers.to_vec(), evt.terminate))
+            .collect();
+
+        Checkpoint { tag, events, reactors }.save(&path)?;
+        info!("Wrote checkpoint at {} to {}", tag, path.display());
+        Ok(())
+    }
+
+    /// Process the shutdown tag. The triggers are those of the
+    /// event that terminates the program, if there is one.
+    fn shutdown(&mut self, shutdown_tag: EventTag, triggers: &[TriggerId], reactions: ReactionPlan<'x>) {
         info!("Scheduler is shutting down, at {}", shutdown_tag);
         self.shutdown_time = Some(shutdown_tag);
         let default_plan: ReactionPlan<'x> = Some(Cow::Borrowed(self.dataflow.reactions_triggered_by(&TriggerId::SHUTDOWN)));
         let reactions = ExecutableReactions::merge_cows(reactions, default_plan);
-
-        self.process_tag(true, shutdown_tag, reactions);
+        let mut triggers = triggers.to_vec();
+        triggers.push(TriggerId::SHUTDOWN);
+        let reactions = self.acquire_tag(shutdown_tag, &mut triggers, reactions);
+        self.process_tag(true, shutdown_tag, &triggers, reactions);
+        self.notify_tag_complete(shutdown_tag);
+        if let Some(federate) = &self.federate {
+            federate.resign();
+        }
 
         // notify concurrent threads.
         self.was_terminated.store(true, Ordering::SeqCst);
+        for watchdog in &self.watchdogs {
+            watchdog.wake();
+        }
         info!("Scheduler has been shut down")
     }
 
+    /// Wait until the RTI grants the given tag, if this
+    /// program is a federate, and start the tag. Events
+    /// received in the meantime for that tag or an earlier
+    /// one are merged into the given triggers and reactions.
+    fn acquire_tag(&mut self, tag: EventTag, triggers: &mut Vec<TriggerId>, mut reactions: ReactionPlan<'x>) -> ReactionPlan<'x> {
+        self.trace(TracePoint::AdvancingTimeStarts, tag);
+        while let Err(evt) = self.wait_for_tag_advance_grant(tag).and_then(|_| self.start_tag(tag)) {
+            if let Some(evt) = make_executable!(self, evt) {
+                triggers.extend_from_slice(&evt.triggers);
+                reactions = ExecutableReactions::merge_cows(reactions, evt.reactions);
+            }
+        }
+        self.trace(TracePoint::AdvancingTimeEnds, tag);
+        reactions
+    }
+
+    /// If this program is a federate, wait until the RTI
+    /// grants the given tag. Events received in the meantime
+    /// for a later tag are pushed to the queue. If an event is
+    /// received for that tag or an earlier one, it is returned
+    /// as an error, as it must be processed first, or with
+    /// the reactions of that tag.
+    ///
+    /// If the connection to the RTI is lost, the federate
+    /// stops waiting for grants and shuts down, see
+    /// [Self::lose_rti_connection].
+    fn wait_for_tag_advance_grant(&mut self, tag: EventTag) -> Result<(), PhysicalEvent> {
+        match &self.federate {
+            Some(federate) if !federate.is_granted(tag) => {
+                trace!("  - Waiting for the RTI to grant tag {}", tag);
+                federate.send_next_event_tag(tag);
+            }
+            _ => return Ok(()),
+        }
+
+        while let Some(federate) = self.federate.as_ref().filter(|federate| !federate.is_granted(tag)) {
+            if let Some(error) = federate.take_connection_error() {
+                self.lose_rti_connection(tag, error);
+                break;
+            }
+            match self.rx.recv() {
+                Ok(evt) if evt.tag <= tag && !evt.is_wake_up() => return Err(evt),
+                Ok(evt) => {
+                    if let Some(evt) = make_executable!(self, evt) {
+                        push_event!(self, evt);
+                    }
+                }
+                Err(_) => {
+                    let error = io::Error::new(io::ErrorKind::ConnectionAborted, "No more messages from the RTI");
+                    self.lose_rti_connection(tag, error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that the connection to the RTI was lost. The
+    /// federate then runs on its own and shuts down at the
+    /// microstep after the given tag, and the error is
+    /// returned by [Self::try_run_main].
+    fn lose_rti_connection(&mut self, tag: EventTag, error: io::Error) {
+        error!("Lost the connection to the RTI, shutting down: {}", error);
+        self.federate = None;
+        if self.failure.is_none() {
+            self.failure = Some(RuntimeError::Federation(error));
+        }
+        let shutdown_tag = tag.next_microstep();
+        self.shutdown_time = Some(self.shutdown_time.map_or(shutdown_tag, |t| t.min(shutdown_tag)));
+    }
+
+    /// Publish the tag we are about to process to asynchronous
+    /// threads, if they need it. From then on, they won't send
+    /// events for that tag or an earlier one. Events they have
+    /// sent before for such tags are returned as an error, as
+    /// they must be processed first, or with the reactions of
+    /// that tag.
+    fn start_tag(&mut self, tag: EventTag) -> Result<(), PhysicalEvent> {
+        if self.federate.is_none() && self.processing_tag.is_none() {
+            return Ok(());
+        }
+        if let Some(federate) = &self.federate {
+            federate.start_tag(tag);
+        }
+        if let Some(processing_tag) = &self.processing_tag {
+            *processing_tag.lock().unwrap() = tag;
+        }
+
+        while let Some(evt) = self.rx.try_iter().next() {
+            if evt.tag <= tag && !evt.is_wake_up() {
+                return Err(evt);
+            } else if let Some(evt) = make_executable!(self, evt) {
+                push_event!(self, evt);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn trace(&self, point: TracePoint, tag: EventTag) {
+        if let Some(tracer) = &self.tracer {
+            tracer.record(point, tag)
+        }
+    }
+
+    /// Notify the RTI that the given tag has been processed,
+    /// if this program is a federate.
+    fn notify_tag_complete(&self, tag: EventTag) {
+        if let Some(federate) = &self.federate {
+            federate.send_logical_tag_complete(tag);
+        }
+    }
+
     /// Returns whether the given event should be ignored and
     /// the event loop be terminated. This would be the case
     /// if the tag of the event is later than the projected
@@ -315,11 +1041,31 @@ impl<'x> SyncScheduler<'x> {
     /// Wait for an asynchronous event for as long as we can
     /// expect it.
     fn receive_event(&mut self) -> Option<PhysicalEvent> {
+        if let Some(federate) = &self.federate {
+            // we have nothing to do unless another federate sends us a message
+            federate.send_next_event_tag(FOREVER);
+        }
         if let Some(shutdown_t) = self.shutdown_time {
             let absolute = shutdown_t.to_logical_time(self.initial_time);
-            if let Some(timeout) = absolute.checked_duration_since(Instant::now()) {
-                trace!("Will wait for asynchronous event {} ns", timeout.as_nanos());
-                self.rx.recv_timeout(timeout).ok()
+            if self.fast {
+                // logical time does not follow physical time, so
+                // the program jumps to the shutdown tag instead
+                trace!("Will not wait for asynchronous event in fast mode");
+                self.rx.try_iter().next()
+            } else if self.clock.now() < absolute {
+                match self
+                    .clock
+                    .real_time_until(absolute, &ClockWaker::scheduler(self.rx.new_sender()))
+                {
+                    Some(timeout) => {
+                        trace!("Will wait for asynchronous event {} ns", timeout.as_nanos());
+                        self.rx.recv_timeout(timeout).ok()
+                    }
+                    None => {
+                        trace!("Will wait for asynchronous event until the clock advances");
+                        self.rx.recv().ok()
+                    }
+                }
             } else {
                 trace!("Cannot wait, already past programmed shutdown time...");
                 None
@@ -333,29 +1079,41 @@ impl<'x> SyncScheduler<'x> {
     /// Sleep/wait until the given time OR an asynchronous
     /// event is received first.
     fn catch_up_physical_time(&mut self, target: Instant) -> Result<(), PhysicalEvent> {
-        let now = Instant::now();
+        if self.fast {
+            return Ok(());
+        }
+        let now = self.clock.now();
 
         if now < target {
-            let t = target - now;
-            trace!("  - Need to sleep {} ns", t.as_nanos());
-            // we use recv_timeout as a thread::sleep so that
-            // our sleep is interrupted properly when an async
-            // event arrives
-            match self.rx.recv_timeout(t) {
-                Ok(async_evt) => {
-                    trace!(
-                        "  - Sleep interrupted by async event for tag {}, going back to queue",
-                        async_evt.tag
-                    );
-                    return Err(async_evt);
-                }
-                Err(RecvTimeoutError::Timeout) => { /*great*/ }
-                Err(RecvTimeoutError::Disconnected) => {
-                    // ok, there are no physical actions in the program so it's useless to block on self.rx
-                    // we still need to wait though..
-                    if let Some(remaining) = target.checked_duration_since(Instant::now()) {
-                        std::thread::sleep(remaining);
+            trace!("  - Need to sleep {} ns", (target - now).as_nanos());
+            while self.clock.now() < target {
+                // we block on self.rx as a thread::sleep so that
+                // our sleep is interrupted properly when an async
+                // event arrives. The clock may also wake us up
+                // when it advances.
+                let timeout = self
+                    .clock
+                    .real_time_until(target, &ClockWaker::scheduler(self.rx.new_sender()));
+                let received = match timeout {
+                    Some(timeout) => self.rx.recv_timeout(timeout),
+                    None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(evt) if evt.is_wake_up() => { /*check the time again*/ }
+                    Ok(async_evt) => {
+                        trace!(
+                            "  - Sleep interrupted by async event for tag {}, going back to queue",
+                            async_evt.tag
+                        );
+                        return Err(async_evt);
                     }
+                    Err(RecvTimeoutError::Timeout) => { /*great*/ }
+                    Err(RecvTimeoutError::Disconnected) => match timeout {
+                        // ok, there are no physical actions in the program so it's useless to block on self.rx
+                        // we still need to wait though..
+                        Some(timeout) => std::thread::sleep(timeout),
+                        None => panic!("The clock dropped the waker of the scheduler, physical time cannot advance"),
+                    },
                 }
             }
         }
@@ -374,6 +1132,7 @@ impl<'x> SyncScheduler<'x> {
 
     /// Create a new reaction wave to process the given
     /// reactions at some point in time.
+    #[allow(clippy::too_many_arguments)]
     fn new_reaction_ctx<'a>(
         &self,
         tag: EventTag,
@@ -382,6 +1141,13 @@ impl<'x> SyncScheduler<'x> {
         debug_info: DebugInfoProvider<'a>,
         was_terminated_atomic: &'a Arc<AtomicBool>,
         was_terminated: bool,
+        federate: Option<&'a FederateClient>,
+        processing_tag: Option<&'a Arc<Mutex<EventTag>>>,
+        clock: &'a Arc<dyn Clock>,
+        tracer: Option<&'a Tracer>,
+        failure_policy: FailurePolicy,
+        modes: &'a ModeInfo,
+        replaying: bool,
     ) -> ReactionCtx<'a, 'x> {
         ReactionCtx::new(
             rx,
@@ -392,6 +1158,13 @@ impl<'x> SyncScheduler<'x> {
             debug_info,
             was_terminated_atomic,
             was_terminated,
+            federate,
+            processing_tag,
+            clock,
+            tracer,
+            failure_policy,
+            modes,
+            replaying,
         )
     }
 
@@ -402,57 +1175,282 @@ impl<'x> SyncScheduler<'x> {
 
     /// Actually process a tag. The provided reactions are the
     /// root reactions that startup the "wave".
-    fn process_tag(&mut self, is_shutdown: bool, tag: EventTag, mut reactions: ReactionPlan<'x>) {
+    fn process_tag(&mut self, is_shutdown: bool, tag: EventTag, triggers: &[TriggerId], reactions: ReactionPlan<'x>) {
         if cfg!(debug_assertions) {
             if let Some(latest) = self.latest_processed_tag {
                 debug_assert!(tag > latest, "Tag ordering mismatch")
             }
         }
+        self.cleanup_tag();
         self.latest_processed_tag = Some(tag);
+        self.num_tags += 1;
+        self.present.extend_from_slice(triggers);
+        // reactions of inactive modes are never executed nor counted
+        let mut reactions = self.modes.filter_plan(reactions);
+
+        if let Some(debugger) = &mut self.debugger {
+            let view = DebugView {
+                tag,
+                queue: &self.event_queue,
+                plan: &reactions,
+                set_ports: &[],
+                debug: debug_info!(self),
+            };
+            debugger.before_tag(triggers, &view);
+        }
+
+        for trigger in triggers {
+            if let Some(&i) = self.delayed_connections.by_downstream.get(trigger) {
+                self.delayed_connections.connections[i].deliver(tag);
+            }
+        }
 
         let mut next_level = reactions.as_ref().and_then(|todo| todo.first_batch());
         if next_level.is_none() {
+            self.forward_delayed_values(tag);
+            if !self.cleanup_deferred {
+                self.cleanup_tag();
+            }
             return;
         }
 
-        let mut ctx = self.new_reaction_ctx(tag, None, &self.rx, debug_info!(self), &self.was_terminated, is_shutdown);
+        let mut ctx = self.new_reaction_ctx(
+            tag,
+            None,
+            &self.rx,
+            debug_info!(self),
+            &self.was_terminated,
+            is_shutdown,
+            self.federate.as_ref(),
+            self.processing_tag.as_ref(),
+            &self.clock,
+            self.tracer.as_ref(),
+            self.failure_policy,
+            &self.modes,
+            self.replay.is_some(),
+        );
 
         while let Some((level_no, batch)) = next_level {
             let level_no = level_no.cloned();
+            self.num_reactions += batch.len() as u64;
             trace!("  - Level {}", level_no);
             ctx.cur_level = level_no.key;
+            if let Some(debugger) = &mut self.debugger {
+                let view = DebugView {
+                    tag,
+                    queue: &self.event_queue,
+                    plan: &reactions,
+                    set_ports: &ctx.insides.set_ports,
+                    debug: debug_info!(self),
+                };
+                debugger.before_level(level_no, &view);
+            }
 
             /// Minimum number of reactions (inclusive) required
             /// to parallelize reactions.
             /// TODO experiment with tweaking this
             const PARALLEL_THRESHOLD: usize = 3;
 
-            if cfg!(feature = "parallel-runtime") && batch.len() >= PARALLEL_THRESHOLD {
+            // reactions are executed one by one while debugging
+            if cfg!(feature = "parallel-runtime") && batch.len() >= PARALLEL_THRESHOLD && self.debugger.is_none() {
                 #[cfg(feature = "parallel-runtime")]
                 parallel_rt_impl::process_batch(&mut ctx, &mut self.reactors, batch);
             } else {
                 // the impl for non-parallel runtime
                 for reaction_id in batch {
+                    if let Some(debugger) = &mut self.debugger {
+                        let view = DebugView {
+                            tag,
+                            queue: &self.event_queue,
+                            plan: &reactions,
+                            set_ports: &ctx.insides.set_ports,
+                            debug: debug_info!(self),
+                        };
+                        debugger.before_reaction(*reaction_id, &view);
+                    }
                     let reactor = &mut self.reactors[reaction_id.0.container()];
                     ctx.execute(reactor, *reaction_id);
                 }
             }
 
             reactions = ExecutableReactions::merge_plans_after(reactions, ctx.insides.todo_now.take(), level_no.key.next());
+            reactions = self.modes.filter_plan(reactions);
             next_level = reactions.as_ref().and_then(|todo| todo.next_batch(level_no.as_ref()));
         }
 
-        for evt in ctx.insides.future_events.drain(..) {
-            push_event!(self, evt)
+        push_events!(self, ctx.insides.future_events.drain(..));
+
+        for ScheduleToken { trigger, tag } in ctx.insides.cancellations.drain(..) {
+            trace!("Cancelling event of {} at {}", self.id_registry.fmt_component(trigger), tag);
+            self.event_queue.cancel(tag, trigger, self.dataflow);
         }
 
-        // cleanup tag-specific resources, eg clear port values
-        let ctx = CleanupCtx { tag };
-        // TODO measure performance of cleaning up all reactors w/ virtual dispatch like this.
-        //   see also efforts in the C runtime to  avoid this
-        for reactor in &mut self.reactors {
-            reactor.cleanup_tag(&ctx)
+        if let Some(ReactionFailure { reaction, message }) = ctx.insides.failures.drain(..).next() {
+            if self.failure.is_none() {
+                let reaction = debug_info!(self).display_reaction(reaction).to_string();
+                self.failure = Some(RuntimeError::ReactionFailed { reaction, message });
+            }
+        }
+
+        let checkpoint_requested = ctx.insides.checkpoint_requested;
+        let mutations = std::mem::take(&mut ctx.insides.mutations);
+        self.present.append(&mut ctx.insides.set_ports);
+
+        for (mode, transition) in std::mem::take(&mut ctx.insides.mode_changes) {
+            for (trigger, eta) in self.modes.set_mode(mode, transition, tag) {
+                let downstream = self.dataflow.reactions_triggered_by(&trigger);
+                push_event!(self, Event::execute(eta, trigger, Cow::Borrowed(downstream)))
+            }
+        }
+
+        if !mutations.is_empty() {
+            self.apply_mutations(tag, mutations);
+        }
+
+        self.forward_delayed_values(tag);
+        if !self.cleanup_deferred {
+            self.cleanup_tag();
+        }
+
+        if checkpoint_requested && !is_shutdown {
+            if let Err(e) = self.save_checkpoint(tag) {
+                error!("Could not write checkpoint at {}: {}", tag, e);
+            }
+        }
+    }
+
+    /// Apply the mutations requested by reactions at the given
+    /// tag, see [ReactionCtx::mutate]. Each one is assembled on
+    /// copies of the dependency graph and debug info, so that one
+    /// that fails leaves the program unchanged.
+    ///
+    /// The dependency information is then recomputed, and so are
+    /// the reactions of pending events, as the levels of existing
+    /// reactions may change. The previous dependency information
+    /// is leaked, as the scheduler may hold references to it for
+    /// its whole lifetime. The startup reactions of the new
+    /// reactors are scheduled at the next microstep.
+    fn apply_mutations(&mut self, tag: EventTag, mutations: Vec<PendingMutation>) {
+        let first_new = self.reactors.next_idx();
+        let mut dataflow = None;
+        for PendingMutation { reaction, mutation } in mutations {
+            let parent = reaction.0.container();
+            let mut root = RootAssembler::for_mutation(self.graph.clone(), self.id_registry.clone(), self.reactors.len());
+            let result = mutation(&mut Mutator::new(&mut root, &mut self.tag_cleanups, parent))
+                .and_then(|_| root.check_mutation())
+                .and_then(|_| DataflowInfo::new(&root.graph));
+            match result {
+                Ok(new_dataflow) => {
+                    let (reactors, graph, id_registry, tag_cleanups) = root.finish_mutation(self.reactors.next_idx());
+                    self.modes.adopt(parent, reactors.iter().map(|r| r.id()));
+                    self.reactors.extend(reactors);
+                    self.graph = graph;
+                    self.id_registry = id_registry;
+                    self.tag_cleanups.extend(tag_cleanups);
+                    dataflow = Some(new_dataflow);
+                }
+                Err(e) => {
+                    let failure = e.lift(&root.debug_info);
+                    self.fail_mutation(tag, reaction, failure);
+                }
+            }
+        }
+
+        if let Some(dataflow) = dataflow {
+            let dataflow: &'x DataflowInfo = Box::leak(Box::new(dataflow));
+            self.dataflow = dataflow;
+            self.event_queue.replan(dataflow);
+
+            let mut startup = dataflow.reactions_triggered_by(&TriggerId::STARTUP).clone();
+            startup.retain(|r| r.0.container() >= first_new);
+            if !startup.is_empty() {
+                let evt = Event {
+                    tag: tag.next_microstep(),
+                    reactions: Some(Cow::Owned(startup)),
+                    triggers: Default::default(),
+                    terminate: false,
+                };
+                push_event!(self, evt);
+            }
+        }
+    }
+
+    /// Handle a mutation that could not be applied like a failure
+    /// of the reaction that requested it, see [FailurePolicy].
+    fn fail_mutation(&mut self, tag: EventTag, reaction: GlobalReactionId, failure: AssemblyFailure) {
+        let reaction = debug_info!(self).display_reaction(reaction).to_string();
+        let message = format!("Mutation failed: {}", failure);
+        match self.failure_policy {
+            FailurePolicy::Abort => panic!("Reaction {} failed: {}", reaction, message),
+            FailurePolicy::Log => error!("Reaction {} failed: {}", reaction, message),
+            FailurePolicy::Shutdown => {
+                error!("Reaction {} failed, shutting down: {}", reaction, message);
+                if self.failure.is_none() {
+                    self.failure = Some(RuntimeError::ReactionFailed { reaction, message });
+                }
+                push_event!(self, Event::terminate_at(tag.next_microstep()));
+            }
+        }
+    }
+
+    /// Clear the ports set and the actions present at the latest
+    /// processed tag. Only those are visited, so the cost does
+    /// not depend on the size of the program.
+    pub(super) fn cleanup_tag(&mut self) {
+        let tag = match self.latest_processed_tag {
+            Some(tag) => tag,
+            None => return,
+        };
+        for id in self.present.drain(..) {
+            if let Some(handle) = self.tag_cleanups.get_mut(&id) {
+                handle.cleanup(tag);
+            }
+        }
+    }
+
+    /// Keep the ports and actions present at a tag until the
+    /// next tag is processed, or [Self::cleanup_tag] is called,
+    /// so that their values may be observed after the tag.
+    pub(super) fn defer_cleanup(&mut self) {
+        self.cleanup_deferred = true;
+    }
+
+    /// Buffer the values of the upstream ports of delayed
+    /// connections, and schedule the tag where they're delivered.
+    /// Only the connections whose upstream port is present at
+    /// this tag are visited. Their downstream ports are cleared
+    /// with the other ports present at the tag.
+    /// Physical connections go through the channel of physical
+    /// events, as if a physical action had been scheduled.
+    fn forward_delayed_values(&mut self, tag: EventTag) {
+        if self.delayed_connections.is_empty() {
+            return;
+        }
+        let mut present = std::mem::take(&mut self.present);
+        present.sort_unstable();
+        present.dedup();
+
+        let mut tx = None;
+        for id in &present {
+            let indices = match self.delayed_connections.by_upstream.get(id) {
+                Some(indices) => indices,
+                None => continue,
+            };
+            for &i in indices {
+                let connection = &mut self.delayed_connections.connections[i];
+                if let Some(eta) = connection.forward(tag, self.clock.as_ref(), self.initial_time) {
+                    let downstream_id = connection.downstream_id();
+                    if connection.is_physical() {
+                        let tx = tx.get_or_insert_with(|| self.rx.new_sender());
+                        tx.send(PhysicalEvent::trigger(eta, downstream_id)).ok();
+                    } else {
+                        let downstream = self.dataflow.reactions_triggered_by(&downstream_id);
+                        push_event!(self, Event::execute(eta, downstream_id, Cow::Borrowed(downstream)))
+                    }
+                }
+            }
         }
+        self.present = present;
     }
 }
 
@@ -0,0 +1,477 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Recording of execution traces.
+//!
+//! Traces are written either in the binary format of the
+//! C runtime (see `trace.h` there), which can be converted
+//! with the `trace_to_csv` and `trace_to_chrome` utilities of
+//! LF, or directly in the [Chrome Trace Event format](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU),
+//! which can be opened in Perfetto or `chrome://tracing`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use index_vec::{Idx, IndexVec};
+
+use super::PhysicalEvent;
+use crate::assembly::TriggerId;
+use crate::*;
+
+/// Where and how to record an execution trace, see
+/// [SchedulerOptions::trace](crate::SchedulerOptions::trace).
+#[derive(Clone, Debug)]
+pub struct TraceOptions {
+    /// Path of the trace file, which is overwritten.
+    pub path: PathBuf,
+    /// Format of the trace file.
+    pub format: TraceFormat,
+}
+
+/// Format of an execution trace.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// Binary format of the C runtime of LF (`.lft` files).
+    LinguaFranca,
+    /// JSON format of the Chrome Trace Event format.
+    Chrome,
+}
+
+/// Something that happened during execution.
+pub(super) enum TracePoint {
+    ReactionStarts(GlobalReactionId),
+    ReactionEnds(GlobalReactionId),
+    /// A reaction scheduled an action.
+    ScheduleCalled {
+        action: TriggerId,
+        delay: Duration,
+    },
+    /// The scheduler starts waiting for the given tag (for
+    /// physical time to catch up, or for the RTI to grant it).
+    AdvancingTimeStarts,
+    AdvancingTimeEnds,
+    /// The scheduler received an event from an asynchronous
+    /// thread. If there is no trigger, it is a stop request.
+    PhysicalEventArrived(Option<TriggerId>),
+}
+
+// Constants of the C runtime, from trace.h.
+const REACTION_STARTS: i32 = 0;
+const REACTION_ENDS: i32 = 1;
+const SCHEDULE_CALLED: i32 = 2;
+const USER_EVENT: i32 = 3;
+const SCHEDULER_ADVANCING_TIME_STARTS: i32 = 7;
+const SCHEDULER_ADVANCING_TIME_ENDS: i32 = 8;
+
+const TRACE_REACTOR: i32 = 0;
+const TRACE_TRIGGER: i32 = 1;
+const TRACE_USER: i32 = 2;
+
+/// Number of records per batch, like in the C runtime.
+const BATCH_CAPACITY: i32 = 2048;
+
+/// Stands in for the pointer to the description of the user
+/// event that records the arrival of physical events.
+const PHYSICAL_EVENT_POINTER: u64 = u64::MAX;
+
+/// Records [TracePoint]s to a file. This may be used from
+/// several threads. The file is flushed when the tracer is
+/// dropped.
+pub(super) struct Tracer {
+    initial_time: Instant,
+    /// Unix time of [Self::initial_time] in nanoseconds.
+    start_time_ns: i64,
+    clock: Arc<dyn Clock>,
+    /// Paths of the reactors.
+    reactors: IndexVec<ReactorId, String>,
+    /// Names and containers of the triggers.
+    triggers: IndexVec<TriggerId, (String, Option<ReactorId>)>,
+    /// Names of the reactions that have a label. Other
+    /// reactions are named after their index.
+    reaction_labels: HashMap<GlobalReactionId, String>,
+    sink: Mutex<TraceSink>,
+}
+
+enum TraceSink {
+    /// Records are written in batches, each prefixed by their count.
+    LinguaFranca {
+        out: BufWriter<File>,
+        batch: Vec<u8>,
+        batch_len: i32,
+    },
+    Chrome {
+        out: BufWriter<File>,
+        is_empty: bool,
+    },
+}
+
+impl Tracer {
+    pub(super) fn new(
+        options: &TraceOptions,
+        id_registry: &DebugInfoRegistry,
+        initial_time: Instant,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<Self> {
+        let now = clock.now();
+        let unix_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let start_time = if initial_time <= now {
+            unix_now.saturating_sub(now - initial_time)
+        } else {
+            // federates agree on a start time in the future
+            unix_now + (initial_time - now)
+        };
+
+        let out = BufWriter::new(File::create(&options.path)?);
+        let sink = match options.format {
+            TraceFormat::LinguaFranca => TraceSink::LinguaFranca { out, batch: Vec::new(), batch_len: 0 },
+            TraceFormat::Chrome => TraceSink::Chrome { out, is_empty: true },
+        };
+        let tracer = Self {
+            initial_time,
+            start_time_ns: to_nanos(start_time),
+            clock,
+            reactors: id_registry
+                .reactor_ids()
+                .map(|id| id_registry.get_debug_info(id).to_string())
+                .collect(),
+            triggers: id_registry
+                .trigger_ids()
+                .map(|id| {
+                    (
+                        id_registry.fmt_component(id).to_string(),
+                        id_registry.get_trigger_container(id),
+                    )
+                })
+                .collect(),
+            reaction_labels: id_registry
+                .labeled_reactions()
+                .map(|id| (id, id_registry.fmt_reaction(id).to_string()))
+                .collect(),
+            sink: Mutex::new(sink),
+        };
+
+        match &mut *tracer.sink.lock().unwrap() {
+            TraceSink::LinguaFranca { out, .. } => tracer.write_lf_header(out)?,
+            TraceSink::Chrome { out, .. } => writeln!(out, "[")?,
+        }
+        info!("Recording execution trace to {}", options.path.display());
+        Ok(tracer)
+    }
+
+    /// Record the given trace point, which occurred while
+    /// processing the given tag.
+    pub(super) fn record(&self, point: TracePoint, tag: EventTag) {
+        let physical_time = self.clock.now().saturating_duration_since(self.initial_time);
+        let worker = worker_id();
+
+        let mut sink = self.sink.lock().unwrap();
+        let result = match &mut *sink {
+            TraceSink::LinguaFranca { out, batch, batch_len } => {
+                self.lf_record(&point, tag, physical_time, worker).encode(batch);
+                *batch_len += 1;
+                if *batch_len == BATCH_CAPACITY {
+                    flush_batch(out, batch, batch_len)
+                } else {
+                    Ok(())
+                }
+            }
+            TraceSink::Chrome { out, is_empty } => {
+                let separator = if *is_empty { "" } else { ",\n" };
+                *is_empty = false;
+                out.write_all(separator.as_bytes())
+                    .and_then(|_| self.write_chrome_event(out, &point, tag, physical_time, worker))
+            }
+        };
+        if let Err(e) = result {
+            error!("Could not write execution trace: {}", e)
+        }
+    }
+
+    /// Record the arrival of an event sent by an asynchronous
+    /// thread. Wake-up events are not recorded.
+    pub(super) fn record_physical_event(&self, evt: &PhysicalEvent) {
+        if !evt.is_wake_up() {
+            self.record(TracePoint::PhysicalEventArrived(evt.trigger_id), evt.tag)
+        }
+    }
+
+    /// Write the start time and the table of object descriptions.
+    fn write_lf_header(&self, out: &mut impl Write) -> io::Result<()> {
+        let num_objects = self.reactors.len() + self.triggers.len() + 1;
+        out.write_all(&self.start_time_ns.to_ne_bytes())?;
+        out.write_all(&(num_objects as i32).to_ne_bytes())?;
+
+        for (id, path) in self.reactors.iter_enumerated() {
+            write_lf_object(out, reactor_pointer(Some(id)), 0, TRACE_REACTOR, path)?;
+        }
+        for (id, (name, container)) in self.triggers.iter_enumerated() {
+            write_lf_object(
+                out,
+                reactor_pointer(*container),
+                trigger_pointer(Some(id)),
+                TRACE_TRIGGER,
+                name,
+            )?;
+        }
+        write_lf_object(out, PHYSICAL_EVENT_POINTER, 0, TRACE_USER, "physical event")
+    }
+
+    fn lf_record(&self, point: &TracePoint, tag: EventTag, physical_time: Duration, worker: i32) -> TraceRecord {
+        let mut record = TraceRecord {
+            event_type: 0,
+            pointer: 0,
+            src_id: worker,
+            dst_id: -1,
+            logical_time: self.start_time_ns + to_nanos(tag.offset_from_t0),
+            microstep: tag.microstep.raw(),
+            physical_time: self.start_time_ns + to_nanos(physical_time),
+            trigger: 0,
+            extra_delay: 0,
+        };
+        match *point {
+            TracePoint::ReactionStarts(id) | TracePoint::ReactionEnds(id) => {
+                record.event_type = match point {
+                    TracePoint::ReactionStarts(_) => REACTION_STARTS,
+                    _ => REACTION_ENDS,
+                };
+                record.pointer = reactor_pointer(Some(id.0.container()));
+                record.dst_id = id.0.local().index() as i32;
+            }
+            TracePoint::ScheduleCalled { action, delay } => {
+                record.event_type = SCHEDULE_CALLED;
+                record.pointer = reactor_pointer(self.triggers[action].1);
+                record.trigger = trigger_pointer(Some(action));
+                record.extra_delay = to_nanos(delay);
+            }
+            TracePoint::AdvancingTimeStarts => record.event_type = SCHEDULER_ADVANCING_TIME_STARTS,
+            TracePoint::AdvancingTimeEnds => record.event_type = SCHEDULER_ADVANCING_TIME_ENDS,
+            TracePoint::PhysicalEventArrived(trigger) => {
+                record.event_type = USER_EVENT;
+                record.pointer = PHYSICAL_EVENT_POINTER;
+                record.trigger = trigger_pointer(trigger);
+            }
+        }
+        record
+    }
+
+    fn write_chrome_event(
+        &self,
+        out: &mut impl Write,
+        point: &TracePoint,
+        tag: EventTag,
+        physical_time: Duration,
+        worker: i32,
+    ) -> io::Result<()> {
+        let (name, category, phase) = match *point {
+            TracePoint::ReactionStarts(id) => (self.reaction_name(id), "reaction", "B"),
+            TracePoint::ReactionEnds(id) => (self.reaction_name(id), "reaction", "E"),
+            TracePoint::ScheduleCalled { action, .. } => (format!("schedule {}", self.triggers[action].0), "action", "i"),
+            TracePoint::AdvancingTimeStarts => ("advancing time".to_string(), "scheduler", "B"),
+            TracePoint::AdvancingTimeEnds => ("advancing time".to_string(), "scheduler", "E"),
+            TracePoint::PhysicalEventArrived(Some(trigger)) => {
+                (format!("physical event {}", self.triggers[trigger].0), "action", "i")
+            }
+            TracePoint::PhysicalEventArrived(None) => ("stop request".to_string(), "action", "i"),
+        };
+
+        write!(out, "{{\"name\":")?;
+        write_json_str(out, &name)?;
+        write!(
+            out,
+            ",\"cat\":\"{}\",\"ph\":\"{}\",\"ts\":{:.3},\"pid\":0,\"tid\":{}",
+            category,
+            phase,
+            physical_time.as_nanos() as f64 / 1000.0,
+            worker
+        )?;
+        if phase == "i" {
+            write!(out, ",\"s\":\"t\"")?;
+        }
+        write!(
+            out,
+            ",\"args\":{{\"logical_time_ns\":{},\"microstep\":{}}}}}",
+            tag.offset_from_t0.as_nanos(),
+            tag.microstep.raw()
+        )
+    }
+
+    fn reaction_name(&self, id: GlobalReactionId) -> String {
+        match self.reaction_labels.get(&id) {
+            Some(label) => label.clone(),
+            None => format!("{}{}", self.reactors[id.0.container()], id.0.local().index()),
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let result = match self.sink.get_mut().unwrap() {
+            TraceSink::LinguaFranca { out, batch, batch_len } => flush_batch(out, batch, batch_len).and_then(|_| out.flush()),
+            TraceSink::Chrome { out, .. } => writeln!(out, "\n]").and_then(|_| out.flush()),
+        };
+        if let Err(e) = result {
+            error!("Could not write execution trace: {}", e)
+        }
+    }
+}
+
+/// A record in the binary format of the C runtime. This
+/// mirrors the layout of `trace_record_t` on 64-bit platforms,
+/// which the C runtime writes with `fwrite`.
+#[derive(Debug, Eq, PartialEq)]
+struct TraceRecord {
+    event_type: i32,
+    /// Pointer to the reactor, or to the description of a user event.
+    pointer: u64,
+    /// Worker that recorded the event.
+    src_id: i32,
+    /// Index of the reaction within its reactor, if any.
+    dst_id: i32,
+    logical_time: i64,
+    microstep: u32,
+    physical_time: i64,
+    /// Pointer to the trigger, if any.
+    trigger: u64,
+    extra_delay: i64,
+}
+
+impl TraceRecord {
+    const SIZE: usize = 64;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.reserve(Self::SIZE);
+        buf.extend_from_slice(&self.event_type.to_ne_bytes());
+        buf.extend_from_slice(&[0; 4]); // padding
+        buf.extend_from_slice(&self.pointer.to_ne_bytes());
+        buf.extend_from_slice(&self.src_id.to_ne_bytes());
+        buf.extend_from_slice(&self.dst_id.to_ne_bytes());
+        buf.extend_from_slice(&self.logical_time.to_ne_bytes());
+        buf.extend_from_slice(&self.microstep.to_ne_bytes());
+        buf.extend_from_slice(&[0; 4]); // padding
+        buf.extend_from_slice(&self.physical_time.to_ne_bytes());
+        buf.extend_from_slice(&self.trigger.to_ne_bytes());
+        buf.extend_from_slice(&self.extra_delay.to_ne_bytes());
+    }
+}
+
+fn flush_batch(out: &mut impl Write, batch: &mut Vec<u8>, batch_len: &mut i32) -> io::Result<()> {
+    if *batch_len > 0 {
+        out.write_all(&batch_len.to_ne_bytes())?;
+        out.write_all(batch)?;
+        batch.clear();
+        *batch_len = 0;
+    }
+    Ok(())
+}
+
+fn write_lf_object(out: &mut impl Write, pointer: u64, trigger: u64, object_type: i32, description: &str) -> io::Result<()> {
+    out.write_all(&pointer.to_ne_bytes())?;
+    out.write_all(&trigger.to_ne_bytes())?;
+    out.write_all(&object_type.to_ne_bytes())?;
+    out.write_all(description.as_bytes())?;
+    out.write_all(&[0])
+}
+
+/// Reactors and triggers don't have an address like in the
+/// C runtime, so we make up pointers from their ids. Zero
+/// is the null pointer.
+fn reactor_pointer(id: Option<ReactorId>) -> u64 {
+    id.map_or(0, |id| id.index() as u64 + 1)
+}
+
+fn trigger_pointer(id: Option<TriggerId>) -> u64 {
+    id.map_or(0, |id| id.index() as u64 + 1)
+}
+
+fn to_nanos(d: Duration) -> i64 {
+    i64::try_from(d.as_nanos()).unwrap_or(i64::MAX)
+}
+
+/// Index of the worker thread executing the current code.
+fn worker_id() -> i32 {
+    #[cfg(feature = "parallel-runtime")]
+    if let Some(ix) = rayon::current_thread_index() {
+        return ix as i32;
+    }
+    0
+}
+
+fn write_json_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_layout() {
+        let record = TraceRecord {
+            event_type: REACTION_ENDS,
+            pointer: 2,
+            src_id: 3,
+            dst_id: 4,
+            logical_time: 5,
+            microstep: 6,
+            physical_time: 7,
+            trigger: 8,
+            extra_delay: 9,
+        };
+        let mut buf = Vec::new();
+        record.encode(&mut buf);
+        assert_eq!(buf.len(), TraceRecord::SIZE);
+
+        let i64_at = |offset: usize| i64::from_ne_bytes(buf[offset..offset + 8].try_into().unwrap());
+        let i32_at = |offset: usize| i32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap());
+        assert_eq!(i32_at(0), REACTION_ENDS);
+        assert_eq!(i64_at(8), 2);
+        assert_eq!(i32_at(16), 3);
+        assert_eq!(i32_at(20), 4);
+        assert_eq!(i64_at(24), 5);
+        assert_eq!(i32_at(32), 6);
+        assert_eq!(i64_at(40), 7);
+        assert_eq!(i64_at(48), 8);
+        assert_eq!(i64_at(56), 9);
+    }
+
+    #[test]
+    fn test_json_escape() {
+        let mut buf = Vec::new();
+        write_json_str(&mut buf, "/main/a\"b\\c\n").unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), r#""/main/a\"b\\c\u000a""#);
+    }
+}
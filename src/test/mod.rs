@@ -23,5 +23,14 @@
  */
 
 pub mod stuff_that_must_compile;
+pub mod test_builder;
+pub mod test_checkpoints;
+pub mod test_federated;
+pub mod test_harness;
+pub mod test_mutations;
 pub mod test_ports;
+pub mod test_reactor_macro;
+pub mod test_replay;
+pub mod test_scheduler;
+pub mod test_watchdogs;
 pub mod testutil;
@@ -0,0 +1,212 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of [ProgramBuilder].
+
+use std::sync::{Arc, Mutex};
+
+use crate::assembly::AssemblyFailure;
+use crate::prelude::*;
+use crate::{ProgramBuilder, RuntimeError, SchedulerOptions};
+
+fn options() -> SchedulerOptions {
+    SchedulerOptions { fast: true, ..Default::default() }
+}
+
+#[test]
+fn test_timer_and_connection() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = received.clone();
+
+    let mut program = ProgramBuilder::new();
+    program
+        .reactor("source")
+        .state(0u32)
+        .timer("tick", Duration::ZERO, Duration::from_millis(10))
+        .output::<u32>("out")
+        .reaction(&["tick"], &["out"], |ctx, this| {
+            let count = this.state_mut::<u32>();
+            *count += 1;
+            let count = *count;
+            ctx.set(this.port_mut::<u32>("out"), count);
+            if count == 3 {
+                ctx.request_stop(Asap);
+            }
+        });
+    program
+        .reactor("sink")
+        .input::<u32>("in")
+        .reaction(&["in"], &[], move |ctx, this| {
+            let value = ctx.get(this.port::<u32>("in")).unwrap();
+            sink.lock().unwrap().push((ctx.get_tag(), value));
+        });
+    program.connect("source.out", "sink.in");
+    program.run(options()).unwrap();
+
+    let expected = vec![
+        (EventTag::offset(Duration::ZERO, 0), 1),
+        (EventTag::offset(Duration::from_millis(10), 0), 2),
+        (EventTag::offset(Duration::from_millis(20), 0), 3),
+    ];
+    assert_eq!(*received.lock().unwrap(), expected);
+}
+
+#[test]
+fn test_actions_and_shutdown() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let on_action = received.clone();
+    let on_shutdown = received.clone();
+
+    let mut program = ProgramBuilder::new();
+    program
+        .reactor("main")
+        .logical_action::<&'static str>("act", None)
+        .reaction(&["startup"], &["act"], |ctx, this| {
+            ctx.schedule_with_v(
+                this.action_mut::<&'static str>("act"),
+                Some("action"),
+                After(Duration::from_millis(5)),
+            );
+        })
+        .reaction(&["act"], &[], move |ctx, this| {
+            on_action
+                .lock()
+                .unwrap()
+                .push(ctx.get(this.action::<&'static str>("act")).unwrap());
+        })
+        .reaction(&["shutdown"], &[], move |_, _| {
+            on_shutdown.lock().unwrap().push("shutdown");
+        });
+    program.run(options()).unwrap();
+
+    assert_eq!(*received.lock().unwrap(), vec!["action", "shutdown"]);
+}
+
+#[test]
+fn test_ports_are_cleared_after_tag() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = received.clone();
+
+    let mut program = ProgramBuilder::new();
+    program
+        .reactor("source")
+        .output::<u32>("out")
+        .reaction(&["startup"], &["out"], |ctx, this| {
+            ctx.set(this.port_mut::<u32>("out"), 1);
+        });
+    program
+        .reactor("sink")
+        .input::<u32>("in")
+        .timer("tick", Duration::ZERO, Duration::from_millis(10))
+        .reaction(&["tick"], &[], move |ctx, this| {
+            sink.lock().unwrap().push(ctx.get(this.port::<u32>("in")));
+            if ctx.get_tag() > EventTag::ORIGIN {
+                ctx.request_stop(Asap);
+            }
+        });
+    program.connect("source.out", "sink.in");
+    program.run(options()).unwrap();
+
+    assert_eq!(*received.lock().unwrap(), vec![Some(1), None]);
+}
+
+#[test]
+fn test_logical_actions_are_cleared_after_tag() {
+    let payload = Arc::new(());
+    let counts = Arc::new(Mutex::new(Vec::new()));
+    let (scheduled, sink) = (payload.clone(), counts.clone());
+
+    let mut program = ProgramBuilder::new();
+    program
+        .reactor("r")
+        .logical_action::<Arc<()>>("act", None)
+        .timer("tick", Duration::from_millis(10), Duration::ZERO)
+        .reaction(&["startup"], &["act"], move |ctx, this| {
+            ctx.schedule_with_v(this.action_mut::<Arc<()>>("act"), Some(scheduled.clone()), Asap);
+        })
+        .reaction(&["act"], &[], |_, _| {})
+        .reaction(&["tick"], &[], move |_, _| {
+            sink.lock().unwrap().push(Arc::strong_count(&payload));
+        });
+    program.run(options()).unwrap();
+
+    // only the closure of the tick reaction still holds the payload
+    assert_eq!(*counts.lock().unwrap(), vec![2]);
+}
+
+#[test]
+fn test_bind_twice() {
+    let mut program = ProgramBuilder::new();
+    program.reactor("a").output::<u32>("out");
+    program.reactor("b").output::<u32>("out");
+    program.reactor("c").input::<u32>("in");
+    program.connect("a.out", "c.in");
+    program.connect("b.out", "c.in");
+
+    let result = program.run(options());
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Assembly(AssemblyFailure::CannotBind { .. }))
+    ));
+}
+
+#[test]
+fn test_connect_different_types() {
+    let mut program = ProgramBuilder::new();
+    program.reactor("a").output::<u32>("out");
+    program.reactor("b").input::<i32>("in");
+    program.connect("a.out", "b.in");
+
+    let result = program.run(options());
+    assert_eq!(
+        result.err().map(|e| e.to_string()),
+        Some("Error while assembling the program: Cannot bind a.out (u32) to b.in (i32), ports have different types".to_owned())
+    );
+}
+
+#[test]
+fn test_connect_unknown_ports() {
+    for path in ["a.missing", "missing.in", "a.tick", "a"] {
+        let mut program = ProgramBuilder::new();
+        program
+            .reactor("a")
+            .input::<u32>("in")
+            .timer("tick", Duration::ZERO, Duration::ZERO);
+        program.connect(path, "a.in");
+
+        let result = program.run(options());
+        assert!(
+            matches!(&result, Err(RuntimeError::Assembly(AssemblyFailure::UnknownPort { path: p })) if p == path),
+            "{}",
+            path
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "Reactor a has no component missing")]
+fn test_unknown_trigger() {
+    let mut program = ProgramBuilder::new();
+    program.reactor("a").reaction(&["missing"], &[], |_, _| {});
+}
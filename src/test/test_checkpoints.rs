@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of checkpoints: a program is run until it writes a
+//! checkpoint, then resumed from it.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::assembly::{AssemblyCtx, AssemblyResult, FinishedReactor, ReactorInitializer, TriggerId, TriggerLike};
+use crate::prelude::*;
+use crate::{
+    CheckpointReader, CheckpointWriter, Checkpointable, LocalReactionId, ReactorBehavior, ReactorId, SchedulerOptions,
+    SpacingPolicy, SyncScheduler, Watchdog,
+};
+
+type Log = Arc<Mutex<Vec<(EventTag, u64)>>>;
+
+/// Adds 1, 2, 3... to a sum, every 10 ms, and requests a
+/// checkpoint when adding 2. The next number to add is the
+/// value of the pending action.
+struct Accumulator {
+    id: ReactorId,
+    sum: u64,
+    next: LogicalAction<u64>,
+    /// The sum after each addition.
+    log: Log,
+    /// Makes the program impossible to checkpoint, if present.
+    watchdog: Option<Watchdog>,
+}
+
+impl ReactorInitializer for Accumulator {
+    type Wrapped = Accumulator;
+    /// The log, and whether the reactor has a watchdog.
+    type Params = (Log, bool);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble((log, with_watchdog): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Accumulator {
+                        id,
+                        sum: 0,
+                        next: cc.new_logical_action("next", None, None, SpacingPolicy::Defer),
+                        log,
+                        watchdog: with_watchdog.then(|| cc.new_watchdog("watchdog", Duration::from_secs(1))),
+                    })
+                },
+                2,
+                [None, None],
+                |dd, this, [startup, add]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.next.get_id(), add)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Accumulator {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {
+                ctx.schedule_with_v(&mut self.next, Some(1), After(Duration::from_millis(10)));
+                if let Some(watchdog) = &self.watchdog {
+                    ctx.start_watchdog(watchdog);
+                }
+            }
+            1 => {
+                let value = ctx.get(&self.next).unwrap();
+                self.sum += value;
+                self.log.lock().unwrap().push((ctx.get_tag(), self.sum));
+                if value == 2 {
+                    ctx.request_checkpoint();
+                }
+                ctx.schedule_with_v(&mut self.next, Some(value + 1), After(Duration::from_millis(10)));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn as_checkpointable(&mut self) -> Option<&mut dyn Checkpointable> {
+        Some(self)
+    }
+}
+
+impl Checkpointable for Accumulator {
+    fn save_state(&self, out: &mut CheckpointWriter) -> io::Result<()> {
+        out.write_u64(self.sum)?;
+        out.write_action(&self.next, |v| v.to_le_bytes().to_vec())
+    }
+
+    fn restore_state(&mut self, input: &mut CheckpointReader) -> io::Result<()> {
+        self.sum = input.read_u64()?;
+        input.read_action(&mut self.next, |bytes| {
+            let bytes = bytes.try_into().map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            Ok(u64::from_le_bytes(bytes))
+        })
+    }
+}
+
+/// A checkpoint file that is removed at the end of the test.
+struct TempCheckpoint(PathBuf);
+
+impl TempCheckpoint {
+    fn new(name: &str) -> Self {
+        let file = format!("reactor_rt_{}_{}.lfcp", name, std::process::id());
+        Self(std::env::temp_dir().join(file))
+    }
+
+    fn options(&self) -> SchedulerOptions {
+        SchedulerOptions {
+            fast: true,
+            timeout: Some(Duration::from_millis(45)),
+            checkpoint: Some(self.0.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+impl Drop for TempCheckpoint {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+#[test]
+fn test_resume_from_checkpoint() {
+    let checkpoint = TempCheckpoint::new("resume");
+    let log = Log::default();
+    SyncScheduler::try_run_main::<Accumulator>(checkpoint.options(), (log.clone(), false)).unwrap();
+    let expected = vec![
+        (tag!(T0 + 10 ms), 1),
+        (tag!(T0 + 20 ms), 3),
+        (tag!(T0 + 30 ms), 6),
+        (tag!(T0 + 40 ms), 10),
+    ];
+    assert_eq!(*log.lock().unwrap(), expected);
+
+    // the sum and the pending value are restored as of 20 ms
+    let resumed = Log::default();
+    let summary =
+        SyncScheduler::try_resume_main::<Accumulator>(checkpoint.options(), (resumed.clone(), false), &checkpoint.0).unwrap();
+    assert_eq!(*resumed.lock().unwrap(), expected[2..]);
+    assert_eq!(summary.final_tag, tag!(T0 + 45 ms));
+}
+
+#[test]
+fn test_watchdogs_are_not_checkpointed() {
+    let checkpoint = TempCheckpoint::new("watchdog");
+    let log = Log::default();
+    SyncScheduler::try_run_main::<Accumulator>(checkpoint.options(), (log.clone(), true)).unwrap();
+    assert_eq!(log.lock().unwrap().len(), 4);
+    assert!(!checkpoint.0.exists());
+}
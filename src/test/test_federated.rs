@@ -0,0 +1,251 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of federated programs, see [crate::federated].
+
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::assembly::{
+    AssemblyCtx, AssemblyFailure, AssemblyResult, FinishedReactor, ReactorInitializer, TriggerId, TriggerLike,
+};
+use crate::federated::protocol::Message;
+use crate::federated::{FederateId, FederateOptions, NetworkChannel, Rti};
+use crate::prelude::*;
+use crate::{LocalReactionId, ReactorBehavior, ReactorId, RuntimeError, SchedulerOptions, SpacingPolicy, SyncScheduler};
+
+/// The channel over which [Source] sends its messages.
+const CHANNEL: NetworkChannel = NetworkChannel { federate: FederateId(1), port: 0 };
+
+/// Logs the tags at which a message was sent, and whether
+/// sending it succeeded.
+type SendLog = Arc<Mutex<Vec<(EventTag, Result<(), io::ErrorKind>)>>>;
+
+/// Triggers an action a number of times, 10 ms apart.
+/// Each time, sends the number of remaining ticks over
+/// [CHANNEL].
+struct Source {
+    id: ReactorId,
+    remaining: u32,
+    tick: LogicalAction<()>,
+    log: SendLog,
+}
+
+impl ReactorInitializer for Source {
+    type Wrapped = Source;
+    /// The number of ticks, and the log.
+    type Params = (u32, SendLog);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble((remaining, log): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Source {
+                        id,
+                        remaining,
+                        tick: cc.new_logical_action("tick", None, None, SpacingPolicy::Defer),
+                        log,
+                    })
+                },
+                2,
+                [None, None],
+                |dd, this, [startup, tick]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.tick.get_id(), tick)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Source {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {}
+            1 => {
+                self.remaining -= 1;
+                let sent = ctx.send_network_message(CHANNEL, vec![self.remaining as u8]);
+                self.log.lock().unwrap().push((ctx.get_tag(), sent.map_err(|e| e.kind())));
+            }
+            _ => unreachable!(),
+        }
+        if self.remaining > 0 {
+            ctx.schedule(&mut self.tick, After(Duration::from_millis(10)));
+        }
+    }
+}
+
+/// Logs the messages received from other federates, with
+/// the tag at which they were received.
+type ReceiveLog = Arc<Mutex<Vec<(EventTag, Vec<u8>)>>>;
+
+/// Receives messages on network inputs with the given
+/// port numbers.
+struct Sink {
+    id: ReactorId,
+    inputs: Vec<PhysicalActionRef<Vec<u8>>>,
+    log: ReceiveLog,
+}
+
+impl ReactorInitializer for Sink {
+    type Wrapped = Sink;
+    /// The port numbers of the inputs, and the log.
+    type Params = (Vec<u32>, ReceiveLog);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(1);
+
+    fn assemble((ports, log): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    let inputs = ports
+                        .into_iter()
+                        .map(|port| cc.new_network_input("input", port))
+                        .collect::<AssemblyResult<_>>()?;
+                    Ok(Sink { id, inputs, log })
+                },
+                1,
+                [None],
+                |dd, this, [receive]| {
+                    for input in &this.inputs {
+                        dd.declare_triggers(input.get_id(), receive)?;
+                    }
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Sink {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, _: LocalReactionId) {
+        for input in &self.inputs {
+            if let Some(payload) = ctx.use_ref_opt(input, Vec::clone) {
+                self.log.lock().unwrap().push((ctx.get_tag(), payload));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_duplicate_network_input_is_an_error() {
+    let result = SyncScheduler::try_run_main::<Sink>(SchedulerOptions::default(), (vec![0, 1, 0], ReceiveLog::default()));
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Assembly(AssemblyFailure::DuplicateNetworkInput { port: 0 }))
+    ));
+}
+
+#[test]
+fn test_lost_connection_to_the_rti_is_an_error() {
+    // an RTI that closes the connection after the start time
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let rti_address = listener.local_addr().unwrap();
+    let rti = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_matches!(Message::read_from(&mut stream).unwrap(), Message::Register { .. });
+        let start_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Message::StartTime(start_time.as_nanos() as u64)
+            .write_to(&mut stream)
+            .unwrap();
+    });
+
+    let log = SendLog::default();
+    let options = SchedulerOptions {
+        federate: Some(FederateOptions { id: FederateId(0), rti_address, upstream: vec![] }),
+        ..Default::default()
+    };
+    let result = SyncScheduler::try_run_main::<Source>(options, (3, log.clone()));
+    rti.join().unwrap();
+
+    assert_matches!(result, Err(RuntimeError::Federation(_)));
+    // the federate was never granted a tag, so it shut down before the first tick
+    assert_eq!(*log.lock().unwrap(), vec![]);
+}
+
+#[test]
+fn test_network_message_outside_of_a_federation_is_an_error() {
+    let log = SendLog::default();
+    let options = SchedulerOptions { fast: true, ..Default::default() };
+    SyncScheduler::try_run_main::<Source>(options, (1, log.clone())).unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![(tag!(T0 + 10 ms), Err(io::ErrorKind::NotConnected))]
+    );
+}
+
+#[test]
+fn test_two_federates_over_localhost() {
+    let rti = Rti::bind("127.0.0.1:0", 2).unwrap();
+    let rti_address = rti.local_addr().unwrap();
+    let rti = std::thread::spawn(move || rti.run());
+
+    let federate = |id: u16, upstream: Vec<FederateId>| SchedulerOptions {
+        federate: Some(FederateOptions { id: FederateId(id), rti_address, upstream }),
+        ..Default::default()
+    };
+    let sent = SendLog::default();
+    let source = {
+        let (options, sent) = (federate(0, vec![]), sent.clone());
+        std::thread::spawn(move || SyncScheduler::try_run_main::<Source>(options, (3, sent)))
+    };
+    let received = ReceiveLog::default();
+    let sink = {
+        let (options, received) = (federate(1, vec![FederateId(0)]), received.clone());
+        std::thread::spawn(move || SyncScheduler::try_run_main::<Sink>(options, (vec![CHANNEL.port], received)))
+    };
+
+    source.join().unwrap().unwrap();
+    sink.join().unwrap().unwrap();
+    rti.join().unwrap().unwrap();
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            (tag!(T0 + 10 ms), Ok(())),
+            (tag!(T0 + 20 ms), Ok(())),
+            (tag!(T0 + 30 ms), Ok(()))
+        ]
+    );
+    // messages are received at the tag at which they were sent
+    assert_eq!(
+        *received.lock().unwrap(),
+        vec![
+            (tag!(T0 + 10 ms), vec![2]),
+            (tag!(T0 + 20 ms), vec![1]),
+            (tag!(T0 + 30 ms), vec![0])
+        ]
+    );
+}
@@ -0,0 +1,265 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of [ReactorTestHarness], with a reactor written by hand.
+
+use crate::assembly::{AssemblyCtx, AssemblyResult, FinishedReactor, PortKind, ReactorInitializer, TriggerId, TriggerLike};
+use crate::prelude::*;
+use crate::{LocalReactionId, ReactorBehavior, ReactorId, ReactorTestHarness, SpacingPolicy};
+
+/// Doubles its input, and outputs the doubled
+/// value again plus one, `delay` later.
+struct Doubler {
+    id: ReactorId,
+    started: bool,
+    input: Port<u32>,
+    output: Port<u32>,
+    later: LogicalAction<u32>,
+    delay: Duration,
+    /// Whether the input was present when `later` triggered.
+    later_saw_input: bool,
+}
+
+impl ReactorInitializer for Doubler {
+    type Wrapped = Doubler;
+    type Params = Duration;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(3);
+
+    fn assemble(delay: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Doubler {
+                        id,
+                        started: false,
+                        input: cc.new_port("input", PortKind::Input),
+                        output: cc.new_port("output", PortKind::Output),
+                        later: cc.new_logical_action("later", None, None, SpacingPolicy::Defer),
+                        delay,
+                        later_saw_input: false,
+                    })
+                },
+                3,
+                [None, None, None],
+                |dd, this, [startup, double, plus_one]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.input.get_id(), double)?;
+                    dd.effects_port(double, &this.output)?;
+                    dd.declare_triggers(this.later.get_id(), plus_one)?;
+                    dd.effects_port(plus_one, &this.output)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Doubler {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => self.started = true,
+            1 => {
+                let doubled = ctx.get(&self.input).unwrap() * 2;
+                ctx.set(&mut self.output, doubled);
+                ctx.schedule_with_v(&mut self.later, Some(doubled), After(self.delay));
+            }
+            2 => {
+                self.later_saw_input |= ctx.is_present(&self.input);
+                let value = ctx.get(&self.later).unwrap();
+                ctx.set(&mut self.output, value + 1);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Sends a counter around a cycle, which goes through a
+/// delayed connection: `output` is bound to `relay`, which
+/// is connected to `input` with a delay.
+struct Counter {
+    id: ReactorId,
+    input: Port<u32>,
+    relay: Port<u32>,
+    output: Port<u32>,
+    received: Vec<u32>,
+}
+
+impl ReactorInitializer for Counter {
+    type Wrapped = Counter;
+    type Params = ();
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble(_: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Counter {
+                        id,
+                        input: cc.new_port("input", PortKind::Input),
+                        relay: cc.new_port("relay", PortKind::Output),
+                        output: cc.new_port("output", PortKind::Output),
+                        received: Vec::new(),
+                    })
+                },
+                2,
+                [None, None],
+                |dd, this, [startup, count]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.effects_port(startup, &this.output)?;
+                    dd.declare_triggers(this.input.get_id(), count)?;
+                    dd.effects_port(count, &this.output)?;
+                    dd.bind_ports(&mut this.output, &mut this.relay)?;
+                    dd.bind_ports_delayed(&mut this.relay, &mut this.input, Duration::from_millis(2))?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Counter {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => ctx.set(&mut self.output, 1),
+            1 => {
+                let value = ctx.get(&self.input).unwrap();
+                self.received.push(value);
+                if value < 3 {
+                    ctx.set(&mut self.output, value + 1);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_startup_and_inputs() {
+    let mut harness = ReactorTestHarness::<Doubler>::new(Duration::from_millis(10)).unwrap();
+    assert_eq!(harness.next_tag(), Some(EventTag::ORIGIN));
+    assert!(!harness.reactor().started);
+
+    harness.set_input(|r| &mut r.input, 21);
+    assert!(harness.advance_to(EventTag::ORIGIN));
+    assert!(harness.reactor().started);
+    assert_eq!(harness.current_tag(), Some(EventTag::ORIGIN));
+    assert_eq!(harness.get(|r| &r.output), Some(42));
+    assert!(harness.is_scheduled(|r| &r.later, tag!(T0 + 10 ms)));
+    assert!(!harness.is_scheduled(|r| &r.later, tag!(T0 + 5 ms)));
+
+    // nothing is pending at 5 ms, outputs are absent
+    assert!(harness.advance_to(tag!(T0 + 5 ms)));
+    assert!(!harness.is_present(|r| &r.output));
+
+    assert_eq!(harness.step(), Some(tag!(T0 + 10 ms)));
+    assert!(harness.is_present(|r| &r.later));
+    assert_eq!(harness.get(|r| &r.output), Some(43));
+    assert_eq!(harness.next_tag(), None);
+}
+
+#[test]
+fn test_schedule_and_cancel() {
+    let mut harness = ReactorTestHarness::<Doubler>::new(Duration::from_millis(10)).unwrap();
+    harness.advance_to(EventTag::ORIGIN);
+
+    let token = harness.schedule(|r| &mut r.later, Some(1), After(Duration::from_millis(2)));
+    assert!(harness.is_scheduled(|r| &r.later, tag!(T0 + 2 ms)));
+    harness.schedule(|r| &mut r.later, Some(5), After(Duration::from_millis(4)));
+
+    assert!(harness.cancel(|r| &mut r.later, token.unwrap()));
+    assert!(!harness.is_scheduled(|r| &r.later, tag!(T0 + 2 ms)));
+
+    // the event at 4 ms is processed on the way
+    harness.set_input(|r| &mut r.input, 1);
+    assert!(harness.advance_to(tag!(T0 + 6 ms)));
+    assert_eq!(harness.get(|r| &r.output), Some(2));
+    assert!(harness.is_scheduled(|r| &r.later, tag!(T0 + 16 ms)));
+
+    harness.shutdown_at(tag!(T0 + 10 ms));
+    assert!(!harness.advance_to(tag!(T0 + 20 ms)));
+}
+
+#[test]
+fn test_inputs_are_set_at_their_tag() {
+    let mut harness = ReactorTestHarness::<Doubler>::new(Duration::from_millis(10)).unwrap();
+    harness.advance_to(EventTag::ORIGIN);
+    harness.schedule(|r| &mut r.later, Some(1), After(Duration::from_millis(4)));
+
+    harness.set_input(|r| &mut r.input, 3);
+    assert!(!harness.is_present(|r| &r.input));
+
+    // the event at 4 ms is processed first, without the input
+    assert!(harness.advance_to(tag!(T0 + 6 ms)));
+    assert!(!harness.reactor().later_saw_input);
+    assert_eq!(harness.get(|r| &r.input), Some(3));
+    assert_eq!(harness.get(|r| &r.output), Some(6));
+}
+
+#[test]
+fn test_values_are_cleared_without_cleanup_tag() {
+    // the doubler does not implement cleanup_tag
+    let mut harness = ReactorTestHarness::<Doubler>::new(Duration::from_millis(10)).unwrap();
+    harness.set_input(|r| &mut r.input, 1);
+    assert!(harness.advance_to(EventTag::ORIGIN));
+    assert_eq!(harness.get(|r| &r.output), Some(2));
+
+    // the input set at the origin is not seen at the next tag
+    assert_eq!(harness.step(), Some(tag!(T0 + 10 ms)));
+    assert!(!harness.reactor().later_saw_input);
+    assert_eq!(harness.get(|r| &r.later), Some(2));
+    assert_eq!(harness.get(|r| &r.output), Some(3));
+
+    // nor are the action and output of that tag
+    assert!(harness.advance_to(tag!(T0 + 15 ms)));
+    assert!(!harness.is_present(|r| &r.later));
+    assert!(!harness.is_present(|r| &r.output));
+}
+
+#[test]
+fn test_values_go_around_a_delayed_cycle() {
+    // the cycle is legal, so the dataflow graph can be built
+    let mut harness = ReactorTestHarness::<Counter>::new(()).unwrap();
+    assert!(harness.advance_to(EventTag::ORIGIN));
+    assert_eq!(harness.get(|r| &r.relay), Some(1));
+    assert!(harness.is_scheduled(|r| &r.input, tag!(T0 + 2 ms)));
+
+    assert_eq!(harness.step(), Some(tag!(T0 + 2 ms)));
+    assert_eq!(harness.get(|r| &r.input), Some(1));
+    // the delivered value is cleared with the tag
+    assert!(harness.advance_to(tag!(T0 + 3 ms)));
+    assert!(!harness.is_present(|r| &r.input));
+
+    assert_eq!(harness.step(), Some(tag!(T0 + 4 ms)));
+    assert_eq!(harness.step(), Some(tag!(T0 + 6 ms)));
+    assert_eq!(harness.reactor().received, vec![1, 2, 3]);
+    assert_eq!(harness.next_tag(), None);
+}
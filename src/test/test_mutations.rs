@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of runtime mutations, see [ReactionCtx::mutate].
+
+use std::sync::{Arc, Mutex};
+
+use crate::assembly::{AssemblyCtx, AssemblyResult, FinishedReactor, PortKind, ReactorInitializer, TriggerId, TriggerLike};
+use crate::prelude::*;
+use crate::{
+    FailurePolicy, LocalReactionId, ReactorBehavior, ReactorId, RuntimeError, SchedulerOptions, SpacingPolicy, SyncScheduler,
+};
+
+/// Logs tags with a value.
+type Log = Arc<Mutex<Vec<(EventTag, u32)>>>;
+
+/// Added at runtime by a [Spawner]. Logs its startup with
+/// the value 0, and replies to each request with twice
+/// its value.
+struct Worker {
+    id: ReactorId,
+    request: Port<u32>,
+    reply: Port<u32>,
+    log: Log,
+}
+
+impl ReactorInitializer for Worker {
+    type Wrapped = Worker;
+    type Params = Log;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble(log: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Worker {
+                        id,
+                        request: cc.new_port("request", PortKind::Input),
+                        reply: cc.new_port("reply", PortKind::Output),
+                        log,
+                    })
+                },
+                2,
+                [None, None],
+                |dd, this, [startup, react]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.request.get_id(), react)?;
+                    dd.effects_port(react, &this.reply)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Worker {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => self.log.lock().unwrap().push((ctx.get_tag(), 0)),
+            1 => {
+                let request = ctx.get(&self.request).unwrap();
+                ctx.set(&mut self.reply, request * 2);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Adds a [Worker] at 10 ms, and sends it a request at 20 ms.
+/// Logs the replies it receives. If `bind_to_requests` is set,
+/// the mutation tries to bind the reply of the worker to the
+/// requests port, which is already set by a reaction.
+struct Spawner {
+    id: ReactorId,
+    tick: LogicalAction<()>,
+    requests: Port<u32>,
+    replies: Port<u32>,
+    bind_to_requests: bool,
+    log: Log,
+}
+
+impl ReactorInitializer for Spawner {
+    type Wrapped = Spawner;
+    /// Whether the mutation is invalid, and the log.
+    type Params = (bool, Log);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(3);
+
+    fn assemble((bind_to_requests, log): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Spawner {
+                        id,
+                        tick: cc.new_logical_action("tick", None, None, SpacingPolicy::Defer),
+                        requests: cc.new_port("requests", PortKind::Output),
+                        replies: cc.new_port("replies", PortKind::Input),
+                        bind_to_requests,
+                        log,
+                    })
+                },
+                3,
+                [None, None, None],
+                |dd, this, [startup, tick, reply]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.tick.get_id(), tick)?;
+                    dd.effects_port(tick, &this.requests)?;
+                    dd.declare_triggers(this.replies.get_id(), reply)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Spawner {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {
+                ctx.schedule(&mut self.tick, After(Duration::from_millis(10)));
+            }
+            1 if ctx.get_tag() == tag!(T0 + 10 ms) => {
+                let (requests, replies) = (self.requests.get_id(), self.replies.get_id());
+                let bind_to_requests = self.bind_to_requests;
+                let log = self.log.clone();
+                ctx.mutate(move |m| {
+                    m.add_child::<Worker>("worker", log, |m, worker| {
+                        m.bind_from(requests, &mut worker.request)?;
+                        m.bind_to(&mut worker.reply, if bind_to_requests { requests } else { replies })
+                    })?;
+                    Ok(())
+                });
+                ctx.schedule(&mut self.tick, After(Duration::from_millis(10)));
+            }
+            1 => ctx.set(&mut self.requests, 5),
+            2 => {
+                let reply = ctx.get(&self.replies).unwrap();
+                self.log.lock().unwrap().push((ctx.get_tag(), reply));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_mutation_adds_a_connected_child() {
+    let log = Log::default();
+    let options = SchedulerOptions { fast: true, ..Default::default() };
+    let summary = SyncScheduler::try_run_main::<Spawner>(options, (false, log.clone())).unwrap();
+
+    // the worker starts at the next microstep, the reply
+    // is received at the tag of the request
+    assert_eq!(*log.lock().unwrap(), vec![(tag!(T0 + 10 ms, 1), 0), (tag!(T0 + 20 ms), 10)]);
+    // startup, two ticks, the worker startup, the request and the reply
+    assert_eq!(summary.num_reactions, 6);
+}
+
+#[test]
+fn test_failed_mutation_leaves_the_program_unchanged() {
+    let log = Log::default();
+    let options = SchedulerOptions {
+        fast: true,
+        failure_policy: FailurePolicy::Log,
+        ..Default::default()
+    };
+    let summary = SyncScheduler::try_run_main::<Spawner>(options, (true, log.clone())).unwrap();
+    assert_eq!(*log.lock().unwrap(), vec![]);
+    assert_eq!(summary.num_reactions, 3);
+
+    let options = SchedulerOptions {
+        fast: true,
+        failure_policy: FailurePolicy::Shutdown,
+        ..Default::default()
+    };
+    let result = SyncScheduler::try_run_main::<Spawner>(options, (true, log));
+    assert!(matches!(result, Err(RuntimeError::ReactionFailed { message, .. }) if message.starts_with("Mutation failed")));
+}
@@ -66,7 +66,29 @@ type TestResult = Result<(), String>;
 
 impl TestFixture {
     pub fn bind<T: Sync>(&self, upstream: &mut Port<T>, downstream: &mut Port<T>) -> TestResult {
-        upstream.forward_to(downstream).map_err(|e| e.lift(&self.debug))
+        upstream.forward_to(downstream).map_err(|e| e.lift(&self.debug).to_string())
+    }
+
+    pub fn bind_delayed<T: Sync + Clone>(
+        &self,
+        upstream: &mut Port<T>,
+        downstream: &mut Port<T>,
+        delay: Duration,
+    ) -> Result<DelayedConnection<T>, String> {
+        upstream
+            .delay_to(downstream, delay)
+            .map_err(|e| e.lift(&self.debug).to_string())
+    }
+
+    pub fn bind_physical<T: Sync + Clone>(
+        &self,
+        upstream: &mut Port<T>,
+        downstream: &mut Port<T>,
+        delay: Duration,
+    ) -> Result<DelayedConnection<T>, String> {
+        upstream
+            .physical_to(downstream, delay)
+            .map_err(|e| e.lift(&self.debug).to_string())
     }
 
     pub fn set<T: Sync>(&self, port: &mut Port<T>, value: T) -> TestResult {
@@ -253,3 +275,73 @@ fn repeated_binding_panics() -> TestResult {
 
     test.ok()
 }
+
+#[test]
+fn delayed_binding_delivers_values_later() -> TestResult {
+    let mut test = TestAssembler::default();
+    let mut upstream = test.new_port("up");
+    let mut downstream = test.new_port("down");
+    let test = test.ready();
+
+    let mut connection = test.bind_delayed(&mut upstream, &mut downstream, Duration::from_millis(2))?;
+    let clock = VirtualClock::new();
+    let initial_time = clock.now();
+    let t0 = EventTag::ORIGIN;
+
+    test.set(&mut upstream, 5)?;
+    assert_eq!(None, downstream.get());
+
+    let eta = connection.forward(t0, &clock, initial_time);
+    assert_eq!(Some(t0.successor(Duration::from_millis(2))), eta);
+    upstream.clear_value();
+    assert_eq!(None, connection.forward(t0.next_microstep(), &clock, initial_time));
+
+    connection.deliver(eta.unwrap());
+    assert_eq!(Some(5), downstream.get());
+    // the downstream is cleared by the scheduler, like the other ports
+    assert_eq!(None, connection.forward(eta.unwrap(), &clock, initial_time));
+    assert_eq!(Some(5), downstream.get());
+
+    test.ok()
+}
+
+#[test]
+fn physical_binding_timestamps_values_with_physical_time() -> TestResult {
+    let mut test = TestAssembler::default();
+    let mut upstream = test.new_port("up");
+    let mut downstream = test.new_port("down");
+    let test = test.ready();
+
+    let mut connection = test.bind_physical(&mut upstream, &mut downstream, Duration::from_millis(2))?;
+    let clock = VirtualClock::new();
+    let initial_time = clock.now();
+    assert!(connection.is_physical());
+
+    clock.advance(Duration::from_secs(1));
+    test.set(&mut upstream, 5)?;
+    let eta = connection.forward(EventTag::ORIGIN, &clock, initial_time);
+    assert_eq!(Some(EventTag::offset(Duration::from_millis(1002), 0)), eta);
+
+    connection.deliver(eta.unwrap());
+    assert_eq!(Some(5), downstream.get());
+
+    test.ok()
+}
+
+#[test]
+fn delayed_binding_allows_cycles() -> TestResult {
+    let mut test = TestAssembler::default();
+    let mut a: Port<u32> = test.new_port("a");
+    let mut b = test.new_port("b");
+    let test = test.ready();
+
+    test.bind(&mut a, &mut b)?;
+    test.bind_delayed(&mut b, &mut a, Duration::ZERO)?;
+
+    assert_eq!(
+        Err("Cannot bind /a to /b, downstream is already bound".into()),
+        test.bind_delayed(&mut a, &mut b, Duration::ZERO).map(|_| ())
+    );
+
+    test.ok()
+}
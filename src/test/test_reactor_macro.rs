@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of reactors declared with [macro@crate::reactor].
+
+use crate::prelude::*;
+use crate::{reactor, ReactorTestHarness};
+
+#[reactor]
+struct Counter {
+    #[input]
+    input: Port<u32>,
+    #[output]
+    output: Port<u32>,
+    #[logical_action(delay!(5 ms))]
+    later: LogicalAction<u32>,
+    #[timer(delay!(0), delay!(10 ms))]
+    tick: Timer,
+    started: bool,
+    ticks: u32,
+}
+
+#[reactor]
+impl Counter {
+    #[reaction(triggers = [startup])]
+    fn on_startup(&mut self, _ctx: &mut ReactionCtx) {
+        self.started = true;
+    }
+
+    #[reaction(triggers = [tick])]
+    fn on_tick(&mut self, _ctx: &mut ReactionCtx) {
+        self.ticks += 1;
+    }
+
+    #[reaction(triggers = [input], effects = [output, later])]
+    fn on_input(&mut self, ctx: &mut ReactionCtx) {
+        let value = ctx.get(&self.input).unwrap();
+        ctx.set(&mut self.output, value * 2);
+        ctx.schedule_with_v(&mut self.later, Some(value), Asap);
+    }
+
+    #[reaction(triggers = [later], effects = [output])]
+    fn on_later(&mut self, ctx: &mut ReactionCtx) {
+        let value = ctx.get(&self.later).unwrap();
+        ctx.set(&mut self.output, value + 1);
+    }
+}
+
+#[test]
+fn test_reactions_and_timers() {
+    let mut harness = ReactorTestHarness::<Counter>::new(()).unwrap();
+    assert!(harness.advance_to(EventTag::ORIGIN));
+    assert!(harness.reactor().started);
+    assert_eq!(harness.reactor().ticks, 1);
+    assert!(harness.is_scheduled(|r| &r.tick, tag!(T0 + 10 ms)));
+
+    harness.set_input(|r| &mut r.input, 4);
+    assert!(harness.advance_to(tag!(T0 + 3 ms)));
+    assert_eq!(harness.get(|r| &r.output), Some(8));
+    assert!(harness.is_scheduled(|r| &r.later, tag!(T0 + 8 ms)));
+
+    assert_eq!(harness.step(), Some(tag!(T0 + 8 ms)));
+    assert_eq!(harness.get(|r| &r.output), Some(5));
+
+    // ports and actions are cleaned up after each tag
+    assert_eq!(harness.step(), Some(tag!(T0 + 10 ms)));
+    assert_eq!(harness.reactor().ticks, 2);
+    assert!(!harness.is_present(|r| &r.output));
+    assert!(!harness.is_present(|r| &r.later));
+}
@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of [SchedulerOptions::record] and [SchedulerOptions::replay].
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::assembly::{AssemblyCtx, AssemblyResult, FinishedReactor, ReactorInitializer, TriggerId, TriggerLike};
+use crate::prelude::*;
+use crate::{LocalReactionId, PhysicalActionRef, ReactorBehavior, ReactorId, SchedulerOptions, SpacingPolicy, SyncScheduler};
+
+type Log = Arc<Mutex<Vec<(EventTag, u32)>>>;
+
+/// Receives numbers from an asynchronous thread, which then
+/// stops the program. The tags at which they are received
+/// depend on physical time, unless they are replayed.
+struct Receiver {
+    id: ReactorId,
+    received: PhysicalActionRef<u32>,
+    /// The numbers, with the tag at which they were received.
+    log: Log,
+}
+
+impl ReactorInitializer for Receiver {
+    type Wrapped = Receiver;
+    type Params = Log;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble(log: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    let received = cc.new_physical_action("received", None, None, SpacingPolicy::Defer);
+                    cc.record_physical_action(
+                        &received,
+                        |v: &u32| v.to_le_bytes().to_vec(),
+                        |bytes| {
+                            let bytes = bytes.try_into().map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+                            Ok(u32::from_le_bytes(bytes))
+                        },
+                    );
+                    Ok(Receiver { id, received, log })
+                },
+                2,
+                [None, None],
+                |dd, this, [startup, receive]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.received.get_id(), receive)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Receiver {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {
+                let received = self.received.clone();
+                ctx.spawn_physical_thread(move |link| {
+                    for i in 1..=3 {
+                        std::thread::sleep(Duration::from_millis(2));
+                        link.schedule_physical_with_v(&received, Some(i), Asap).unwrap();
+                    }
+                    link.request_stop(After(Duration::from_millis(1))).unwrap();
+                });
+            }
+            1 => {
+                let value = ctx.get(&self.received).unwrap();
+                self.log.lock().unwrap().push((ctx.get_tag(), value));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A recording file that is removed at the end of the test.
+struct TempRecording(PathBuf);
+
+impl Drop for TempRecording {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+#[test]
+fn test_replay_executes_like_the_recording() {
+    let file = format!("reactor_rt_replay_{}.lfrr", std::process::id());
+    let recording = TempRecording(std::env::temp_dir().join(file));
+
+    let recorded = Log::default();
+    let options = SchedulerOptions {
+        keep_alive: true,
+        record: Some(recording.0.clone()),
+        ..Default::default()
+    };
+    let recorded_run = SyncScheduler::try_run_main::<Receiver>(options, recorded.clone()).unwrap();
+    assert_eq!(
+        recorded.lock().unwrap().iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+
+    // the asynchronous thread runs again, but its events are ignored
+    let replayed = Log::default();
+    let options = SchedulerOptions {
+        keep_alive: true,
+        replay: Some(recording.0.clone()),
+        ..Default::default()
+    };
+    let replayed_run = SyncScheduler::try_run_main::<Receiver>(options, replayed.clone()).unwrap();
+    assert_eq!(*replayed.lock().unwrap(), *recorded.lock().unwrap());
+    assert_eq!(replayed_run.final_tag, recorded_run.final_tag);
+    assert_eq!(replayed_run.num_tags, recorded_run.num_tags);
+}
@@ -0,0 +1,577 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of the scheduler running whole programs.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::assembly::{
+    AssemblyCtx, AssemblyFailure, AssemblyResult, FinishedReactor, PortKind, ReactorInitializer, TriggerId, TriggerLike,
+};
+use crate::prelude::*;
+use crate::{
+    FailurePolicy, LocalReactionId, Mode, ModeTransition, ReactorBehavior, ReactorId, RuntimeError, SchedulerOptions,
+    SpacingPolicy, SyncScheduler, TraceFormat, TraceOptions, VirtualClock,
+};
+
+type Log = Arc<Mutex<Vec<EventTag>>>;
+
+/// Triggers an action a number of times, every period,
+/// and logs the tags at which it is triggered.
+struct Ticker {
+    id: ReactorId,
+    period: Duration,
+    remaining: u32,
+    tick: LogicalAction<()>,
+    log: Log,
+}
+
+impl ReactorInitializer for Ticker {
+    type Wrapped = Ticker;
+    /// The period, the number of ticks, and the log.
+    type Params = (Duration, u32, Log);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble((period, remaining, log): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Ticker {
+                        id,
+                        period,
+                        remaining,
+                        tick: cc.new_logical_action("tick", None, None, SpacingPolicy::Defer),
+                        log,
+                    })
+                },
+                2,
+                [None, None],
+                |dd, this, [startup, tick]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.tick.get_id(), tick)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Ticker {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {}
+            1 => {
+                self.log.lock().unwrap().push(ctx.get_tag());
+                self.remaining -= 1;
+            }
+            _ => unreachable!(),
+        }
+        if self.remaining > 0 {
+            ctx.schedule(&mut self.tick, After(self.period));
+        }
+    }
+}
+
+/// Logs the tag and the local ID of the reaction executed
+/// each time the action is triggered.
+type ReactionLog = Arc<Mutex<Vec<(EventTag, usize)>>>;
+
+/// Triggers an action twice, 10 ms apart. The reaction to
+/// the action has a deadline of 5 ms, and a violation handler.
+struct Lagging {
+    id: ReactorId,
+    tick: LogicalAction<()>,
+    log: ReactionLog,
+}
+
+impl ReactorInitializer for Lagging {
+    type Wrapped = Lagging;
+    type Params = ReactionLog;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(3);
+
+    fn assemble(log: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Lagging {
+                        id,
+                        tick: cc.new_logical_action("tick", None, None, SpacingPolicy::Defer),
+                        log,
+                    })
+                },
+                3,
+                [None, None, None],
+                |dd, this, [startup, tick, handler]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.tick.get_id(), tick)?;
+                    dd.declare_deadline(tick, Duration::from_millis(5), handler)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Lagging {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        if local_rid.index() > 0 {
+            let mut log = self.log.lock().unwrap();
+            log.push((ctx.get_tag(), local_rid.index()));
+            if log.len() == 2 {
+                return;
+            }
+        }
+        ctx.schedule(&mut self.tick, After(Duration::from_millis(10)));
+    }
+}
+
+/// Triggers an action at 10 ms and 20 ms. The action triggers
+/// one reaction in each mode, the first one switches to the
+/// second mode.
+struct Modal {
+    id: ReactorId,
+    tick: LogicalAction<()>,
+    idle: Mode,
+    running: Mode,
+    log: ReactionLog,
+}
+
+impl ReactorInitializer for Modal {
+    type Wrapped = Modal;
+    /// Whether each mode is initial, and the log.
+    type Params = ([bool; 2], ReactionLog);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(3);
+
+    fn assemble(([idle, running], log): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Modal {
+                        id,
+                        tick: cc.new_logical_action("tick", None, None, SpacingPolicy::Defer),
+                        idle: cc.new_mode("idle", idle),
+                        running: cc.new_mode("running", running),
+                        log,
+                    })
+                },
+                3,
+                [None, None, None],
+                |dd, this, [startup, idle, running]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.tick.get_id(), idle)?;
+                    dd.declare_triggers(this.tick.get_id(), running)?;
+                    dd.declare_mode_reaction(idle, this.idle)?;
+                    dd.declare_mode_reaction(running, this.running)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Modal {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {
+                ctx.schedule(&mut self.tick, After(Duration::from_millis(10)));
+            }
+            1 => {
+                self.log.lock().unwrap().push((ctx.get_tag(), 1));
+                ctx.set_mode(self.running, ModeTransition::Reset);
+                ctx.schedule(&mut self.tick, After(Duration::from_millis(10)));
+            }
+            2 => self.log.lock().unwrap().push((ctx.get_tag(), 2)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Declares its reaction in a mode of its child, which
+/// is an error.
+struct ModalParent {
+    id: ReactorId,
+}
+
+impl ReactorInitializer for ModalParent {
+    type Wrapped = ModalParent;
+    type Params = ReactionLog;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(1);
+
+    fn assemble(log: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.with_child::<Modal, _>("modal", ([true, false], log), |ctx, modal| {
+                let mode = modal.idle;
+                ctx.assemble_self(
+                    |_, id| Ok(ModalParent { id }),
+                    1,
+                    [None],
+                    |dd, _, [react]| dd.declare_mode_reaction(react, mode),
+                )
+            })
+        })
+    }
+}
+
+impl ReactorBehavior for ModalParent {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, _ctx: &mut ReactionCtx, _local_rid: LocalReactionId) {
+        unreachable!()
+    }
+}
+
+/// Has a reaction that is triggered by the port it sets,
+/// which is an instantaneous cycle.
+struct Cyclic {
+    id: ReactorId,
+    port: Port<u32>,
+}
+
+impl ReactorInitializer for Cyclic {
+    type Wrapped = Cyclic;
+    type Params = ();
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(1);
+
+    fn assemble(_: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| Ok(Cyclic { id, port: cc.new_port("port", PortKind::Output) }),
+                1,
+                [None],
+                |dd, this, [react]| {
+                    dd.declare_triggers(this.port.get_id(), react)?;
+                    dd.effects_port(react, &this.port)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Cyclic {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, _ctx: &mut ReactionCtx, _local_rid: LocalReactionId) {
+        unreachable!()
+    }
+}
+
+/// Logs the indices of the reactors of a bank.
+type ShutdownLog = Arc<Mutex<Vec<usize>>>;
+
+/// Reacts to startup depending on its index in a bank: the
+/// reactor 1 panics, the reactor 2 fails, the others do
+/// nothing. Logs its index at shutdown.
+struct Failing {
+    id: ReactorId,
+    index: usize,
+    log: ShutdownLog,
+}
+
+impl ReactorInitializer for Failing {
+    type Wrapped = Failing;
+    type Params = (usize, ShutdownLog);
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(2);
+
+    fn assemble((index, log): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |_, id| Ok(Failing { id, index, log }),
+                2,
+                [None, None],
+                |dd, _, [startup, shutdown]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(TriggerId::SHUTDOWN, shutdown)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Failing {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match (local_rid.index(), self.index) {
+            (0, 1) => panic!("boom"),
+            (0, 2) => ctx.fail("bad input"),
+            (0, _) => {}
+            (1, _) => self.log.lock().unwrap().push(self.index),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// A bank of three [Failing] reactors, whose startup
+/// reactions execute in parallel with the parallel runtime.
+struct FailingBank {
+    id: ReactorId,
+}
+
+impl ReactorInitializer for FailingBank {
+    type Wrapped = FailingBank;
+    type Params = ShutdownLog;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(0);
+
+    fn assemble(log: Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.with_child_bank::<Failing, _, _>(
+                "failing",
+                3,
+                |i| (i, log.clone()),
+                |ctx, _| ctx.assemble_self(|_, id| Ok(FailingBank { id }), 0, [], |_, _, []| Ok(())),
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for FailingBank {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, _ctx: &mut ReactionCtx, _local_rid: LocalReactionId) {
+        unreachable!()
+    }
+}
+
+/// Wait until the condition holds, which is
+/// expected to happen on another thread.
+fn wait_until(condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Condition was not met in time");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_fast_mode_does_not_wait_for_physical_time() {
+    let log = Log::default();
+    let options = SchedulerOptions { fast: true, ..Default::default() };
+    let start = Instant::now();
+    let summary = SyncScheduler::try_run_main::<Ticker>(options, (Duration::from_secs(3600), 3, log.clone())).unwrap();
+
+    // three hours of logical time
+    assert!(start.elapsed() < Duration::from_secs(60));
+    let expected = vec![tag!(T0 + 1 h), tag!(T0 + 2 h), tag!(T0 + 3 h)];
+    assert_eq!(*log.lock().unwrap(), expected);
+    assert_eq!(summary.final_tag, tag!(T0 + 3 h, 1));
+}
+
+#[test]
+fn test_fast_mode_does_not_wait_for_the_timeout() {
+    let log = Log::default();
+    let options = SchedulerOptions {
+        fast: true,
+        timeout: Some(Duration::from_secs(3600)),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    let summary = SyncScheduler::try_run_main::<Ticker>(options, (Duration::from_millis(10), 2, log.clone())).unwrap();
+
+    // the queue is empty after 20 ms, the program jumps to the timeout
+    assert!(start.elapsed() < Duration::from_secs(60));
+    assert_eq!(*log.lock().unwrap(), vec![tag!(T0 + 10 ms), tag!(T0 + 20 ms)]);
+    assert_eq!(summary.final_tag, tag!(T0 + 1 h));
+}
+
+#[test]
+fn test_handle_stops_a_running_program() {
+    let log = Log::default();
+    let mut handle =
+        SyncScheduler::start::<Ticker>(SchedulerOptions::default(), (Duration::from_millis(1), u32::MAX, log.clone())).unwrap();
+    wait_until(|| log.lock().unwrap().len() >= 2);
+    assert!(!handle.is_terminated());
+
+    handle.request_stop(Asap).unwrap();
+    let summary = handle.join().unwrap();
+    let log = log.lock().unwrap();
+    assert!(summary.final_tag >= *log.last().unwrap());
+    // the startup reaction, then one per tick
+    assert_eq!(summary.num_reactions, log.len() as u64 + 1);
+}
+
+#[test]
+fn test_errors_are_returned_before_running() {
+    let result = SyncScheduler::try_run_main::<Cyclic>(SchedulerOptions::default(), ());
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Assembly(AssemblyFailure::CyclicDependencyGraph))
+    ));
+
+    let missing = std::env::temp_dir().join(format!("reactor_rt_missing_{}", std::process::id()));
+    let options = SchedulerOptions {
+        trace: Some(TraceOptions {
+            path: missing.join("trace.json"),
+            format: TraceFormat::Chrome,
+        }),
+        ..Default::default()
+    };
+    let log = Log::default();
+    let result = SyncScheduler::try_run_main::<Ticker>(options, (Duration::from_millis(1), 1, log.clone()));
+    assert!(matches!(result, Err(RuntimeError::Io(_))));
+
+    let options = SchedulerOptions {
+        replay: Some(missing.join("recording.lfrr")),
+        ..Default::default()
+    };
+    let result = SyncScheduler::try_run_main::<Ticker>(options, (Duration::from_millis(1), 1, log.clone()));
+    assert!(matches!(result, Err(RuntimeError::Replay(_))));
+
+    // the program did not start
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_failure_policies() {
+    let log = ShutdownLog::default();
+    let options = SchedulerOptions {
+        failure_policy: FailurePolicy::Log,
+        ..Default::default()
+    };
+    SyncScheduler::try_run_main::<FailingBank>(options, log.clone()).unwrap();
+    log.lock().unwrap().sort_unstable();
+    assert_eq!(*log.lock().unwrap(), vec![0, 1, 2]);
+
+    log.lock().unwrap().clear();
+    let options = SchedulerOptions {
+        failure_policy: FailurePolicy::Shutdown,
+        ..Default::default()
+    };
+    match SyncScheduler::try_run_main::<FailingBank>(options, log.clone()) {
+        Err(RuntimeError::ReactionFailed { message, .. }) => {
+            assert!(message == "panicked: boom" || message == "bad input", "{}", message)
+        }
+        result => panic!("Expected a failure, got {:?}", result.map(|summary| summary.final_tag)),
+    }
+    // shutdown reactions are still executed
+    assert_eq!(log.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_handle_joins_a_program_that_ends_by_itself() {
+    let log = Log::default();
+    let options = SchedulerOptions { fast: true, ..Default::default() };
+    let handle = SyncScheduler::start::<Ticker>(options, (Duration::from_secs(3600), 3, log.clone())).unwrap();
+
+    // no stop request, the program shuts down once its events are processed
+    let summary = handle.join().unwrap();
+    assert_eq!(*log.lock().unwrap(), vec![tag!(T0 + 1 h), tag!(T0 + 2 h), tag!(T0 + 3 h)]);
+    assert_eq!(summary.final_tag, tag!(T0 + 3 h, 1));
+}
+
+#[test]
+fn test_virtual_clock_drives_the_scheduler() {
+    let log = Log::default();
+    let clock = Arc::new(VirtualClock::new());
+    let options = SchedulerOptions { clock: Some(clock.clone()), ..Default::default() };
+    let mut handle = SyncScheduler::start::<Ticker>(options, (Duration::from_secs(3600), 2, log.clone())).unwrap();
+
+    // the scheduler waits for the clock to reach each tag
+    clock.advance(Duration::from_secs(3600));
+    wait_until(|| log.lock().unwrap().len() == 1);
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(*log.lock().unwrap(), vec![tag!(T0 + 1 h)]);
+
+    clock.advance(Duration::from_secs(3600));
+    wait_until(|| log.lock().unwrap().len() == 2);
+    assert_eq!(*log.lock().unwrap(), vec![tag!(T0 + 1 h), tag!(T0 + 2 h)]);
+
+    // the handle keeps the program alive until it is stopped
+    handle.request_stop(Asap).unwrap();
+    let summary = handle.join().unwrap();
+    assert_eq!(summary.final_tag, tag!(T0 + 2 h, 1));
+}
+
+#[test]
+fn test_deadline_violation_executes_the_handler() {
+    let log = ReactionLog::default();
+    let clock = Arc::new(VirtualClock::new());
+    let options = SchedulerOptions { clock: Some(clock.clone()), ..Default::default() };
+    let mut handle = SyncScheduler::start::<Lagging>(options, log.clone()).unwrap();
+
+    // the first tick executes on time
+    clock.advance(Duration::from_millis(10));
+    wait_until(|| log.lock().unwrap().len() == 1);
+
+    // physical time lags behind the second tick by more than the deadline
+    clock.advance(Duration::from_secs(1));
+    wait_until(|| log.lock().unwrap().len() == 2);
+    assert_eq!(*log.lock().unwrap(), vec![(tag!(T0 + 10 ms), 1), (tag!(T0 + 20 ms), 2)]);
+
+    handle.request_stop(Asap).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn test_reactions_of_inactive_modes_are_skipped() {
+    let log = ReactionLog::default();
+    let options = SchedulerOptions { fast: true, ..Default::default() };
+    let summary = SyncScheduler::try_run_main::<Modal>(options, ([true, false], log.clone())).unwrap();
+
+    assert_eq!(*log.lock().unwrap(), vec![(tag!(T0 + 10 ms), 1), (tag!(T0 + 20 ms), 2)]);
+    // the startup reaction, then one reaction per tick
+    assert_eq!(summary.num_reactions, 3);
+}
+
+#[test]
+fn test_modal_reactor_needs_one_initial_mode() {
+    for initial in [[true, true], [false, false]] {
+        let result = SyncScheduler::try_run_main::<Modal>(SchedulerOptions::default(), (initial, ReactionLog::default()));
+        assert!(matches!(
+            result,
+            Err(RuntimeError::Assembly(AssemblyFailure::InvalidInitialMode { .. }))
+        ));
+    }
+}
+
+#[test]
+fn test_reaction_cannot_be_declared_in_a_mode_of_another_reactor() {
+    let result = SyncScheduler::try_run_main::<ModalParent>(SchedulerOptions::default(), ReactionLog::default());
+    assert!(matches!(
+        result,
+        Err(RuntimeError::Assembly(AssemblyFailure::ForeignModeMember { .. }))
+    ));
+}
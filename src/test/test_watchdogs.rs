@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! Tests of watchdogs in a running program, driven by a virtual clock.
+
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::assembly::{AssemblyCtx, AssemblyResult, FinishedReactor, ReactorInitializer, TriggerId, TriggerLike};
+use crate::prelude::*;
+use crate::{
+    LocalReactionId, ReactorBehavior, ReactorId, SchedulerOptions, SpacingPolicy, SyncScheduler, VirtualClock, Watchdog,
+};
+
+/// Kicks its watchdog at every beat, three times, every 10 ms.
+/// A second watchdog is started at startup, and stopped at the
+/// first beat.
+struct Heartbeat {
+    id: ReactorId,
+    beats: u32,
+    beat: LogicalAction<()>,
+    watchdog: Watchdog,
+    stopped: Watchdog,
+    /// Notified once the watchdog has been kicked for the last time.
+    done: Sender<()>,
+    /// The tags at which a watchdog expired, with its name.
+    expired: Arc<Mutex<Vec<(&'static str, EventTag)>>>,
+}
+
+type HeartbeatParams = (Sender<()>, Arc<Mutex<Vec<(&'static str, EventTag)>>>);
+
+impl ReactorInitializer for Heartbeat {
+    type Wrapped = Heartbeat;
+    type Params = HeartbeatParams;
+    const MAX_REACTION_ID: LocalReactionId = LocalReactionId::new(4);
+
+    fn assemble((done, expired): Self::Params, ctx: AssemblyCtx<Self>) -> AssemblyResult<FinishedReactor<Self>> {
+        ctx.assemble(|ctx| {
+            ctx.assemble_self(
+                |cc, id| {
+                    Ok(Heartbeat {
+                        id,
+                        beats: 0,
+                        beat: cc.new_logical_action("beat", None, None, SpacingPolicy::Defer),
+                        watchdog: cc.new_watchdog("watchdog", Duration::from_millis(100)),
+                        stopped: cc.new_watchdog("stopped", Duration::from_millis(50)),
+                        done,
+                        expired,
+                    })
+                },
+                4,
+                [None, None, None, None],
+                |dd, this, [startup, beat, expired, stopped_expired]| {
+                    dd.declare_triggers(TriggerId::STARTUP, startup)?;
+                    dd.declare_triggers(this.beat.get_id(), beat)?;
+                    dd.declare_triggers(this.watchdog.get_id(), expired)?;
+                    dd.declare_triggers(this.stopped.get_id(), stopped_expired)?;
+                    Ok(())
+                },
+            )
+        })
+    }
+}
+
+impl ReactorBehavior for Heartbeat {
+    fn id(&self) -> ReactorId {
+        self.id
+    }
+
+    fn react(&mut self, ctx: &mut ReactionCtx, local_rid: LocalReactionId) {
+        match local_rid.index() {
+            0 => {
+                ctx.start_watchdog(&self.watchdog);
+                ctx.start_watchdog(&self.stopped);
+                ctx.schedule(&mut self.beat, After(Duration::from_millis(10)));
+            }
+            1 => {
+                self.beats += 1;
+                ctx.start_watchdog(&self.watchdog);
+                ctx.stop_watchdog(&self.stopped);
+                if self.beats < 3 {
+                    ctx.schedule(&mut self.beat, After(Duration::from_millis(10)));
+                } else {
+                    self.done.send(()).unwrap();
+                }
+            }
+            2 => {
+                self.expired.lock().unwrap().push(("watchdog", ctx.get_tag()));
+                ctx.request_stop(Asap);
+            }
+            3 => self.expired.lock().unwrap().push(("stopped", ctx.get_tag())),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_watchdog_expires_after_last_kick() {
+    let clock = Arc::new(VirtualClock::new());
+    let options = SchedulerOptions {
+        fast: true,
+        clock: Some(clock.clone()),
+        ..Default::default()
+    };
+    let (done, last_kick) = channel();
+    let expired = Arc::new(Mutex::new(Vec::new()));
+    let handle = SyncScheduler::start::<Heartbeat>(options, (done, expired.clone())).unwrap();
+
+    // the last kick was at 30 ms, so the watchdog expires at 130 ms
+    last_kick.recv().unwrap();
+    clock.advance(Duration::from_millis(100));
+    clock.advance(Duration::from_millis(30));
+
+    let summary = handle.join().unwrap();
+    assert_eq!(*expired.lock().unwrap(), vec![("watchdog", tag!(T0 + 130 ms))]);
+    assert_eq!(summary.final_tag, tag!(T0 + 130 ms, 1));
+}
@@ -34,9 +34,16 @@ pub struct MicroStep(MS);
 
 impl MicroStep {
     pub const ZERO: MicroStep = MicroStep(0);
+    pub(crate) const MAX: MicroStep = MicroStep(MS::MAX);
+
     pub fn new(u: MS) -> Self {
         Self(u)
     }
+
+    #[inline]
+    pub(crate) fn raw(self) -> MS {
+        self.0
+    }
 }
 
 impl Display for MicroStep {
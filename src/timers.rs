@@ -21,6 +21,7 @@
  * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
  * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
 use std::time::Instant;
 
 use super::*;
@@ -30,31 +31,135 @@ use crate::assembly::{TriggerId, TriggerLike};
 /// itself periodically.
 ///
 /// For periodic timers, a reaction is synthesized which reschedules
-/// the timer.
+/// the timer. Timers may be stopped, restarted, and have their period
+/// changed at runtime, see [ReactionCtx::set_timer_period] and similar.
 pub struct Timer {
     id: TriggerId,
 
     /// Minimal duration after the start of the program after
-    /// which the timer starts to trigger.
-    pub offset: Duration,
+    /// which the timer starts to trigger. This is also the
+    /// offset used when the mode of the timer is reset.
+    offset: Duration,
 
-    /// Period between events emitted by this timer. A period
-    /// of zero means that the timer will trigger exactly once
-    /// after the specified offset.
-    pub period: Duration,
+    /// Ticks of this timer. They are shared with the scheduler,
+    /// as timers declared in a mode restart when their mode is
+    /// entered, see [crate::ModeTransition]. They are only changed
+    /// when the timer fires or is changed by a reaction, otherwise
+    /// they are only read.
+    ticks: Arc<RwLock<TimerTicks>>,
+}
+
+/// The ticks of a timer that matter at runtime: the last one,
+/// which may be being processed, and the next scheduled one.
+/// The presence of a timer is determined by these, so that it
+/// is consistent with the events that are actually scheduled.
+pub(crate) struct TimerTicks {
+    /// The last tick that was processed, or is being processed.
+    prev: Option<EventTag>,
+    /// The next tick, for which an event is scheduled.
+    next: Option<EventTag>,
+    /// The current period of the timer.
+    period: Duration,
+}
+
+impl TimerTicks {
+    pub(crate) fn next(&self) -> Option<EventTag> {
+        self.next
+    }
+
+    /// Returns the current period of the timer.
+    pub(crate) fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Change the period of the timer. This does not
+    /// reschedule the next tick, see [Self::set_next].
+    pub(crate) fn set_period(&mut self, period: Duration) {
+        self.period = period;
+    }
+
+    /// Record that the tick at the given tag is processed, and
+    /// returns the next tick if the timer is periodic. Returns
+    /// None if the tag is not the next tick, which means the timer
+    /// was stopped or rescheduled after this event was scheduled.
+    pub(crate) fn fire(&mut self, tag: EventTag) -> Option<EventTag> {
+        if self.next != Some(tag) {
+            return None;
+        }
+        self.prev = Some(tag);
+        self.next = if self.period.is_zero() {
+            None
+        } else {
+            Some(tag.successor(self.period))
+        };
+        self.next
+    }
+
+    /// Replace the next tick, and return the previous one. If the
+    /// previous one is the current tag, it is kept as the last tick,
+    /// so that the timer stays present until the end of the tag.
+    pub(crate) fn set_next(&mut self, now: EventTag, next: Option<EventTag>) -> Option<EventTag> {
+        if self.next == Some(now) {
+            self.prev = Some(now);
+        }
+        std::mem::replace(&mut self.next, next)
+    }
+
+    /// Returns the last tick, if any.
+    pub(crate) fn prev(&self) -> Option<EventTag> {
+        self.prev
+    }
+
+    /// Whether the given tag is a tick of the timer.
+    fn is_tick(&self, tag: EventTag) -> bool {
+        self.next == Some(tag) || self.prev == Some(tag)
+    }
+
+    /// Restore the ticks from a checkpoint.
+    pub(crate) fn restore(&mut self, prev: Option<EventTag>, next: Option<EventTag>, period: Duration) {
+        *self = TimerTicks { prev, next, period };
+    }
 }
 
 impl Timer {
     pub(crate) fn new(id: TriggerId, offset: Duration, period: Duration) -> Self {
-        Self { offset, period, id }
+        let ticks = TimerTicks { prev: None, next: None, period };
+        Self { offset, id, ticks: Arc::new(RwLock::new(ticks)) }
     }
 
-    /// Whether the timer should repeat itself. A period of zero
-    /// means that the timer will trigger exactly once after the
-    /// specified offset.
+    /// Returns the ticks of this timer, to share them with
+    /// the scheduler.
+    pub(crate) fn share_ticks(&self) -> Arc<RwLock<TimerTicks>> {
+        self.ticks.clone()
+    }
+
+    /// Lock the ticks of this timer to change them.
+    pub(crate) fn ticks(&self) -> RwLockWriteGuard<'_, TimerTicks> {
+        self.ticks.write().unwrap()
+    }
+
+    /// Minimal duration after the start of the program after
+    /// which the timer starts to trigger.
+    #[inline]
+    pub fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    /// Period between events emitted by this timer. A period
+    /// of zero means that the timer will trigger exactly once
+    /// after the specified offset. This is the period as declared,
+    /// or as last changed with [ReactionCtx::set_timer_period].
+    #[inline]
+    pub fn period(&self) -> Duration {
+        self.ticks.read().unwrap().period()
+    }
+
+    /// Whether the timer should repeat itself. This reflects
+    /// the current period of the timer, which may have been
+    /// changed at runtime.
     #[inline]
     pub fn is_periodic(&self) -> bool {
-        !self.period.is_zero()
+        !self.period().is_zero()
     }
 }
 
@@ -65,15 +170,9 @@ impl TriggerLike for Timer {
 }
 
 impl ReactionTrigger<()> for Timer {
+    #[inline]
     fn is_present(&self, now: &EventTag, _start: &Instant) -> bool {
-        let elapsed = now.duration_since_start();
-        if elapsed == self.offset {
-            true
-        } else if elapsed < self.offset || !self.is_periodic() {
-            false
-        } else {
-            ((elapsed - self.offset).as_nanos() % self.period.as_nanos()) == 0
-        }
+        self.ticks.read().unwrap().is_tick(*now)
     }
 
     #[inline]
@@ -94,3 +193,46 @@ impl ReactionTrigger<()> for Timer {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_presence_follows_rescheduled_ticks() {
+        let timer = Timer::new(TriggerId::FIRST_REGULAR, Duration::ZERO, Duration::from_millis(10));
+        let start = Instant::now();
+        timer.ticks().set_next(EventTag::ORIGIN, Some(tag!(T0)));
+        assert_eq!(timer.ticks().fire(tag!(T0)), Some(tag!(T0 + 10 ms)));
+        assert_eq!(timer.ticks().fire(tag!(T0 + 10 ms)), Some(tag!(T0 + 20 ms)));
+        assert!(timer.is_present(&tag!(T0 + 10 ms), &start));
+
+        // the period is changed at 15 ms, the next tick is moved
+        timer.ticks().set_period(Duration::from_millis(3));
+        assert_eq!(
+            timer.ticks().set_next(tag!(T0 + 15 ms), Some(tag!(T0 + 18 ms))),
+            Some(tag!(T0 + 20 ms))
+        );
+        assert!(!timer.is_present(&tag!(T0 + 20 ms), &start));
+        // the stale event at 20 ms does not reschedule anything
+        assert_eq!(timer.ticks().fire(tag!(T0 + 20 ms)), None);
+        assert_eq!(timer.ticks().fire(tag!(T0 + 18 ms)), Some(tag!(T0 + 21 ms)));
+
+        // stopping the timer at one of its ticks keeps it present at that tick
+        timer.ticks().set_next(tag!(T0 + 21 ms), None);
+        assert!(timer.is_present(&tag!(T0 + 21 ms), &start));
+        assert_eq!(timer.ticks().fire(tag!(T0 + 21 ms)), None);
+        assert_eq!(timer.ticks().next(), None);
+    }
+
+    #[test]
+    fn test_period_follows_changes() {
+        let timer = Timer::new(TriggerId::FIRST_REGULAR, Duration::ZERO, Duration::from_millis(10));
+        assert_eq!(timer.period(), Duration::from_millis(10));
+        assert!(timer.is_periodic());
+
+        timer.ticks().set_period(Duration::ZERO);
+        assert_eq!(timer.period(), Duration::ZERO);
+        assert!(!timer.is_periodic());
+    }
+}
@@ -25,6 +25,7 @@
 use std::convert::TryFrom;
 use std::time::Duration;
 
+pub(crate) mod reconnectable;
 pub(crate) mod vecmap;
 
 #[macro_export]
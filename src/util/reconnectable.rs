@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+//! An unbounded channel whose receiver can create new senders,
+//! even after all senders have been dropped.
+//!
+//! The receiver is disconnected while no sender is alive: [Receiver::recv]
+//! then returns an error once the queue is empty, instead of blocking
+//! forever. This is implemented over a [crossbeam_channel] channel,
+//! by counting the live senders. The last sender to be dropped sends
+//! a message that wakes up a blocked receiver.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crossbeam_channel::{RecvError, RecvTimeoutError, SendError};
+
+enum Message<T> {
+    Value(T),
+    /// Sent by the last live sender when it is dropped.
+    Wake,
+}
+
+/// Creates an unbounded channel.
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let live = Arc::new(AtomicUsize::new(0));
+    let receiver = Receiver { rx, tx, live };
+    (receiver.new_sender(), receiver)
+}
+
+/// The sending side of a channel. Senders may be cloned and
+/// shared between threads.
+pub struct Sender<T> {
+    tx: crossbeam_channel::Sender<Message<T>>,
+    live: Arc<AtomicUsize>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, which fails only if the receiver has
+    /// been dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.tx.send(Message::Value(value)).map_err(|SendError(msg)| match msg {
+            Message::Value(value) => SendError(value),
+            Message::Wake => unreachable!(),
+        })
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        Self { tx: self.tx.clone(), live: self.live.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.live.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // the receiver may be gone already, which is fine
+            let _ = self.tx.send(Message::Wake);
+        }
+    }
+}
+
+/// The receiving side of a channel.
+pub struct Receiver<T> {
+    rx: crossbeam_channel::Receiver<Message<T>>,
+    /// Kept to create new senders, this does not count as
+    /// a live sender.
+    tx: crossbeam_channel::Sender<Message<T>>,
+    live: Arc<AtomicUsize>,
+}
+
+impl<T> Receiver<T> {
+    /// Creates a new sender, which reconnects the channel if
+    /// all senders had been dropped.
+    pub fn new_sender(&self) -> Sender<T> {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        Sender { tx: self.tx.clone(), live: self.live.clone() }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.live.load(Ordering::SeqCst) == 0
+    }
+
+    /// Iterates over the values that are already queued,
+    /// without blocking.
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.rx.try_iter().filter_map(|msg| match msg {
+            Message::Value(value) => Some(value),
+            Message::Wake => None,
+        })
+    }
+
+    /// Blocks until a value is received. Returns an error if
+    /// the queue is empty and no sender is alive.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            if self.is_disconnected() {
+                return self.try_iter().next().ok_or(RecvError);
+            }
+            // this cannot fail, as we hold a sender
+            if let Ok(Message::Value(value)) = self.rx.recv() {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Blocks until a value is received or the timeout elapses.
+    /// Returns an error if the queue is empty and no sender is
+    /// alive.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.is_disconnected() {
+                return self.try_iter().next().ok_or(RecvTimeoutError::Disconnected);
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(Message::Value(value)) => return Ok(value),
+                Ok(Message::Wake) => continue,
+                Err(_) => return Err(RecvTimeoutError::Timeout),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_recv_fails_when_all_senders_are_dropped() {
+        let (tx, rx) = unbounded::<u32>();
+        let other = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            other.send(2).unwrap();
+        });
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        // the queued values are received before the error
+        assert_eq!(rx.recv(), Err(RecvError));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_new_sender_reconnects() {
+        let (tx, rx) = unbounded::<u32>();
+        drop(tx);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+
+        let tx = rx.new_sender();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+        tx.send(3).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![3]);
+    }
+}
@@ -120,6 +120,20 @@ where
         }
     }
 
+    /// Keeps only the entries for which the predicate returns
+    /// true. The predicate may modify the values.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        let mut i = 0;
+        while i < self.v.len() {
+            let (key, value) = &mut self.v[i];
+            if f(key, value) {
+                i += 1;
+            } else {
+                self.v.remove(i);
+            }
+        }
+    }
+
     /// Get the value associated with `key`, if it exists.
     pub fn get(&self, key: &K) -> Option<&V> {
         match self.find_k(key) {
@@ -0,0 +1,214 @@
+/*
+ * Copyright (c) 2021, TU Dresden.
+ *
+ * Redistribution and use in source and binary forms, with or without modification,
+ * are permitted provided that the following conditions are met:
+ *
+ * 1. Redistributions of source code must retain the above copyright notice,
+ *    this list of conditions and the following disclaimer.
+ *
+ * 2. Redistributions in binary form must reproduce the above copyright notice,
+ *    this list of conditions and the following disclaimer in the documentation
+ *    and/or other materials provided with the distribution.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY
+ * EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL
+ * THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+ * SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+ * PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+ * INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT,
+ * STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF
+ * THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+use super::*;
+use crate::assembly::{TriggerId, TriggerLike};
+
+/// A watchdog triggers its reactions if it is not restarted or
+/// stopped within some bound on physical time after it has been
+/// started, see [ReactionCtx::start_watchdog].
+///
+/// The expiry of a watchdog is a physical event: like for physical
+/// actions, its tag is the physical time at which it is noticed.
+#[derive(Clone)]
+pub struct Watchdog {
+    id: TriggerId,
+
+    /// Bound on physical time after which the watchdog expires,
+    /// counted from the logical time at which it is started.
+    pub timeout: Duration,
+
+    state: Arc<(Mutex<WatchdogState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    /// Incremented every time the watchdog is started or stopped,
+    /// so that an expiry noticed for an earlier deadline is ignored.
+    generation: u64,
+    /// Physical time at which the watchdog expires, if it is running.
+    deadline: Option<Instant>,
+    /// Whether a thread is waiting for the deadline. There is
+    /// at most one such thread, which is woken up through the
+    /// condition variable when the deadline changes.
+    has_waiter: bool,
+    /// The tag at which the watchdog last expired.
+    expired_at: Option<EventTag>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(id: TriggerId, timeout: Duration) -> Self {
+        Self { id, timeout, state: Default::default() }
+    }
+
+    /// Replace the deadline of the watchdog. Returns true if
+    /// a thread must be started to wait for it, see [Self::wait_for_expiry],
+    /// because none is waiting already.
+    pub(crate) fn start(&self, deadline: Instant) -> bool {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        state.generation += 1;
+        state.deadline = Some(deadline);
+        cvar.notify_all();
+        !std::mem::replace(&mut state.has_waiter, true)
+    }
+
+    /// Clear the deadline of the watchdog, if any.
+    pub(crate) fn stop(&self) {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        state.generation += 1;
+        state.deadline = None;
+        cvar.notify_all();
+    }
+
+    /// Wake up the thread waiting for the deadline, so that it
+    /// reads the clock again, or notices that the scheduler has
+    /// shut down.
+    pub(crate) fn wake(&self) {
+        let (lock, cvar) = &*self.state;
+        let _state = lock.lock().unwrap();
+        cvar.notify_all();
+    }
+
+    /// Block until the current deadline is reached on the clock,
+    /// and return its generation. The deadline may be moved by
+    /// [Self::start] in the meantime. Returns None when the
+    /// watchdog is stopped or the scheduler has shut down, in
+    /// which case the waiting thread must exit.
+    pub(crate) fn wait_for_expiry(&self, clock: &dyn Clock, was_terminated: &AtomicBool) -> Option<u64> {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock().unwrap();
+        loop {
+            let deadline = match state.deadline {
+                Some(deadline) if !was_terminated.load(Ordering::SeqCst) => deadline,
+                _ => {
+                    state.has_waiter = false;
+                    return None;
+                }
+            };
+            if clock.now() >= deadline {
+                state.deadline = None;
+                return Some(state.generation);
+            }
+            state = match clock.real_time_until(deadline, &ClockWaker::watchdog(self.clone())) {
+                Some(timeout) => cvar.wait_timeout(state, timeout).unwrap().0,
+                None => cvar.wait(state).unwrap(),
+            };
+        }
+    }
+
+    /// Record that the deadline of the given generation expired
+    /// at the given tag. Returns false if the watchdog was started
+    /// or stopped since then, in which case the expiry must be ignored.
+    pub(crate) fn record_expiry(&self, generation: u64, tag: EventTag) -> bool {
+        let mut state = self.state.0.lock().unwrap();
+        if state.generation != generation {
+            return false;
+        }
+        state.expired_at = Some(tag);
+        true
+    }
+
+    /// Record an expiry that was replayed from a recording of
+    /// the program, see [SchedulerOptions::replay].
+    pub(crate) fn replay_expiry(&self, tag: EventTag) {
+        self.state.0.lock().unwrap().expired_at = Some(tag);
+    }
+}
+
+impl TriggerLike for Watchdog {
+    fn get_id(&self) -> TriggerId {
+        self.id
+    }
+}
+
+impl ReactionTrigger<()> for Watchdog {
+    fn is_present(&self, now: &EventTag, _start: &Instant) -> bool {
+        self.state.0.lock().unwrap().expired_at == Some(*now)
+    }
+
+    #[inline]
+    fn get_value(&self, now: &EventTag, start: &Instant) -> Option<()> {
+        if self.is_present(now, start) {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn use_value_ref<O>(&self, now: &EventTag, start: &Instant, action: impl FnOnce(Option<&()>) -> O) -> O {
+        if self.is_present(now, start) {
+            action(Some(&()))
+        } else {
+            action(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stopped_watchdog_does_not_expire() {
+        let clock = VirtualClock::new();
+        let was_terminated = AtomicBool::new(false);
+        let watchdog = Watchdog::new(TriggerId::FIRST_REGULAR, Duration::from_millis(5));
+        assert!(watchdog.start(clock.now() + watchdog.timeout));
+        // a thread is already waiting
+        assert!(!watchdog.start(clock.now() + watchdog.timeout));
+
+        clock.advance(Duration::from_millis(5));
+        let generation = watchdog.wait_for_expiry(&clock, &was_terminated).unwrap();
+
+        watchdog.stop();
+        assert_eq!(watchdog.wait_for_expiry(&clock, &was_terminated), None);
+        assert!(!watchdog.record_expiry(generation, EventTag::ORIGIN));
+        assert!(!watchdog.is_present(&EventTag::ORIGIN, &clock.now()));
+        // the waiting thread has exited
+        assert!(watchdog.start(clock.now() + watchdog.timeout));
+    }
+
+    #[test]
+    fn test_waiter_is_woken_up_by_virtual_clock() {
+        let clock = Arc::new(VirtualClock::new());
+        let was_terminated = Arc::new(AtomicBool::new(false));
+        let watchdog = Watchdog::new(TriggerId::FIRST_REGULAR, Duration::from_secs(3600));
+        assert!(watchdog.start(clock.now() + watchdog.timeout));
+
+        let waiter = {
+            let (clock, was_terminated, watchdog) = (clock.clone(), was_terminated.clone(), watchdog.clone());
+            std::thread::spawn(move || watchdog.wait_for_expiry(clock.as_ref(), &was_terminated))
+        };
+        // the deadline is an hour away in virtual time only
+        clock.advance(Duration::from_secs(3600));
+        assert!(waiter.join().unwrap().is_some());
+    }
+}